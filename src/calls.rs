@@ -0,0 +1,463 @@
+use crate::flow::Condition;
+use crate::layout::KeyboardLayout;
+use crate::script::Script;
+use crate::serializable_event::{SerializableEvent, SerializableEventType};
+use crate::variables::{substitute_known, Variables};
+use std::{
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+// Deepest a chain of Call steps may go before playback gives up, which is what
+// stops a script that calls itself through a file saved elsewhere
+pub const MAX_CALL_DEPTH: usize = 16;
+
+// Where a Call step's script is, relative paths are taken from the directory of
+// the calling script, or the working directory if it was never saved
+pub fn resolve_call(base: Option<&Path>, script: &Path) -> PathBuf {
+    match base.and_then(Path::parent) {
+        Some(directory) if script.is_relative() => directory.join(script),
+        _ => script.to_path_buf(),
+    }
+}
+
+// The other way round from resolve_call: how to get to `target` from the
+// directory of `base`, so the two files can be moved together. Stays absolute
+// when they have nothing in common, like being on different drives.
+pub fn relative_path(base: &Path, target: &Path) -> PathBuf {
+    let (Some(directory), Ok(target)) = (
+        base.parent()
+            .and_then(|directory| directory.canonicalize().ok()),
+        target.canonicalize(),
+    ) else {
+        return target.to_path_buf();
+    };
+
+    let mut directory = directory.components().peekable();
+    let mut rest = target.components().peekable();
+    let mut shared = false;
+    while let Some(component) = directory.next_if(|component| rest.peek() == Some(component)) {
+        shared |= matches!(component, Component::Normal(_));
+        rest.next();
+    }
+    if !shared {
+        return target;
+    }
+
+    let mut relative: PathBuf = directory.map(|_| Component::ParentDir).collect();
+    relative.extend(rest);
+    relative
+}
+
+// Paths are compared canonicalized so "a/../b.bin" and "b.bin" are the same file
+fn identity(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn calls(events: &[SerializableEvent]) -> impl Iterator<Item = &Path> {
+    events.iter().filter_map(|event| match &event.event_type {
+        SerializableEventType::Call { script, .. } => Some(script.as_path()),
+        _ => None,
+    })
+}
+
+// Chain of files that would call back into one another if `script` was saved
+// as `path`, ending with the file that repeats. Called files that can't be read
+// are skipped, playback reports them when it gets to them.
+pub fn find_cycle(script: &Script, path: &Path) -> Option<Vec<PathBuf>> {
+    let mut chain = vec![path.to_path_buf()];
+    let mut identities = vec![identity(path)];
+    let mut finished = Vec::new();
+    search(script, &mut chain, &mut identities, &mut finished)
+}
+
+fn search(
+    script: &Script,
+    chain: &mut Vec<PathBuf>,
+    identities: &mut Vec<PathBuf>,
+    finished: &mut Vec<PathBuf>,
+) -> Option<Vec<PathBuf>> {
+    let base = chain.last().cloned();
+    for called in calls(&script.events) {
+        let called = resolve_call(base.as_deref(), called);
+        let id = identity(&called);
+        if let Some(start) = identities.iter().position(|other| *other == id) {
+            let mut cycle = chain[start..].to_vec();
+            cycle.push(called);
+            return Some(cycle);
+        }
+        if finished.contains(&id) {
+            continue;
+        }
+
+        let Ok(callee) = Script::load(&called) else {
+            continue;
+        };
+        chain.push(called);
+        identities.push(id.clone());
+        if let Some(cycle) = search(&callee, chain, identities, finished) {
+            return Some(cycle);
+        }
+        chain.pop();
+        identities.pop();
+        finished.push(id);
+    }
+    None
+}
+
+// Copy of the script with every Call step replaced by the events of the script
+// it calls, so it can be shared as a single file.
+//
+// A called script's default variables, with the parameters over them, are
+// written into its TypeText steps, and variable conditions on them check a
+// renamed copy kept in the script's variables. Labels are renamed as well so
+// calling the same script twice doesn't repeat them, and an Exit in a called
+// script jumps past its events, since it only ends that script. Called events
+// are retimed to start where the Call step was, with keys converted to the
+// calling script's keyboard layout.
+pub fn flatten(script: &Script) -> Result<Script, String> {
+    let mut flat = script.clone();
+    let mut inliner = Inliner {
+        counter: 0,
+        variables: script.variables.clone(),
+        keyboard_layout: script.keyboard_layout,
+    };
+    flat.events = inliner.inline(
+        &script.events,
+        script.path.as_deref(),
+        &Variables::new(),
+        "",
+        None,
+        0,
+    )?;
+    flat.variables = inliner.variables;
+    Ok(flat)
+}
+
+struct Inliner {
+    // Number of scripts inlined so far, for unique prefixes
+    counter: usize,
+    variables: Variables,
+    keyboard_layout: KeyboardLayout,
+}
+
+impl Inliner {
+    // `exit_label` is where an Exit jumps to, None for the top level script
+    fn inline(
+        &mut self,
+        events: &[SerializableEvent],
+        path: Option<&Path>,
+        parameters: &Variables,
+        prefix: &str,
+        exit_label: Option<&str>,
+        depth: usize,
+    ) -> Result<Vec<SerializableEvent>, String> {
+        let mut output = Vec::with_capacity(events.len());
+        // How much later everything after an inlined script has to happen
+        let mut shift = Duration::ZERO;
+
+        for event in events {
+            let time = event.time + shift;
+            let SerializableEventType::Call {
+                script,
+                parameters: call_parameters,
+            } = &event.event_type
+            else {
+                let event_type = match (&event.event_type, exit_label) {
+                    (SerializableEventType::Exit { .. }, Some(label)) => {
+                        SerializableEventType::Jump {
+                            label: label.to_string(),
+                        }
+                    }
+                    (event_type, _) => self.bind(event_type, parameters, prefix),
+                };
+                output.push(SerializableEvent { time, event_type });
+                continue;
+            };
+
+            if depth >= MAX_CALL_DEPTH {
+                return Err(format!(
+                    "Calls are nested more than {} deep at {}",
+                    MAX_CALL_DEPTH,
+                    script.display()
+                ));
+            }
+            let called = resolve_call(path, script);
+            let mut callee =
+                Script::load(&called).map_err(|e| format!("{}: {}", called.display(), e))?;
+            if callee.keyboard_layout != self.keyboard_layout {
+                callee.translate_layout(self.keyboard_layout);
+            }
+
+            // Inside the called script its defaults win over the caller's
+            // variables and parameters win over both, as when playing it
+            let mut inner_parameters = callee.variables.clone();
+            inner_parameters.extend(
+                call_parameters
+                    .iter()
+                    .map(|(name, value)| (name.clone(), substitute_known(value, parameters))),
+            );
+            // Rhai steps look variables up while playing, so ones only the called
+            // script has are kept for them
+            for (name, value) in &callee.variables {
+                if !self.variables.contains_key(name) {
+                    self.variables.insert(name.clone(), value.clone());
+                }
+            }
+
+            self.counter += 1;
+            // Labels of the called script all get the "name#n:" prefix, so its end
+            // can't clash with them
+            let inner_name = format!(
+                "{}#{}",
+                called.file_stem().unwrap_or_default().to_string_lossy(),
+                self.counter
+            );
+            let inner_prefix = format!("{}:", inner_name);
+            let inlined = self.inline(
+                &callee.events,
+                Some(&called),
+                &inner_parameters,
+                &inner_prefix,
+                Some(&inner_name),
+                depth + 1,
+            )?;
+            let exits = inlined.iter().any(|inner_event| {
+                matches!(&inner_event.event_type, SerializableEventType::Jump { label } if *label == inner_name)
+            });
+
+            let start = inlined.first().map(|first| first.time);
+            let mut length = Duration::ZERO;
+            for inner_event in inlined {
+                let offset = start
+                    .and_then(|start| inner_event.time.duration_since(start).ok())
+                    .unwrap_or_default();
+                length = length.max(offset);
+                output.push(SerializableEvent {
+                    time: time + offset,
+                    event_type: inner_event.event_type,
+                });
+            }
+            if exits {
+                output.push(SerializableEvent {
+                    time: time + length,
+                    event_type: SerializableEventType::Label { name: inner_name },
+                });
+            }
+            shift += length;
+        }
+
+        Ok(output)
+    }
+
+    // Apply the parameters of the script being inlined to one of its steps
+    fn bind(
+        &mut self,
+        event_type: &SerializableEventType,
+        parameters: &Variables,
+        prefix: &str,
+    ) -> SerializableEventType {
+        match event_type {
+            SerializableEventType::TypeText {
+                text,
+                per_char_delay,
+            } => SerializableEventType::TypeText {
+                text: substitute_known(text, parameters),
+                per_char_delay: *per_char_delay,
+            },
+            SerializableEventType::If { condition } => SerializableEventType::If {
+                condition: self.bind_condition(condition, parameters, prefix),
+            },
+            SerializableEventType::Label { name } => SerializableEventType::Label {
+                name: format!("{}{}", prefix, name),
+            },
+            SerializableEventType::Jump { label } => SerializableEventType::Jump {
+                label: format!("{}{}", prefix, label),
+            },
+            event_type => event_type.clone(),
+        }
+    }
+
+    fn bind_condition(
+        &mut self,
+        condition: &Condition,
+        parameters: &Variables,
+        prefix: &str,
+    ) -> Condition {
+        match condition {
+            Condition::Variable { name, value } => match parameters.get(name) {
+                Some(parameter) => {
+                    let name = format!("{}{}", prefix, name);
+                    self.variables.insert(name.clone(), parameter.clone());
+                    Condition::Variable {
+                        name,
+                        value: value.clone(),
+                    }
+                }
+                None => condition.clone(),
+            },
+            Condition::Not(condition) => {
+                Condition::Not(Box::new(self.bind_condition(condition, parameters, prefix)))
+            }
+            condition => condition.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow;
+    use crate::serializable_event::SerializableKey;
+    use std::{fs, time::UNIX_EPOCH};
+
+    fn script(event_types: Vec<SerializableEventType>) -> Script {
+        Script::new(
+            event_types
+                .into_iter()
+                .enumerate()
+                .map(|(i, event_type)| SerializableEvent {
+                    time: UNIX_EPOCH + Duration::from_millis(i as u64 * 10),
+                    event_type,
+                })
+                .collect(),
+        )
+    }
+
+    // A fresh directory for the files of one test
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "simplyscriptor2-calls-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn call(script: &str) -> SerializableEventType {
+        SerializableEventType::Call {
+            script: PathBuf::from(script),
+            parameters: Variables::new(),
+        }
+    }
+
+    #[test]
+    fn finds_cycles_through_saved_files() {
+        let directory = directory("cycle");
+        script(vec![call("c.bin")])
+            .save(&directory.join("b.bin"))
+            .unwrap();
+        // c.bin calls a.bin, which isn't saved yet so it can't be checked
+        script(vec![call("a.bin")])
+            .save(&directory.join("c.bin"))
+            .unwrap();
+
+        let a = script(vec![call("b.bin")]);
+        let cycle = find_cycle(&a, &directory.join("a.bin")).unwrap();
+        let names: Vec<_> = cycle
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["a.bin", "b.bin", "c.bin", "a.bin"]);
+
+        // Saved under another name nothing comes back round
+        assert_eq!(find_cycle(&a, &directory.join("d.bin")), None);
+        // Calling itself directly
+        assert!(find_cycle(&script(vec![call("e.bin")]), &directory.join("e.bin")).is_some());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn skips_called_files_that_are_missing() {
+        let directory = directory("missing");
+        let a = script(vec![call("nowhere.bin"), call("b.bin")]);
+        assert_eq!(find_cycle(&a, &directory.join("a.bin")), None);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn flatten_ends_called_scripts_at_their_exit() {
+        let directory = directory("exit");
+        script(vec![
+            SerializableEventType::TypeText {
+                text: "{{greeting}}".to_string(),
+                per_char_delay: Duration::ZERO,
+            },
+            SerializableEventType::Exit { code: 0 },
+            SerializableEventType::KeyPress(SerializableKey::KeyA),
+        ])
+        .save(&directory.join("inner.bin"))
+        .unwrap();
+
+        let mut outer = script(vec![
+            SerializableEventType::Call {
+                script: PathBuf::from("inner.bin"),
+                parameters: Variables::new(),
+            },
+            SerializableEventType::KeyPress(SerializableKey::KeyB),
+        ]);
+        outer.path = Some(directory.join("outer.bin"));
+
+        let flat = flatten(&outer).unwrap();
+        let types: Vec<_> = flat.events.iter().map(|event| &event.event_type).collect();
+        assert!(matches!(
+            types[1],
+            SerializableEventType::Jump { label } if label == "inner#1"
+        ));
+        assert!(matches!(
+            types[3],
+            SerializableEventType::Label { name } if name == "inner#1"
+        ));
+        assert!(matches!(
+            types[4],
+            SerializableEventType::KeyPress(SerializableKey::KeyB)
+        ));
+        assert!(flow::parse(&flat.events).is_ok());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn flatten_prefers_called_defaults_and_converts_layout() {
+        let directory = directory("scope");
+        let mut inner = script(vec![
+            SerializableEventType::TypeText {
+                text: "{{name}} {{mode}}".to_string(),
+                per_char_delay: Duration::ZERO,
+            },
+            SerializableEventType::KeyPress(SerializableKey::KeyZ),
+            SerializableEventType::KeyRelease(SerializableKey::KeyZ),
+        ]);
+        inner.keyboard_layout = KeyboardLayout::De;
+        inner
+            .variables
+            .insert("name".to_string(), "inner".to_string());
+        inner
+            .variables
+            .insert("mode".to_string(), "fast".to_string());
+        inner.save(&directory.join("inner.bin")).unwrap();
+
+        let mut outer = script(vec![SerializableEventType::Call {
+            script: PathBuf::from("inner.bin"),
+            parameters: Variables::from([("mode".to_string(), "slow".to_string())]),
+        }]);
+        outer
+            .variables
+            .insert("name".to_string(), "outer".to_string());
+        outer.path = Some(directory.join("outer.bin"));
+
+        let flat = flatten(&outer).unwrap();
+        assert!(matches!(
+            &flat.events[0].event_type,
+            SerializableEventType::TypeText { text, .. } if text == "inner slow"
+        ));
+        // Z and Y swap places on a German keyboard
+        assert!(matches!(
+            flat.events[1].event_type,
+            SerializableEventType::KeyPress(SerializableKey::KeyY)
+        ));
+        assert_eq!(flat.variables["name"], "outer");
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
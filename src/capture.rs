@@ -0,0 +1,223 @@
+use crate::screen::ScreenGeometry;
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// Rectangle of the screen, in the same global coordinates as recorded mouse events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Source of screen contents for steps that look at the screen. Playback uses the
+// real display, anything else can hand in images from elsewhere.
+pub trait ScreenCapture {
+    fn capture(&mut self, region: Region) -> Result<RgbImage, String>;
+
+    // The whole area that can be captured
+    fn screen_region(&mut self) -> Result<Region, String>;
+
+    fn pixel(&mut self, x: i32, y: i32) -> Result<[u8; 3], String> {
+        let image = self.capture(Region {
+            x,
+            y,
+            width: 1,
+            height: 1,
+        })?;
+        Ok(image.get_pixel(0, 0).0)
+    }
+}
+
+// Fake screen backed by an image file. The file is read again on every capture,
+// so replacing it simulates the screen changing.
+pub struct ImageFileScreen {
+    path: PathBuf,
+}
+
+impl ImageFileScreen {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ImageFileScreen { path: path.into() }
+    }
+}
+
+impl ScreenCapture for ImageFileScreen {
+    fn capture(&mut self, region: Region) -> Result<RgbImage, String> {
+        let screen = image::open(&self.path)
+            .map_err(|e| format!("Could not open {}: {}", self.path.display(), e))?
+            .to_rgb8();
+        crop(&screen, region)
+    }
+
+    fn screen_region(&mut self) -> Result<Region, String> {
+        let (width, height) = image::image_dimensions(&self.path)
+            .map_err(|e| format!("Could not open {}: {}", self.path.display(), e))?;
+        Ok(Region {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        })
+    }
+}
+
+// Cut a region out of a full screen image, failing if it reaches past the edges
+pub fn crop(screen: &RgbImage, region: Region) -> Result<RgbImage, String> {
+    let fits = region.x >= 0
+        && region.y >= 0
+        && region.x as u64 + region.width as u64 <= screen.width() as u64
+        && region.y as u64 + region.height as u64 <= screen.height() as u64;
+    if !fits {
+        return Err(format!(
+            "Region {}x{} at ({}, {}) is outside the {}x{} screen",
+            region.width,
+            region.height,
+            region.x,
+            region.y,
+            screen.width(),
+            screen.height()
+        ));
+    }
+
+    Ok(image::imageops::crop_imm(
+        screen,
+        region.x as u32,
+        region.y as u32,
+        region.width,
+        region.height,
+    )
+    .to_image())
+}
+
+// The real display
+#[derive(Default)]
+pub struct SystemScreen {
+    #[cfg(target_os = "linux")]
+    connection: Option<(x11rb::rust_connection::RustConnection, u32)>,
+}
+
+impl SystemScreen {
+    pub fn new() -> Self {
+        SystemScreen::default()
+    }
+
+    fn bounds() -> Result<Region, String> {
+        let bounds = ScreenGeometry::current()
+            .ok_or("Could not detect the screen size")?
+            .bounds();
+        Ok(Region {
+            x: bounds.x as i32,
+            y: bounds.y as i32,
+            width: bounds.width as u32,
+            height: bounds.height as u32,
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ScreenCapture for SystemScreen {
+    fn capture(&mut self, region: Region) -> Result<RgbImage, String> {
+        use x11rb::connection::Connection;
+        use x11rb::protocol::xproto::{ConnectionExt as _, ImageFormat};
+
+        if self.connection.is_none() {
+            let (conn, screen_num) = x11rb::rust_connection::RustConnection::connect(None)
+                .map_err(|e| format!("Could not connect to X11: {}", e))?;
+            let root = conn.setup().roots[screen_num].root;
+            self.connection = Some((conn, root));
+        }
+        let (conn, root) = self.connection.as_ref().unwrap();
+
+        let reply = conn
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                *root,
+                region.x as i16,
+                region.y as i16,
+                region.width as u16,
+                region.height as u16,
+                !0,
+            )
+            .map_err(|e| format!("Could not capture screen: {}", e))?
+            .reply()
+            .map_err(|e| format!("Could not capture screen: {}", e))?;
+
+        // 24 and 32 bit visuals both come back as 4 bytes per pixel, BGRX
+        let expected = region.width as usize * region.height as usize * 4;
+        if reply.data.len() < expected {
+            return Err(format!("Unsupported screen format (depth {})", reply.depth));
+        }
+
+        let rgb = reply
+            .data
+            .chunks_exact(4)
+            .take(region.width as usize * region.height as usize)
+            .flat_map(|bgrx| [bgrx[2], bgrx[1], bgrx[0]])
+            .collect();
+        RgbImage::from_raw(region.width, region.height, rgb)
+            .ok_or_else(|| "Could not capture screen".to_string())
+    }
+
+    fn screen_region(&mut self) -> Result<Region, String> {
+        SystemScreen::bounds()
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl ScreenCapture for SystemScreen {
+    fn capture(&mut self, region: Region) -> Result<RgbImage, String> {
+        use core_graphics::display::{
+            kCGNullWindowID, kCGWindowImageNominalResolution, kCGWindowListOptionOnScreenOnly,
+            CGDisplay,
+        };
+        use core_graphics::geometry::{CGPoint, CGRect, CGSize};
+
+        let bounds = CGRect::new(
+            &CGPoint::new(region.x as f64, region.y as f64),
+            &CGSize::new(region.width as f64, region.height as f64),
+        );
+        let image = CGDisplay::screenshot(
+            bounds,
+            kCGWindowListOptionOnScreenOnly,
+            kCGNullWindowID,
+            kCGWindowImageNominalResolution,
+        )
+        .ok_or("Could not capture screen, is screen recording allowed?")?;
+
+        if image.bits_per_pixel() != 32 {
+            return Err(format!(
+                "Unsupported screen format ({} bits per pixel)",
+                image.bits_per_pixel()
+            ));
+        }
+
+        // Rows are BGRA and may be padded past the image width
+        let (width, height) = (image.width() as u32, image.height() as u32);
+        let bytes_per_row = image.bytes_per_row();
+        let data = image.data();
+        let bytes = data.bytes();
+        let mut rgb = RgbImage::new(width, height);
+        for (x, y, pixel) in rgb.enumerate_pixels_mut() {
+            let offset = y as usize * bytes_per_row + x as usize * 4;
+            *pixel = image::Rgb([bytes[offset + 2], bytes[offset + 1], bytes[offset]]);
+        }
+        Ok(rgb)
+    }
+
+    fn screen_region(&mut self) -> Result<Region, String> {
+        SystemScreen::bounds()
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+impl ScreenCapture for SystemScreen {
+    fn capture(&mut self, _region: Region) -> Result<RgbImage, String> {
+        Err("Screen capture is not supported on this platform".to_string())
+    }
+
+    fn screen_region(&mut self) -> Result<Region, String> {
+        SystemScreen::bounds()
+    }
+}
@@ -0,0 +1,394 @@
+use crate::Message;
+use iced::mouse::{self, Cursor, ScrollDelta};
+use iced::widget::canvas::{self, event, Frame, Geometry, Path, Program, Stroke, Text};
+use iced::{keyboard, Color, Point, Rectangle, Renderer, Size, Theme};
+use simplyscriptor2::mouse_path::MousePath;
+use simplyscriptor2::screen::{Monitor, ScreenGeometry};
+use simplyscriptor2::serializable_event::SerializableButton;
+use simplyscriptor2::timeline::{Lane, Timeline};
+use std::time::Duration;
+
+// Room on the left for the lane names and at the bottom for the time axis,
+// dragging in the axis scrubs instead of selecting
+const LABEL_WIDTH: f32 = 52.0;
+const AXIS_HEIGHT: f32 = 18.0;
+// Narrowest the view can be zoomed in to
+const MIN_VIEW: Duration = Duration::from_millis(10);
+// Width of each bar of the mouse move lane
+const DENSITY_BAR: f32 = 3.0;
+
+const LANE_COLOR: Color = Color::from_rgb(0.2, 0.15, 0.08);
+const SPAN_COLOR: Color = Color::from_rgb(0.95, 0.75, 0.4);
+const SELECTION_COLOR: Color = Color::from_rgba(0.6, 0.8, 1.0, 0.25);
+const CURSOR_COLOR: Color = Color::from_rgb(1.0, 0.45, 0.3);
+const TEXT_COLOR: Color = Color::from_rgb(0.85, 0.85, 0.85);
+// Clicks on the mouse path, by button
+pub const LEFT_COLOR: Color = Color::from_rgb(0.4, 0.9, 0.4);
+pub const RIGHT_COLOR: Color = Color::from_rgb(0.5, 0.7, 1.0);
+pub const OTHER_COLOR: Color = Color::from_rgb(0.9, 0.5, 0.9);
+
+// The script's events in lanes over time, drawn from `start` to `end`
+pub struct TimelineChart {
+    pub timeline: Timeline,
+    pub start: Duration,
+    pub end: Duration,
+    pub selection: Option<(Duration, Duration)>,
+    pub cursor: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+enum Drag {
+    #[default]
+    None,
+    Select(Duration),
+    Scrub,
+}
+
+#[derive(Debug, Default)]
+pub struct ChartState {
+    drag: Drag,
+    modifiers: keyboard::Modifiers,
+}
+
+impl TimelineChart {
+    fn plot_width(&self, bounds: Rectangle) -> f32 {
+        (bounds.width - LABEL_WIDTH).max(1.0)
+    }
+
+    fn x_of(&self, time: Duration, bounds: Rectangle) -> f32 {
+        let view = (self.end - self.start).as_secs_f64().max(f64::EPSILON);
+        let along = (time.as_secs_f64() - self.start.as_secs_f64()) / view;
+        LABEL_WIDTH + along as f32 * self.plot_width(bounds)
+    }
+
+    // Time under `x` from the left of the canvas, kept inside the script
+    fn time_at(&self, x: f32, bounds: Rectangle) -> Duration {
+        let along = ((x - LABEL_WIDTH) / self.plot_width(bounds)).clamp(0.0, 1.0) as f64;
+        let time = self.start.as_secs_f64() + along * (self.end - self.start).as_secs_f64();
+        Duration::from_secs_f64(time).min(self.timeline.length)
+    }
+
+    // Keep a zoomed or panned view inside the script
+    fn view(&self, start: f64, end: f64) -> Message {
+        let length = self.timeline.length.as_secs_f64();
+        let width = (end - start).clamp(MIN_VIEW.as_secs_f64(), length.max(f64::EPSILON));
+        let start = start.clamp(0.0, (length - width).max(0.0));
+        Message::TimelineView(
+            Duration::from_secs_f64(start),
+            Duration::from_secs_f64(start + width),
+        )
+    }
+}
+
+impl Program<Message> for TimelineChart {
+    type State = ChartState;
+
+    fn update(
+        &self,
+        state: &mut ChartState,
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> (event::Status, Option<Message>) {
+        // Drags carry on outside the canvas until the button is let go
+        let x = cursor.position().map(|position| position.x - bounds.x);
+        match event {
+            canvas::Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
+                state.modifiers = modifiers;
+                (event::Status::Ignored, None)
+            }
+            canvas::Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let Some(position) = cursor.position_in(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+                let time = self.time_at(position.x, bounds);
+                let message = if position.y > bounds.height - AXIS_HEIGHT {
+                    state.drag = Drag::Scrub;
+                    Message::TimelineScrub(time)
+                } else {
+                    state.drag = Drag::Select(time);
+                    Message::TimelineSelect(time, time)
+                };
+                (event::Status::Captured, Some(message))
+            }
+            canvas::Event::Mouse(mouse::Event::CursorMoved { .. }) => {
+                let (Some(x), drag) = (x, state.drag) else {
+                    return (event::Status::Ignored, None);
+                };
+                let time = self.time_at(x, bounds);
+                let message = match drag {
+                    Drag::None => return (event::Status::Ignored, None),
+                    Drag::Select(from) => Message::TimelineSelect(from.min(time), from.max(time)),
+                    Drag::Scrub => Message::TimelineScrub(time),
+                };
+                (event::Status::Captured, Some(message))
+            }
+            canvas::Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                match std::mem::take(&mut state.drag) {
+                    Drag::None => (event::Status::Ignored, None),
+                    _ => (event::Status::Captured, None),
+                }
+            }
+            canvas::Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let Some(position) = cursor.position_in(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+                let (dx, dy) = match delta {
+                    ScrollDelta::Lines { x, y } => (x, y),
+                    ScrollDelta::Pixels { x, y } => (x / 40.0, y / 40.0),
+                };
+                let start = self.start.as_secs_f64();
+                let end = self.end.as_secs_f64();
+                let width = end - start;
+
+                // Sideways or with Shift pans, otherwise zooms around the cursor
+                let pan = if state.modifiers.shift() { dy } else { dx };
+                let message = if pan != 0.0 {
+                    let by = -pan as f64 * width / 10.0;
+                    self.view(start + by, end + by)
+                } else if dy != 0.0 {
+                    let scale = 0.8f64.powf(dy as f64);
+                    let anchor = self.time_at(position.x, bounds).as_secs_f64();
+                    self.view(
+                        anchor - (anchor - start) * scale,
+                        anchor + (end - anchor) * scale,
+                    )
+                } else {
+                    return (event::Status::Ignored, None);
+                };
+                (event::Status::Captured, Some(message))
+            }
+            _ => (event::Status::Ignored, None),
+        }
+    }
+
+    fn draw(
+        &self,
+        _state: &ChartState,
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let lanes_height = bounds.height - AXIS_HEIGHT;
+        let lane_height = lanes_height / Lane::ALL.len() as f32;
+        let plot = Rectangle {
+            x: LABEL_WIDTH,
+            y: 0.0,
+            width: self.plot_width(bounds),
+            height: lanes_height,
+        };
+        let clip = |x: f32| x.clamp(plot.x, plot.x + plot.width);
+
+        for (i, lane) in Lane::ALL.iter().enumerate() {
+            let top = i as f32 * lane_height;
+            frame.fill_rectangle(
+                Point::new(plot.x, top + 1.0),
+                Size::new(plot.width, lane_height - 2.0),
+                LANE_COLOR,
+            );
+            frame.fill_text(Text {
+                content: lane.to_string(),
+                position: Point::new(2.0, top + lane_height / 2.0 - 6.0),
+                color: TEXT_COLOR,
+                size: 12.0.into(),
+                ..Text::default()
+            });
+
+            if *lane == Lane::Movement {
+                // How busy the mouse is, as bars as tall as the most moves in one
+                let buckets = (plot.width / DENSITY_BAR) as usize;
+                let counts = self.timeline.density(self.start, self.end, buckets);
+                let most = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+                for (bucket, count) in counts.into_iter().enumerate() {
+                    if count == 0 {
+                        continue;
+                    }
+                    let height = (lane_height - 4.0) * count as f32 / most;
+                    frame.fill_rectangle(
+                        Point::new(
+                            plot.x + bucket as f32 * DENSITY_BAR,
+                            top + lane_height - 2.0 - height,
+                        ),
+                        Size::new(DENSITY_BAR - 1.0, height),
+                        SPAN_COLOR,
+                    );
+                }
+                continue;
+            }
+
+            for span in self.timeline.spans.iter().filter(|span| span.lane == *lane) {
+                if span.end < self.start || span.start > self.end {
+                    continue;
+                }
+                let left = clip(self.x_of(span.start, bounds));
+                let right = clip(self.x_of(span.end, bounds));
+                frame.fill_rectangle(
+                    Point::new(left, top + 4.0),
+                    Size::new((right - left).max(2.0), lane_height - 8.0),
+                    SPAN_COLOR,
+                );
+            }
+        }
+
+        if let Some((from, to)) = self.selection {
+            let left = clip(self.x_of(from, bounds));
+            let right = clip(self.x_of(to, bounds));
+            frame.fill_rectangle(
+                Point::new(left, 0.0),
+                Size::new((right - left).max(1.0), lanes_height),
+                SELECTION_COLOR,
+            );
+        }
+
+        // Ticks at a round step, about 80 pixels apart
+        let view = (self.end - self.start).as_secs_f64();
+        let rough = view * 80.0 / plot.width as f64;
+        let step = (-3..7)
+            .flat_map(|power| [1.0, 2.0, 5.0].map(|base| base * 10f64.powi(power)))
+            .find(|step| *step >= rough);
+        if let Some(step) = step {
+            let mut tick = (self.start.as_secs_f64() / step).ceil() * step;
+            while tick <= self.end.as_secs_f64() {
+                let x = self.x_of(Duration::from_secs_f64(tick), bounds);
+                frame.stroke(
+                    &Path::line(
+                        Point::new(x, lanes_height),
+                        Point::new(x, lanes_height + 4.0),
+                    ),
+                    Stroke::default().with_color(TEXT_COLOR),
+                );
+                frame.fill_text(Text {
+                    content: format_seconds(tick, step),
+                    position: Point::new(x + 2.0, lanes_height + 3.0),
+                    color: TEXT_COLOR,
+                    size: 11.0.into(),
+                    ..Text::default()
+                });
+                tick += step;
+            }
+        }
+
+        if let Some(cursor) = self.cursor.filter(|c| (self.start..=self.end).contains(c)) {
+            let x = self.x_of(cursor, bounds);
+            frame.stroke(
+                &Path::line(Point::new(x, 0.0), Point::new(x, bounds.height)),
+                Stroke::default().with_color(CURSOR_COLOR).with_width(2.0),
+            );
+        }
+
+        vec![frame.into_geometry()]
+    }
+
+    fn mouse_interaction(
+        &self,
+        _state: &ChartState,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> mouse::Interaction {
+        match cursor.position_in(bounds) {
+            Some(position) if position.y > bounds.height - AXIS_HEIGHT => {
+                mouse::Interaction::ResizingHorizontally
+            }
+            Some(_) => mouse::Interaction::Crosshair,
+            None => mouse::Interaction::default(),
+        }
+    }
+}
+
+// Seconds with as many decimals as the tick step needs
+fn format_seconds(seconds: f64, step: f64) -> String {
+    let decimals = if step >= 1.0 {
+        0
+    } else {
+        (-step.log10()).ceil() as usize
+    };
+    format!("{:.*}s", decimals, seconds)
+}
+
+// The mouse path of a script drawn over the monitors it was recorded on, scaled
+// down to fit
+pub struct PathChart {
+    pub path: MousePath,
+    pub monitors: Vec<Monitor>,
+    pub playhead: Option<Duration>,
+}
+
+impl Program<Message> for PathChart {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+
+        // Everything the path goes through fits, even off the recorded screen
+        let mut monitors = self.monitors.clone();
+        monitors.extend(self.path.bounds());
+        if monitors.is_empty() {
+            return vec![frame.into_geometry()];
+        }
+        let area = ScreenGeometry { monitors }.bounds();
+        let scale = (bounds.width as f64 / area.width.max(1.0))
+            .min(bounds.height as f64 / area.height.max(1.0));
+        let left = (bounds.width as f64 - area.width * scale) / 2.0;
+        let top = (bounds.height as f64 - area.height * scale) / 2.0;
+        let point = |x: f64, y: f64| {
+            Point::new(
+                (left + (x - area.x) * scale) as f32,
+                (top + (y - area.y) * scale) as f32,
+            )
+        };
+
+        for monitor in &self.monitors {
+            let corner = point(monitor.x, monitor.y);
+            let size = Size::new(
+                (monitor.width * scale) as f32,
+                (monitor.height * scale) as f32,
+            );
+            frame.fill_rectangle(corner, size, LANE_COLOR);
+            frame.stroke(
+                &Path::rectangle(corner, size),
+                Stroke::default().with_color(TEXT_COLOR),
+            );
+        }
+
+        let line = Path::new(|builder| {
+            let mut moves = self.path.moves.iter();
+            if let Some((_, x, y)) = moves.next() {
+                builder.move_to(point(*x, *y));
+            }
+            for (_, x, y) in moves {
+                builder.line_to(point(*x, *y));
+            }
+        });
+        frame.stroke(&line, Stroke::default().with_color(SPAN_COLOR));
+
+        for click in &self.path.clicks {
+            let color = match click.button {
+                SerializableButton::Left => LEFT_COLOR,
+                SerializableButton::Right => RIGHT_COLOR,
+                _ => OTHER_COLOR,
+            };
+            frame.fill(&Path::circle(point(click.x, click.y), 3.5), color);
+        }
+
+        if let Some((x, y)) = self
+            .playhead
+            .and_then(|playhead| self.path.position_at(playhead))
+        {
+            frame.stroke(
+                &Path::circle(point(x, y), 6.0),
+                Stroke::default().with_color(CURSOR_COLOR).with_width(2.0),
+            );
+        }
+
+        vec![frame.into_geometry()]
+    }
+}
@@ -0,0 +1,302 @@
+use simplyscriptor2::calls::flatten;
+use simplyscriptor2::generate::{
+    parse_button, AutoClicker, CellAction, ClickPosition, GridPattern, StopAfter,
+};
+use simplyscriptor2::layout::KeyboardLayout;
+use simplyscriptor2::log;
+use simplyscriptor2::schedule::{Scheduler, Schedules};
+use simplyscriptor2::script::Script;
+use simplyscriptor2::serializable_event::SerializableEventType;
+use std::{
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+    time::Duration,
+};
+
+const USAGE: &str = "Usage:
+    simplyscriptor2                       Open the GUI
+    simplyscriptor2 translate-layout <input> <output> --to <layout> [--from <layout>]
+        Convert a script so it types the same text on another keyboard layout.
+        Layouts are us, uk, de and fr. --from defaults to the layout saved in the script.
+    simplyscriptor2 daemon [--layout <layout>]
+        Play the schedules in the config directory without the GUI, until stopped.
+        --layout is this machine's keyboard layout, the one picked in the window when not given.
+    simplyscriptor2 flatten <input> <output>
+        Replace every call step with the script it calls, for sharing as one file.
+    simplyscriptor2 autoclick <output> [options]
+        Save a script that clicks over and over. Options:
+        --at <x>,<y>          Click there instead of wherever the cursor is
+        --button <button>     left, right or middle (left)
+        --click <click>       single, double or hold (single)
+        --hold <ms>           How long hold keeps the button down (500)
+        --every <ms>          Time from one click to the next (100)
+        --jitter <ms>         Most a click may come early or late (0)
+        --spread <px>         Most a click may land from the position (0)
+        --clicks <n>          Stop after this many clicks (10)
+        --for <seconds>       Stop after this long instead
+        --forever             Click until the run is stopped instead
+    simplyscriptor2 grid <output> --rows <n> --columns <n> --spacing <px>[,<px>] [options]
+        Save a script that moves to every cell of a grid and clicks it. Options:
+        --at <x>,<y>          Centre of the first cell instead of wherever the cursor is
+        --order <order>       rows, columns, snake-rows or snake-columns (rows)
+        --button <button>     left, right or middle (left)
+        --click <click>       single, double, hold or move to only hover (single)
+        --hold <ms>           How long hold keeps the button down (500)
+        --every <ms>          Time from one cell to the next (200)";
+
+// Run a command given on the command line, returning the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "translate-layout" => translate_layout(&args[1..]),
+        "flatten" => flatten_script(&args[1..]),
+        "daemon" => daemon(&args[1..]),
+        "autoclick" => autoclick(&args[1..]),
+        "grid" => grid(&args[1..]),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        command => Err(format!("Unknown command \"{}\"\n\n{}", command, USAGE)),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn translate_layout(args: &[String]) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut from = None;
+    let mut to = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => from = Some(option_value(&mut args, "--from")?.parse()?),
+            "--to" => to = Some(option_value(&mut args, "--to")?.parse()?),
+            _ => paths.push(arg),
+        }
+    }
+
+    let [input, output] = paths[..] else {
+        return Err(format!("Expected an input and an output file\n\n{}", USAGE));
+    };
+    let to: KeyboardLayout = to.ok_or(format!("Missing --to <layout>\n\n{}", USAGE))?;
+
+    let mut script = Script::load(Path::new(input))?;
+    if let Some(from) = from {
+        script.keyboard_layout = from;
+    }
+
+    let from = script.keyboard_layout;
+    let report = script.translate_layout(to);
+    script.save(Path::new(output))?;
+
+    println!("{} -> {}: {}", from, to, report);
+    Ok(())
+}
+
+fn flatten_script(args: &[String]) -> Result<(), String> {
+    let [input, output] = args else {
+        return Err(format!("Expected an input and an output file\n\n{}", USAGE));
+    };
+
+    let script = Script::load(Path::new(input))?;
+    let calls = script
+        .events
+        .iter()
+        .filter(|event| matches!(event.event_type, SerializableEventType::Call { .. }))
+        .count();
+    let mut flat = flatten(&script)?;
+    flat.save(Path::new(output))?;
+
+    println!("Inlined {} calls, {} events", calls, flat.events.len());
+    Ok(())
+}
+
+fn daemon(args: &[String]) -> Result<(), String> {
+    let settings = crate::load_settings();
+    let mut keyboard_layout = settings.keyboard_layout;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--layout" => keyboard_layout = option_value(&mut args, "--layout")?.parse()?,
+            other => return Err(format!("Unknown option \"{}\"\n\n{}", other, USAGE)),
+        }
+    }
+
+    let scheduler = Scheduler::load()?;
+    log(&format!(
+        "Running {} schedules from {}",
+        scheduler.schedules.schedules.len(),
+        Schedules::path()?.display()
+    ));
+    crate::schedule_loop(
+        Arc::new(Mutex::new(scheduler)),
+        || Some(0),
+        Arc::new(AtomicBool::new(true)),
+        Arc::new(Mutex::new(keyboard_layout)),
+        settings.keys.stop,
+        Arc::new(Mutex::new(None)),
+    );
+    Ok(())
+}
+
+fn autoclick(args: &[String]) -> Result<(), String> {
+    let mut output = None;
+    let mut clicker = AutoClicker::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--at" => {
+                let (x, y) = parse_pair(option_value(&mut args, "--at")?, "--at")?;
+                clicker.position = ClickPosition::At { x, y };
+            }
+            "--button" => clicker.button = parse_button(option_value(&mut args, "--button")?)?,
+            "--click" => clicker.kind = option_value(&mut args, "--click")?.parse()?,
+            "--hold" => clicker.hold = parse_millis(option_value(&mut args, "--hold")?, "--hold")?,
+            "--every" => {
+                clicker.interval = parse_millis(option_value(&mut args, "--every")?, "--every")?
+            }
+            "--jitter" => {
+                clicker.jitter_time =
+                    parse_millis(option_value(&mut args, "--jitter")?, "--jitter")?
+            }
+            "--spread" => {
+                clicker.jitter_distance =
+                    parse_number(option_value(&mut args, "--spread")?, "--spread")?
+            }
+            "--clicks" => {
+                let clicks = parse_count(option_value(&mut args, "--clicks")?, "--clicks")?;
+                clicker.stop = StopAfter::Clicks(clicks);
+            }
+            "--for" => {
+                let seconds = parse_number(option_value(&mut args, "--for")?, "--for")?;
+                let time = Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| format!("--for needs a number of seconds, not {}", seconds))?;
+                clicker.stop = StopAfter::Time(time);
+            }
+            "--forever" => clicker.stop = StopAfter::Stopped,
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option \"{}\"\n\n{}", other, USAGE))
+            }
+            _ if output.is_none() => output = Some(arg),
+            _ => return Err(format!("Expected one output file\n\n{}", USAGE)),
+        }
+    }
+
+    let output = output.ok_or(format!("Expected an output file\n\n{}", USAGE))?;
+    let mut script = clicker.script()?;
+    script.save(Path::new(output))?;
+
+    println!("Saved {} events to {}", script.events.len(), output);
+    Ok(())
+}
+
+fn grid(args: &[String]) -> Result<(), String> {
+    let mut output = None;
+    let mut grid = GridPattern::default();
+    let (mut rows, mut columns, mut spacing) = (None, None, None);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rows" => rows = Some(parse_count(option_value(&mut args, "--rows")?, "--rows")?),
+            "--columns" => {
+                columns = Some(parse_count(
+                    option_value(&mut args, "--columns")?,
+                    "--columns",
+                )?)
+            }
+            "--spacing" => {
+                let value = option_value(&mut args, "--spacing")?;
+                spacing = Some(if value.contains(',') {
+                    parse_pair(value, "--spacing")?
+                } else {
+                    let spacing = parse_number(value, "--spacing")?;
+                    (spacing, spacing)
+                });
+            }
+            "--at" => {
+                let (x, y) = parse_pair(option_value(&mut args, "--at")?, "--at")?;
+                grid.origin = ClickPosition::At { x, y };
+            }
+            "--order" => grid.order = option_value(&mut args, "--order")?.parse()?,
+            "--button" => grid.button = parse_button(option_value(&mut args, "--button")?)?,
+            "--click" => {
+                grid.action = match option_value(&mut args, "--click")?.as_str() {
+                    "move" => CellAction::Move,
+                    kind => CellAction::Click(kind.parse()?),
+                }
+            }
+            "--hold" => grid.hold = parse_millis(option_value(&mut args, "--hold")?, "--hold")?,
+            "--every" => {
+                grid.interval = parse_millis(option_value(&mut args, "--every")?, "--every")?
+            }
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option \"{}\"\n\n{}", other, USAGE))
+            }
+            _ if output.is_none() => output = Some(arg),
+            _ => return Err(format!("Expected one output file\n\n{}", USAGE)),
+        }
+    }
+
+    let output = output.ok_or(format!("Expected an output file\n\n{}", USAGE))?;
+    grid.rows = rows.ok_or(format!("Missing --rows <n>\n\n{}", USAGE))?;
+    grid.columns = columns.ok_or(format!("Missing --columns <n>\n\n{}", USAGE))?;
+    (grid.spacing_x, grid.spacing_y) =
+        spacing.ok_or(format!("Missing --spacing <px>\n\n{}", USAGE))?;
+
+    let mut script = grid.script()?;
+    script.save(Path::new(output))?;
+
+    println!(
+        "Saved {} cells, {} events to {}",
+        grid.rows * grid.columns,
+        script.events.len(),
+        output
+    );
+    Ok(())
+}
+
+fn parse_count(value: &str, name: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} needs a whole number, not \"{}\"", name, value))
+}
+
+// Two numbers written as <x>,<y>
+fn parse_pair(value: &str, name: &str) -> Result<(f64, f64), String> {
+    let (x, y) = value
+        .split_once(',')
+        .ok_or_else(|| format!("{} needs <x>,<y>, not \"{}\"", name, value))?;
+    Ok((parse_number(x, name)?, parse_number(y, name)?))
+}
+
+fn parse_number(value: &str, name: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{} needs a number, not \"{}\"", name, value))
+}
+
+fn parse_millis(value: &str, name: &str) -> Result<Duration, String> {
+    value.parse().map(Duration::from_millis).map_err(|_| {
+        format!(
+            "{} needs a whole number of milliseconds, not \"{}\"",
+            name, value
+        )
+    })
+}
+
+fn option_value<'a>(
+    args: &mut impl Iterator<Item = &'a String>,
+    name: &str,
+) -> Result<&'a String, String> {
+    args.next().ok_or_else(|| format!("{} needs a value", name))
+}
@@ -0,0 +1,56 @@
+use serde::de::DeserializeOwned;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Directory the per-user settings are kept in, $XDG_CONFIG_HOME/simplyscriptor2
+// on Linux and the usual config locations elsewhere. Created when missing.
+pub fn config_dir() -> Result<PathBuf, String> {
+    let dir = dirs::config_dir()
+        .ok_or("Could not find the user config directory")?
+        .join("simplyscriptor2");
+    fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+// Reads a toml config file, no file yet is the same as the defaults
+pub fn load_config<T: DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read file: {}", e))?;
+    toml::from_str(&text).map_err(|e| format!("Could not parse {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn loads_missing_files_as_defaults() {
+        let path = std::env::temp_dir().join(format!(
+            "simplyscriptor2-config-missing-{}",
+            std::process::id()
+        ));
+        let loaded: BTreeMap<String, i64> = load_config(&path).unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn loads_and_rejects_files() {
+        let path = std::env::temp_dir().join(format!(
+            "simplyscriptor2-config-file-{}",
+            std::process::id()
+        ));
+        fs::write(&path, "a = 1\n").unwrap();
+        let loaded: BTreeMap<String, i64> = load_config(&path).unwrap();
+        assert_eq!(loaded.get("a"), Some(&1));
+
+        fs::write(&path, "a = \"one\"\n").unwrap();
+        let error = load_config::<BTreeMap<String, i64>>(&path).unwrap_err();
+        assert!(error.starts_with("Could not parse"));
+        fs::remove_file(&path).unwrap();
+    }
+}
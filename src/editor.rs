@@ -0,0 +1,1212 @@
+use crate::capture::Region;
+use crate::flow::Condition;
+use crate::generate::parse_button;
+use crate::hotkey::parse_key;
+use crate::serializable_event::{
+    MatchAction, SerializableButton, SerializableEvent, SerializableEventType, SerializableKey,
+};
+use crate::timeline;
+use crate::variables::Variables;
+use crate::wait::{hex_color, parse_color};
+use crate::window::{TimeoutPolicy, WindowCondition, WindowMatcher};
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+// Edits that can be undone, older ones are dropped
+const UNDO_LIMIT: usize = 50;
+
+// How long inserted wait steps wait before giving up
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What a Repeat without a count shows, and can be typed to go back to one
+const UNTIL_STOPPED: &str = "until stopped";
+
+// Columns of the event table that can be edited in place
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventField {
+    // Milliseconds from the first event
+    Offset,
+    // Key, button or text
+    Detail,
+    X,
+    Y,
+}
+
+// Kinds of event the table can insert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NewEvent {
+    KeyPress,
+    KeyRelease,
+    ButtonPress,
+    ButtonRelease,
+    #[default]
+    MouseMove,
+    Wheel,
+    TypeText,
+    WaitForPixel,
+    WaitForImage,
+    WaitForWindow,
+    Repeat,
+    If,
+    Else,
+    End,
+    Label,
+    Jump,
+    Exit,
+    Call,
+}
+
+impl NewEvent {
+    pub const ALL: [NewEvent; 18] = [
+        NewEvent::KeyPress,
+        NewEvent::KeyRelease,
+        NewEvent::ButtonPress,
+        NewEvent::ButtonRelease,
+        NewEvent::MouseMove,
+        NewEvent::Wheel,
+        NewEvent::TypeText,
+        NewEvent::WaitForPixel,
+        NewEvent::WaitForImage,
+        NewEvent::WaitForWindow,
+        NewEvent::Repeat,
+        NewEvent::If,
+        NewEvent::Else,
+        NewEvent::End,
+        NewEvent::Label,
+        NewEvent::Jump,
+        NewEvent::Exit,
+        NewEvent::Call,
+    ];
+
+    fn event_type(self) -> SerializableEventType {
+        match self {
+            NewEvent::KeyPress => SerializableEventType::KeyPress(SerializableKey::Space),
+            NewEvent::KeyRelease => SerializableEventType::KeyRelease(SerializableKey::Space),
+            NewEvent::ButtonPress => SerializableEventType::ButtonPress(SerializableButton::Left),
+            NewEvent::ButtonRelease => {
+                SerializableEventType::ButtonRelease(SerializableButton::Left)
+            }
+            NewEvent::MouseMove => SerializableEventType::MouseMove { x: 0.0, y: 0.0 },
+            NewEvent::Wheel => SerializableEventType::Wheel {
+                delta_x: 0,
+                delta_y: -1,
+            },
+            NewEvent::TypeText => SerializableEventType::TypeText {
+                text: String::new(),
+                per_char_delay: Duration::from_millis(10),
+            },
+            NewEvent::WaitForPixel => SerializableEventType::WaitForPixel {
+                x: 0,
+                y: 0,
+                color: [0, 0, 0],
+                tolerance: 10,
+                timeout: STEP_TIMEOUT,
+            },
+            // The template is picked once the step is in the table
+            NewEvent::WaitForImage => SerializableEventType::WaitForImage {
+                template: Vec::new(),
+                region: None,
+                confidence: 0.9,
+                timeout: STEP_TIMEOUT,
+                action: MatchAction::Wait,
+            },
+            NewEvent::WaitForWindow => SerializableEventType::WaitForWindow {
+                matcher: WindowMatcher::default(),
+                condition: WindowCondition::Exists,
+                timeout: STEP_TIMEOUT,
+                on_timeout: TimeoutPolicy::Stop,
+            },
+            NewEvent::Repeat => SerializableEventType::Repeat { count: Some(2) },
+            NewEvent::If => SerializableEventType::If {
+                condition: Condition::Variable {
+                    name: "name".to_string(),
+                    value: "value".to_string(),
+                },
+            },
+            NewEvent::Else => SerializableEventType::Else,
+            NewEvent::End => SerializableEventType::End,
+            NewEvent::Label => SerializableEventType::Label {
+                name: "start".to_string(),
+            },
+            NewEvent::Jump => SerializableEventType::Jump {
+                label: "start".to_string(),
+            },
+            NewEvent::Exit => SerializableEventType::Exit { code: 0 },
+            // The script is picked once the step is in the table
+            NewEvent::Call => SerializableEventType::Call {
+                script: PathBuf::new(),
+                parameters: Variables::new(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for NewEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            NewEvent::KeyPress => "Key Press",
+            NewEvent::KeyRelease => "Key Release",
+            NewEvent::ButtonPress => "Button Press",
+            NewEvent::ButtonRelease => "Button Release",
+            NewEvent::MouseMove => "Move",
+            NewEvent::Wheel => "Wheel",
+            NewEvent::TypeText => "Type Text",
+            NewEvent::WaitForPixel => "Wait Pixel",
+            NewEvent::WaitForImage => "Wait Image",
+            NewEvent::WaitForWindow => "Wait Window",
+            NewEvent::Repeat => "Repeat",
+            NewEvent::If => "If",
+            NewEvent::Else => "Else",
+            NewEvent::End => "End",
+            NewEvent::Label => "Label",
+            NewEvent::Jump => "Jump",
+            NewEvent::Exit => "Exit",
+            NewEvent::Call => "Call",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// One line of the event table. Fields that can't be edited for this kind of
+// event are None.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventRow {
+    pub offset: Duration,
+    pub kind: &'static str,
+    pub detail: String,
+    pub detail_editable: bool,
+    pub x: Option<String>,
+    pub y: Option<String>,
+}
+
+impl EventRow {
+    pub fn new(event: &SerializableEvent, first: SystemTime) -> Self {
+        let offset = event.time.duration_since(first).unwrap_or_default();
+        let mut row = EventRow {
+            offset,
+            kind: "",
+            detail: String::new(),
+            detail_editable: false,
+            x: None,
+            y: None,
+        };
+        let (kind, detail) = match &event.event_type {
+            SerializableEventType::KeyPress(key) => ("Key Press", Some(format!("{:?}", key))),
+            SerializableEventType::KeyRelease(key) => ("Key Release", Some(format!("{:?}", key))),
+            SerializableEventType::ButtonPress(button) => {
+                ("Button Press", Some(button.to_string()))
+            }
+            SerializableEventType::ButtonRelease(button) => {
+                ("Button Release", Some(button.to_string()))
+            }
+            SerializableEventType::MouseMove { x, y } => {
+                row.x = Some(x.to_string());
+                row.y = Some(y.to_string());
+                ("Move", None)
+            }
+            SerializableEventType::Wheel { delta_x, delta_y } => {
+                row.x = Some(delta_x.to_string());
+                row.y = Some(delta_y.to_string());
+                ("Wheel", None)
+            }
+            SerializableEventType::TypeText { text, .. } => ("Type Text", Some(text.clone())),
+            SerializableEventType::WaitForPixel {
+                x,
+                y,
+                color,
+                tolerance,
+                timeout,
+            } => {
+                row.x = Some(x.to_string());
+                row.y = Some(y.to_string());
+                let detail = format!("{} ±{} {}", hex_color(*color), tolerance, seconds(*timeout));
+                ("Wait Pixel", Some(detail))
+            }
+            SerializableEventType::WaitForImage {
+                region,
+                confidence,
+                timeout,
+                action,
+                ..
+            } => {
+                let action = match action {
+                    MatchAction::Wait => "wait".to_string(),
+                    MatchAction::Move => "move".to_string(),
+                    MatchAction::Click(button) => format!("click {}", button),
+                };
+                let region = region.map_or("anywhere".to_string(), |region| {
+                    format!(
+                        "in {},{} {}x{}",
+                        region.x, region.y, region.width, region.height
+                    )
+                });
+                let detail = format!(
+                    "{}% {} {} {}",
+                    (confidence * 100.0).round(),
+                    seconds(*timeout),
+                    action,
+                    region
+                );
+                ("Wait Image", Some(detail))
+            }
+            SerializableEventType::WaitForWindow {
+                matcher,
+                condition,
+                timeout,
+                on_timeout,
+            } => {
+                let condition = match condition {
+                    WindowCondition::Exists => "open",
+                    WindowCondition::Focused => "focused",
+                };
+                let on_timeout = match on_timeout {
+                    TimeoutPolicy::Stop => "stop",
+                    TimeoutPolicy::Continue => "continue",
+                };
+                let detail = format!(
+                    "{} {} {} {}",
+                    condition,
+                    matcher,
+                    seconds(*timeout),
+                    on_timeout
+                );
+                ("Wait Window", Some(detail))
+            }
+            SerializableEventType::Repeat { count } => (
+                "Repeat",
+                Some(count.map_or(UNTIL_STOPPED.to_string(), |count| count.to_string())),
+            ),
+            SerializableEventType::If { condition } => ("If", Some(condition.to_string())),
+            SerializableEventType::Label { name } => ("Label", Some(name.clone())),
+            SerializableEventType::Jump { label } => ("Jump", Some(label.clone())),
+            SerializableEventType::Exit { code } => ("Exit", Some(code.to_string())),
+            SerializableEventType::Call { script, parameters } => {
+                let mut detail = script.display().to_string();
+                for (name, value) in parameters {
+                    detail.push_str(&format!("; {}={}", name, value));
+                }
+                ("Call", Some(detail))
+            }
+            event_type => {
+                let (kind, detail) = describe_step(event_type);
+                row.detail = detail;
+                (kind, None)
+            }
+        };
+        row.kind = kind;
+        if let Some(detail) = detail {
+            row.detail = detail;
+            row.detail_editable = true;
+        }
+        row
+    }
+
+    pub fn field(&self, field: EventField) -> Option<String> {
+        match field {
+            EventField::Offset => Some(self.offset.as_millis().to_string()),
+            EventField::Detail => self.detail_editable.then(|| self.detail.clone()),
+            EventField::X => self.x.clone(),
+            EventField::Y => self.y.clone(),
+        }
+    }
+}
+
+// Name and summary of a script step, which the table shows but can't edit
+fn describe_step(event_type: &SerializableEventType) -> (&'static str, String) {
+    match event_type {
+        SerializableEventType::ActiveWindow { title, .. } => ("Window", title.clone()),
+        SerializableEventType::Else => ("Else", String::new()),
+        SerializableEventType::End => ("End", String::new()),
+        SerializableEventType::Rhai { source } => (
+            "Rhai",
+            source.lines().next().unwrap_or_default().to_string(),
+        ),
+        // Input events have their own rows
+        _ => ("", String::new()),
+    }
+}
+
+// Changes made through the event table, with undo and redo. Every change keeps
+// the events in time order, so the script still plays from top to bottom.
+#[derive(Debug, Clone, Default)]
+pub struct EventEditor {
+    undo: Vec<Vec<SerializableEvent>>,
+    redo: Vec<Vec<SerializableEvent>>,
+}
+
+impl EventEditor {
+    pub fn new() -> Self {
+        EventEditor::default()
+    }
+
+    // Forget the history, for when another script is loaded or recorded
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    fn remember(&mut self, events: &[SerializableEvent]) {
+        self.undo.push(events.to_vec());
+        if self.undo.len() > UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn undo(&mut self, events: &mut Vec<SerializableEvent>) -> bool {
+        match self.undo.pop() {
+            Some(previous) => {
+                self.redo.push(std::mem::replace(events, previous));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self, events: &mut Vec<SerializableEvent>) -> bool {
+        match self.redo.pop() {
+            Some(next) => {
+                self.undo.push(std::mem::replace(events, next));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn delete(&mut self, events: &mut Vec<SerializableEvent>, index: usize) {
+        if index < events.len() {
+            self.remember(events);
+            events.remove(index);
+        }
+    }
+
+    // A copy goes right after the original, at the same time
+    pub fn duplicate(&mut self, events: &mut Vec<SerializableEvent>, index: usize) {
+        if let Some(event) = events.get(index).cloned() {
+            self.remember(events);
+            events.insert(index + 1, event);
+        }
+    }
+
+    // The moved event takes the time of the one it now follows, or the one it
+    // now comes before when it's moved to the top
+    pub fn move_event(&mut self, events: &mut Vec<SerializableEvent>, from: usize, to: usize) {
+        if from == to || from >= events.len() || to >= events.len() {
+            return;
+        }
+        self.remember(events);
+        let mut event = events.remove(from);
+        event.time = match to.checked_sub(1) {
+            Some(previous) => events[previous].time,
+            None => events[0].time,
+        };
+        events.insert(to, event);
+    }
+
+    // A new event at `index`, at the time of the event before it
+    pub fn insert(&mut self, events: &mut Vec<SerializableEvent>, index: usize, kind: NewEvent) {
+        let index = index.min(events.len());
+        let time = index
+            .checked_sub(1)
+            .map(|previous| events[previous].time)
+            .or_else(|| events.first().map(|event| event.time))
+            .unwrap_or_else(SystemTime::now);
+        self.remember(events);
+        events.insert(
+            index,
+            SerializableEvent {
+                time,
+                event_type: kind.event_type(),
+            },
+        );
+    }
+
+    // Remove the events in a stretch of time, see timeline::delete_range
+    pub fn delete_range(
+        &mut self,
+        events: &mut Vec<SerializableEvent>,
+        from: Duration,
+        to: Duration,
+    ) {
+        if !events.is_empty() {
+            self.remember(events);
+            timeline::delete_range(events, from, to);
+        }
+    }
+
+    // Slow down or speed up a stretch of time, see timeline::stretch_range
+    pub fn stretch_range(
+        &mut self,
+        events: &mut [SerializableEvent],
+        from: Duration,
+        to: Duration,
+        factor: f64,
+    ) -> Result<(), String> {
+        if !(factor.is_finite() && factor > 0.0) {
+            return Err(format!("Can't stretch by {}, it must be above 0", factor));
+        }
+        if !events.is_empty() {
+            self.remember(events);
+            timeline::stretch_range(events, from, to, factor);
+        }
+        Ok(())
+    }
+
+    // Set one field of an event from what was typed into the table. Offsets are
+    // kept between the events around it.
+    pub fn edit(
+        &mut self,
+        events: &mut [SerializableEvent],
+        index: usize,
+        field: EventField,
+        value: &str,
+    ) -> Result<(), String> {
+        let Some(event) = events.get(index) else {
+            return Err(format!("There is no event {}", index + 1));
+        };
+        let value = value.trim();
+        let mut edited = event.clone();
+
+        match (field, &mut edited.event_type) {
+            (EventField::Offset, _) => {
+                if index == 0 {
+                    return Err("The first event always starts at 0".to_string());
+                }
+                let millis: u64 = value
+                    .parse()
+                    .map_err(|_| format!("\"{}\" is not a whole number of milliseconds", value))?;
+                let time = events[0].time + Duration::from_millis(millis);
+                let earliest = events[index - 1].time;
+                let latest = events.get(index + 1).map_or(time, |next| next.time);
+                edited.time = time.clamp(earliest, latest.max(earliest));
+            }
+            (
+                EventField::Detail,
+                SerializableEventType::KeyPress(key) | SerializableEventType::KeyRelease(key),
+            ) => *key = parse_key(value)?,
+            (
+                EventField::Detail,
+                SerializableEventType::ButtonPress(button)
+                | SerializableEventType::ButtonRelease(button),
+            ) => *button = parse_any_button(value)?,
+            (EventField::Detail, SerializableEventType::TypeText { text, .. }) => {
+                *text = value.to_string()
+            }
+            (EventField::X, SerializableEventType::MouseMove { x, .. })
+            | (EventField::Y, SerializableEventType::MouseMove { y: x, .. }) => {
+                *x = value
+                    .parse()
+                    .ok()
+                    .filter(|x: &f64| x.is_finite())
+                    .ok_or_else(|| format!("\"{}\" is not a position", value))?
+            }
+            (EventField::X, SerializableEventType::WaitForPixel { x, .. })
+            | (EventField::Y, SerializableEventType::WaitForPixel { y: x, .. }) => {
+                *x = value
+                    .parse()
+                    .map_err(|_| format!("\"{}\" is not a whole number of pixels", value))?
+            }
+            (
+                EventField::Detail,
+                SerializableEventType::WaitForPixel {
+                    color,
+                    tolerance,
+                    timeout,
+                    ..
+                },
+            ) => {
+                // Any of "#RRGGBB", "±tolerance" and "seconds" in any order
+                for part in value.split_whitespace() {
+                    if let Some(number) = part.strip_prefix('±').or(part.strip_prefix("+-")) {
+                        *tolerance = number.parse().map_err(|_| {
+                            format!("\"{}\" is not a tolerance from 0 to 255", number)
+                        })?;
+                    } else if part.ends_with('s') {
+                        *timeout = parse_seconds(part)?;
+                    } else {
+                        *color = parse_color(part)?;
+                    }
+                }
+            }
+            (
+                EventField::Detail,
+                SerializableEventType::WaitForImage {
+                    region,
+                    confidence,
+                    timeout,
+                    action,
+                    ..
+                },
+            ) => {
+                // Any of "percent%", "seconds", "wait", "move", "click [button]",
+                // "anywhere" and "in X,Y WxH" in any order
+                let mut parts = value.split_whitespace().peekable();
+                while let Some(part) = parts.next() {
+                    match part.to_lowercase().as_str() {
+                        "wait" => *action = MatchAction::Wait,
+                        "move" => *action = MatchAction::Move,
+                        "click" => {
+                            let button = parts.next_if(|part| parse_button(part).is_ok());
+                            *action = MatchAction::Click(
+                                button.map_or(Ok(SerializableButton::Left), parse_button)?,
+                            );
+                        }
+                        "anywhere" => *region = None,
+                        "in" => *region = Some(parse_region(parts.next(), parts.next())?),
+                        _ if part.ends_with('%') => *confidence = parse_percent(part)?,
+                        _ if part.ends_with('s') => *timeout = parse_seconds(part)?,
+                        _ => return Err(format!("\"{}\" is not part of a Wait Image step", part)),
+                    }
+                }
+            }
+            (
+                EventField::Detail,
+                SerializableEventType::WaitForWindow {
+                    matcher,
+                    condition,
+                    timeout,
+                    on_timeout,
+                },
+            ) => {
+                // "open" or "focused", the window, then the timeout and "stop" or
+                // "continue" when it runs out. Parts left out stay as they were.
+                let mut rest = value;
+                loop {
+                    let (before, last) =
+                        rest.rsplit_once(char::is_whitespace).unwrap_or(("", rest));
+                    match last.to_lowercase().as_str() {
+                        "stop" => *on_timeout = TimeoutPolicy::Stop,
+                        "continue" => *on_timeout = TimeoutPolicy::Continue,
+                        _ if last.ends_with('s') => *timeout = parse_seconds(last)?,
+                        _ => break,
+                    }
+                    rest = before.trim_end();
+                }
+                let (first, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let leading = match first.to_lowercase().as_str() {
+                    "open" => Some(WindowCondition::Exists),
+                    "focused" => Some(WindowCondition::Focused),
+                    _ => None,
+                };
+                if let Some(leading) = leading {
+                    *condition = leading;
+                    rest = after.trim_start();
+                }
+                if !rest.is_empty() {
+                    *matcher = rest.parse()?;
+                }
+            }
+            (EventField::Detail, SerializableEventType::Repeat { count }) => {
+                *count = if value.eq_ignore_ascii_case(UNTIL_STOPPED) || value.is_empty() {
+                    None
+                } else {
+                    Some(value.parse().map_err(|_| {
+                        format!(
+                            "\"{}\" is not a number of times or \"{}\"",
+                            value, UNTIL_STOPPED
+                        )
+                    })?)
+                }
+            }
+            (EventField::Detail, SerializableEventType::If { condition }) => {
+                *condition = value.parse()?
+            }
+            (EventField::Detail, SerializableEventType::Label { name: label })
+            | (EventField::Detail, SerializableEventType::Jump { label }) => {
+                if value.is_empty() {
+                    return Err("Labels need a name".to_string());
+                }
+                *label = value.to_string()
+            }
+            (EventField::Detail, SerializableEventType::Exit { code }) => {
+                *code = value
+                    .parse()
+                    .map_err(|_| format!("\"{}\" is not a whole number", value))?
+            }
+            (EventField::Detail, SerializableEventType::Call { script, parameters }) => {
+                // The script, then its parameters, like "login.bin; user=ann; tries=3"
+                let mut parts = value.split(';');
+                let path = parts.next().unwrap_or_default().trim();
+                if path.is_empty() {
+                    return Err("Call steps need a script to play".to_string());
+                }
+                *script = PathBuf::from(path);
+                *parameters = parts
+                    .filter(|part| !part.trim().is_empty())
+                    .map(|part| match part.split_once('=') {
+                        Some((name, value))
+                            if !name.trim().is_empty() && !name.trim().contains(' ') =>
+                        {
+                            Ok((name.trim().to_string(), value.trim().to_string()))
+                        }
+                        _ => Err(format!(
+                            "\"{}\" is not a parameter, like name=value",
+                            part.trim()
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            (EventField::X, SerializableEventType::Wheel { delta_x: delta, .. })
+            | (EventField::Y, SerializableEventType::Wheel { delta_y: delta, .. }) => {
+                *delta = value
+                    .parse()
+                    .map_err(|_| format!("\"{}\" is not a whole number", value))?
+            }
+            _ => return Err(format!("Event {} can't be changed there", index + 1)),
+        }
+
+        self.remember(events);
+        events[index] = edited;
+        Ok(())
+    }
+
+    // Swap the image a Wait Image step looks for, it has to be a PNG or another
+    // image that can be decoded
+    pub fn set_template(
+        &mut self,
+        events: &mut [SerializableEvent],
+        index: usize,
+        template: Vec<u8>,
+    ) -> Result<(), String> {
+        image::load_from_memory(&template)
+            .map_err(|e| format!("Could not read the template: {}", e))?;
+        self.change_step(events, index, |event_type| match event_type {
+            SerializableEventType::WaitForImage { template: old, .. } => {
+                *old = template;
+                Ok(())
+            }
+            _ => Err(format!("Event {} is not a Wait Image step", index + 1)),
+        })
+    }
+
+    // Point a Wait Window step at another window
+    pub fn set_window(
+        &mut self,
+        events: &mut [SerializableEvent],
+        index: usize,
+        matcher: WindowMatcher,
+    ) -> Result<(), String> {
+        self.change_step(events, index, |event_type| match event_type {
+            SerializableEventType::WaitForWindow { matcher: old, .. } => {
+                *old = matcher;
+                Ok(())
+            }
+            _ => Err(format!("Event {} is not a Wait Window step", index + 1)),
+        })
+    }
+
+    // Play another script from a Call step, keeping its parameters
+    pub fn set_call_script(
+        &mut self,
+        events: &mut [SerializableEvent],
+        index: usize,
+        path: PathBuf,
+    ) -> Result<(), String> {
+        self.change_step(events, index, |event_type| match event_type {
+            SerializableEventType::Call { script, .. } => {
+                *script = path;
+                Ok(())
+            }
+            _ => Err(format!("Event {} is not a Call step", index + 1)),
+        })
+    }
+
+    // Change a copy of a step, and keep it only if that worked
+    fn change_step(
+        &mut self,
+        events: &mut [SerializableEvent],
+        index: usize,
+        change: impl FnOnce(&mut SerializableEventType) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let Some(event) = events.get(index) else {
+            return Err(format!("There is no event {}", index + 1));
+        };
+        let mut edited = event.clone();
+        change(&mut edited.event_type)?;
+        self.remember(events);
+        events[index] = edited;
+        Ok(())
+    }
+}
+
+// Timeouts are shown and typed in seconds, like "2.5s"
+fn seconds(duration: Duration) -> String {
+    format!("{}s", duration.as_secs_f64())
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .strip_suffix('s')
+        .and_then(|seconds| seconds.parse().ok())
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("\"{}\" is not a number of seconds like 2.5s", value))
+}
+
+// A confidence like "85%"
+fn parse_percent(value: &str) -> Result<f32, String> {
+    value
+        .strip_suffix('%')
+        .and_then(|percent| percent.parse::<f32>().ok())
+        .filter(|percent| (0.0..=100.0).contains(percent))
+        .map(|percent| percent / 100.0)
+        .ok_or_else(|| format!("\"{}\" is not a percentage from 0% to 100%", value))
+}
+
+// The two parts after "in", like "10,20 200x100"
+fn parse_region(position: Option<&str>, size: Option<&str>) -> Result<Region, String> {
+    let position = position
+        .and_then(|position| position.split_once(','))
+        .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+    let size = size
+        .and_then(|size| size.split_once('x'))
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+    match (position, size) {
+        (Some((x, y)), Some((width, height))) if width > 0 && height > 0 => Ok(Region {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => {
+            Err("A region is written \"in X,Y WIDTHxHEIGHT\", like \"in 0,0 200x100\"".to_string())
+        }
+    }
+}
+
+// Left, right, middle, or "Button 8" and plain numbers for the others
+fn parse_any_button(value: &str) -> Result<SerializableButton, String> {
+    let number = value
+        .to_lowercase()
+        .trim_start_matches("button")
+        .trim()
+        .parse()
+        .ok();
+    match number {
+        Some(number) => Ok(SerializableButton::Unknown(number)),
+        None => parse_button(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(event_types: Vec<SerializableEventType>) -> Vec<SerializableEvent> {
+        let start = SystemTime::UNIX_EPOCH;
+        event_types
+            .into_iter()
+            .enumerate()
+            .map(|(i, event_type)| SerializableEvent {
+                time: start + Duration::from_millis(100 * i as u64),
+                event_type,
+            })
+            .collect()
+    }
+
+    fn keys(events: &[SerializableEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| format!("{:?}", event.event_type))
+            .collect()
+    }
+
+    fn millis(events: &[SerializableEvent]) -> Vec<u128> {
+        events
+            .iter()
+            .map(|event| {
+                event
+                    .time
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis()
+            })
+            .collect()
+    }
+
+    fn typing() -> Vec<SerializableEvent> {
+        events(vec![
+            SerializableEventType::KeyPress(SerializableKey::KeyA),
+            SerializableEventType::KeyRelease(SerializableKey::KeyA),
+            SerializableEventType::MouseMove { x: 10.0, y: 20.0 },
+        ])
+    }
+
+    #[test]
+    fn undo_and_redo_step_through_edits() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        let original = keys(&events);
+        assert!(!editor.can_undo() && !editor.can_redo());
+
+        editor.delete(&mut events, 0);
+        editor.duplicate(&mut events, 1);
+        let edited = keys(&events);
+        assert_eq!(edited.len(), 3);
+
+        assert!(editor.undo(&mut events));
+        assert!(editor.undo(&mut events));
+        assert_eq!(keys(&events), original);
+        assert!(!editor.undo(&mut events));
+
+        assert!(editor.redo(&mut events));
+        assert!(editor.redo(&mut events));
+        assert_eq!(keys(&events), edited);
+        assert!(!editor.redo(&mut events));
+
+        // A new edit after undoing drops what could be redone
+        editor.undo(&mut events);
+        editor.delete(&mut events, 0);
+        assert!(!editor.can_redo());
+    }
+
+    #[test]
+    fn undo_keeps_a_limited_history() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        for _ in 0..UNDO_LIMIT + 10 {
+            editor.duplicate(&mut events, 0);
+        }
+        let mut undone = 0;
+        while editor.undo(&mut events) {
+            undone += 1;
+        }
+        assert_eq!(undone, UNDO_LIMIT);
+        assert_eq!(events.len(), 3 + 10);
+    }
+
+    #[test]
+    fn moved_events_take_the_time_of_their_new_neighbour() {
+        let mut editor = EventEditor::new();
+
+        let mut events = typing();
+        editor.move_event(&mut events, 2, 0);
+        assert!(keys(&events)[0].starts_with("MouseMove"));
+        assert_eq!(millis(&events), [0, 0, 100]);
+
+        let mut events = typing();
+        editor.move_event(&mut events, 0, 2);
+        assert_eq!(keys(&events)[2], "KeyPress(KeyA)");
+        assert_eq!(millis(&events), [100, 200, 200]);
+
+        // Moves that go nowhere aren't remembered
+        let mut editor = EventEditor::new();
+        editor.move_event(&mut events, 1, 1);
+        editor.move_event(&mut events, 0, 3);
+        assert!(!editor.can_undo());
+    }
+
+    #[test]
+    fn edits_fields_that_fit_the_event() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+
+        editor
+            .edit(&mut events, 0, EventField::Detail, " KeyB ")
+            .unwrap();
+        assert_eq!(keys(&events)[0], "KeyPress(KeyB)");
+        editor.edit(&mut events, 2, EventField::Y, "42.5").unwrap();
+        assert_eq!(keys(&events)[2], "MouseMove { x: 10.0, y: 42.5 }");
+
+        assert!(editor
+            .edit(&mut events, 2, EventField::Detail, "KeyB")
+            .is_err());
+        assert!(editor.edit(&mut events, 0, EventField::X, "5").is_err());
+        assert!(editor.edit(&mut events, 2, EventField::X, "NaN").is_err());
+        assert!(editor.edit(&mut events, 9, EventField::X, "5").is_err());
+
+        // Only the two good edits can be undone
+        assert!(editor.undo(&mut events));
+        assert!(editor.undo(&mut events));
+        assert!(!editor.undo(&mut events));
+        assert_eq!(keys(&events), keys(&typing()));
+    }
+
+    #[test]
+    fn edited_offsets_stay_between_their_neighbours() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+
+        editor
+            .edit(&mut events, 1, EventField::Offset, "150")
+            .unwrap();
+        assert_eq!(millis(&events), [0, 150, 200]);
+        editor
+            .edit(&mut events, 1, EventField::Offset, "900")
+            .unwrap();
+        assert_eq!(millis(&events), [0, 200, 200]);
+        editor
+            .edit(&mut events, 2, EventField::Offset, "900")
+            .unwrap();
+        assert_eq!(millis(&events), [0, 200, 900]);
+
+        assert!(editor
+            .edit(&mut events, 0, EventField::Offset, "50")
+            .is_err());
+        assert!(editor
+            .edit(&mut events, 1, EventField::Offset, "-5")
+            .is_err());
+    }
+
+    #[test]
+    fn inserts_at_the_time_of_the_event_before() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        editor.insert(&mut events, 2, NewEvent::Wheel);
+        assert_eq!(keys(&events)[2], "Wheel { delta_x: 0, delta_y: -1 }");
+        assert_eq!(millis(&events), [0, 100, 100, 200]);
+
+        editor.insert(&mut events, 0, NewEvent::KeyPress);
+        assert_eq!(millis(&events)[0], 0);
+    }
+
+    #[test]
+    fn edits_wait_for_pixel_steps() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        editor.insert(&mut events, 3, NewEvent::WaitForPixel);
+
+        editor.edit(&mut events, 3, EventField::X, "15").unwrap();
+        editor.edit(&mut events, 3, EventField::Y, "-25").unwrap();
+        editor
+            .edit(&mut events, 3, EventField::Detail, "2.5s #ff8000 ±5")
+            .unwrap();
+        assert_eq!(
+            keys(&events)[3],
+            "WaitForPixel { x: 15, y: -25, color: [255, 128, 0], tolerance: 5, timeout: 2.5s }"
+        );
+        let row = EventRow::new(&events[3], events[0].time);
+        assert_eq!(row.detail, "#FF8000 ±5 2.5s");
+        assert!(row.detail_editable);
+
+        // Left out parts stay as they were
+        editor
+            .edit(&mut events, 3, EventField::Detail, "+-7")
+            .unwrap();
+        assert!(keys(&events)[3].contains("tolerance: 7, timeout: 2.5s"));
+
+        for bad in ["±300", "#FF80", "-1s", "5"] {
+            assert!(editor
+                .edit(&mut events, 3, EventField::Detail, bad)
+                .is_err());
+        }
+        assert!(editor.edit(&mut events, 3, EventField::X, "1.5").is_err());
+    }
+
+    #[test]
+    fn edits_wait_for_image_steps() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        editor.insert(&mut events, 3, NewEvent::WaitForImage);
+
+        editor
+            .edit(
+                &mut events,
+                3,
+                EventField::Detail,
+                "in 10,-20 200x100 click right 80% 2.5s",
+            )
+            .unwrap();
+        let row = EventRow::new(&events[3], events[0].time);
+        assert_eq!(row.detail, "80% 2.5s click Right in 10,-20 200x100");
+        editor
+            .edit(&mut events, 3, EventField::Detail, "anywhere click")
+            .unwrap();
+        let row = EventRow::new(&events[3], events[0].time);
+        assert_eq!(row.detail, "80% 2.5s click Left anywhere");
+
+        for bad in ["120%", "in 10,20", "in 0,0 0x10", "click sideways", "5"] {
+            assert!(editor
+                .edit(&mut events, 3, EventField::Detail, bad)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn templates_have_to_be_images() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        editor.insert(&mut events, 3, NewEvent::WaitForImage);
+
+        let mut png = Vec::new();
+        image::RgbImage::new(2, 2)
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        assert!(editor
+            .set_template(&mut events, 3, b"not a png".to_vec())
+            .is_err());
+        assert!(editor.set_template(&mut events, 0, png.clone()).is_err());
+        editor.set_template(&mut events, 3, png.clone()).unwrap();
+        match &events[3].event_type {
+            SerializableEventType::WaitForImage { template, .. } => assert_eq!(*template, png),
+            other => panic!("{:?}", other),
+        }
+        assert!(editor.undo(&mut events));
+        assert!(editor.undo(&mut events));
+        assert!(!editor.can_undo());
+    }
+
+    #[test]
+    fn edits_wait_for_window_steps() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        editor.insert(&mut events, 3, NewEvent::WaitForWindow);
+        let detail =
+            |events: &[SerializableEvent]| EventRow::new(&events[3], events[0].time).detail;
+        assert_eq!(detail(&events), "open any window 10s stop");
+
+        editor
+            .edit(
+                &mut events,
+                3,
+                EventField::Detail,
+                "focused title \"Save As\" 2.5s continue",
+            )
+            .unwrap();
+        assert_eq!(detail(&events), "focused title \"Save As\" 2.5s continue");
+        editor
+            .edit(&mut events, 3, EventField::Detail, "open")
+            .unwrap();
+        assert_eq!(detail(&events), "open title \"Save As\" 2.5s continue");
+        editor
+            .edit(&mut events, 3, EventField::Detail, "class \"gedit\" stop")
+            .unwrap();
+        assert_eq!(detail(&events), "open class \"gedit\" 2.5s stop");
+
+        for bad in ["open Save As", "title \"a\" -1s", "name \"a\""] {
+            assert!(editor
+                .edit(&mut events, 3, EventField::Detail, bad)
+                .is_err());
+        }
+
+        let picked = WindowMatcher {
+            title: Some("notes.txt".to_string()),
+            class: Some("Gedit".to_string()),
+        };
+        editor.set_window(&mut events, 3, picked).unwrap();
+        assert_eq!(
+            detail(&events),
+            "open title \"notes.txt\" and class \"Gedit\" 2.5s stop"
+        );
+        assert!(editor
+            .set_window(&mut events, 0, WindowMatcher::default())
+            .is_err());
+    }
+
+    #[test]
+    fn edits_control_flow_steps() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        for (index, kind) in [
+            (0, NewEvent::Label),
+            (1, NewEvent::Repeat),
+            (2, NewEvent::If),
+            (4, NewEvent::Else),
+            (6, NewEvent::End),
+            (7, NewEvent::End),
+            (8, NewEvent::Jump),
+            (9, NewEvent::Exit),
+        ] {
+            editor.insert(&mut events, index, kind);
+        }
+        crate::flow::parse(&events).unwrap();
+        let detail = |events: &[SerializableEvent], index: usize| {
+            EventRow::new(&events[index], events[0].time).detail
+        };
+        assert_eq!(detail(&events, 1), "2");
+        assert_eq!(detail(&events, 2), "name = value");
+        assert!(!EventRow::new(&events[4], events[0].time).detail_editable);
+
+        editor
+            .edit(&mut events, 1, EventField::Detail, "Until Stopped")
+            .unwrap();
+        assert_eq!(detail(&events, 1), UNTIL_STOPPED);
+        editor
+            .edit(&mut events, 1, EventField::Detail, "5")
+            .unwrap();
+        assert_eq!(keys(&events)[1], "Repeat { count: Some(5) }");
+        editor
+            .edit(
+                &mut events,
+                2,
+                EventField::Detail,
+                "not pixel 1,2 #FF0000 ±3",
+            )
+            .unwrap();
+        assert_eq!(detail(&events, 2), "not pixel 1,2 #FF0000 ±3");
+        editor
+            .edit(&mut events, 0, EventField::Detail, " again ")
+            .unwrap();
+        editor
+            .edit(&mut events, 8, EventField::Detail, "again")
+            .unwrap();
+        editor
+            .edit(&mut events, 9, EventField::Detail, "-2")
+            .unwrap();
+        assert_eq!(keys(&events)[9], "Exit { code: -2 }");
+        crate::flow::parse(&events).unwrap();
+
+        for (index, bad) in [(1, "twice"), (2, "pixel"), (0, " "), (9, "2.5")] {
+            assert!(editor
+                .edit(&mut events, index, EventField::Detail, bad)
+                .is_err());
+        }
+        assert!(editor
+            .edit(&mut events, 4, EventField::Detail, "x")
+            .is_err());
+    }
+
+    #[test]
+    fn edits_call_steps() {
+        let mut editor = EventEditor::new();
+        let mut events = typing();
+        editor.insert(&mut events, 3, NewEvent::Call);
+        let detail =
+            |events: &[SerializableEvent]| EventRow::new(&events[3], events[0].time).detail;
+
+        editor
+            .edit(
+                &mut events,
+                3,
+                EventField::Detail,
+                "scripts/log in.bin; user = ann lee ;tries=3;",
+            )
+            .unwrap();
+        assert_eq!(detail(&events), "scripts/log in.bin; tries=3; user=ann lee");
+
+        editor
+            .set_call_script(&mut events, 3, PathBuf::from("other.bin"))
+            .unwrap();
+        assert_eq!(detail(&events), "other.bin; tries=3; user=ann lee");
+        editor
+            .edit(&mut events, 3, EventField::Detail, "other.bin")
+            .unwrap();
+        assert_eq!(detail(&events), "other.bin");
+
+        for bad in [
+            "",
+            "; user=ann",
+            "other.bin; user",
+            "other.bin; my user=ann",
+        ] {
+            assert!(editor
+                .edit(&mut events, 3, EventField::Detail, bad)
+                .is_err());
+        }
+        assert!(editor
+            .set_call_script(&mut events, 0, PathBuf::from("other.bin"))
+            .is_err());
+    }
+}
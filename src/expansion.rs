@@ -0,0 +1,320 @@
+use crate::calls::resolve_call;
+use crate::config::{config_dir, load_config};
+use crate::layout::{KeyboardLayout, Keystroke};
+use crate::serializable_event::{SerializableEvent, SerializableEventType, SerializableKey};
+use crate::settings::ControlKeys;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+// Text expansions are kept in expansions.toml in the config directory, for example:
+//
+//   [[expansions]]
+//   trigger = ";sig"
+//   text = "Best regards,\nSam"
+//
+//   [[expansions]]
+//   trigger = ";login"
+//   run = "login.bin"
+const EXPANSIONS_FILE: &str = "expansions.toml";
+
+// Gap between the backspaces that erase a trigger
+const BACKSPACE_GAP: Duration = Duration::from_millis(10);
+
+// Typing `trigger` anywhere replaces it with `text`, or erases it and plays the
+// script or playlist `run`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Expansion {
+    pub trigger: String,
+    #[serde(default)]
+    pub text: Option<String>,
+    // Relative to the config directory
+    #[serde(default)]
+    pub run: Option<PathBuf>,
+}
+
+impl Expansion {
+    // Backspaces over the trigger, then the snippet as a type-text step
+    pub fn events(&self, per_char_delay: Duration) -> Vec<SerializableEvent> {
+        let start = SystemTime::now();
+        let mut time = start;
+        let mut events = Vec::new();
+        for _ in self.trigger.chars() {
+            for event_type in Keystroke::plain(SerializableKey::Backspace).event_types() {
+                events.push(SerializableEvent { time, event_type });
+            }
+            time += BACKSPACE_GAP;
+        }
+        if let Some(text) = &self.text {
+            events.push(SerializableEvent {
+                time,
+                event_type: SerializableEventType::TypeText {
+                    text: text.clone(),
+                    per_char_delay,
+                },
+            });
+        }
+        events
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Expansions {
+    // Gap between the characters of a snippet
+    #[serde(default = "default_char_delay")]
+    pub per_char_delay_ms: u64,
+    #[serde(default)]
+    pub expansions: Vec<Expansion>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+fn default_char_delay() -> u64 {
+    10
+}
+
+impl Expansions {
+    pub fn path() -> Result<PathBuf, String> {
+        Ok(config_dir()?.join(EXPANSIONS_FILE))
+    }
+
+    // No expansions file yet is the same as an empty one
+    pub fn load(
+        path: &Path,
+        keys: &ControlKeys,
+        layout: KeyboardLayout,
+    ) -> Result<Expansions, String> {
+        let mut expansions: Expansions = load_config(path)?;
+        expansions.validate(keys, layout)?;
+        expansions.path = Some(path.to_path_buf());
+        Ok(expansions)
+    }
+
+    // Every trigger needs a snippet or a script, and can't end with another
+    // trigger since the shorter one would always fire first. Typing the record
+    // or run key starts recording or playing instead, so triggers can't contain
+    // the characters those keys type on `layout`.
+    pub fn validate(&self, keys: &ControlKeys, layout: KeyboardLayout) -> Result<(), String> {
+        for (i, expansion) in self.expansions.iter().enumerate() {
+            if expansion.trigger.is_empty() {
+                return Err(format!("Expansion {} has an empty trigger", i + 1));
+            }
+            if expansion.text.is_some() == expansion.run.is_some() {
+                return Err(format!(
+                    "\"{}\" needs either text or run",
+                    expansion.trigger
+                ));
+            }
+            if let Some(c) = expansion.trigger.chars().find(|&c| {
+                layout
+                    .keystroke(c)
+                    .is_some_and(|stroke| stroke.key == keys.record || stroke.key == keys.run)
+            }) {
+                return Err(format!(
+                    "\"{}\" uses '{}', which is typed with the record or run key",
+                    expansion.trigger, c
+                ));
+            }
+            if let Some(other) = self
+                .expansions
+                .iter()
+                .enumerate()
+                .find(|(j, other)| *j != i && expansion.trigger.ends_with(&other.trigger))
+            {
+                return Err(format!(
+                    "\"{}\" can never be typed, \"{}\" fires first",
+                    expansion.trigger, other.1.trigger
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn per_char_delay(&self) -> Duration {
+        Duration::from_millis(self.per_char_delay_ms)
+    }
+
+    // Where an expansion's script or playlist is
+    pub fn script_path(&self, run: &Path) -> PathBuf {
+        resolve_call(self.path.as_deref(), run)
+    }
+
+    fn longest_trigger(&self) -> usize {
+        self.expansions
+            .iter()
+            .map(|expansion| expansion.trigger.chars().count())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// Rebuilds the text being typed from the global key events to spot triggers.
+// Anything that could move the caret (clicks, arrows, Return) starts it over.
+#[derive(Debug, Clone, Default)]
+pub struct TextTracker {
+    typed: Vec<char>,
+    held: Vec<SerializableKey>,
+    caps_lock: bool,
+}
+
+impl TextTracker {
+    pub fn new() -> Self {
+        TextTracker::default()
+    }
+
+    // Forget what was typed so far
+    pub fn clear(&mut self) {
+        self.typed.clear();
+    }
+
+    // Feed every input event through here. Returns the expansion whose trigger
+    // was just finished, keys are turned into characters with `layout`.
+    pub fn update<'a>(
+        &mut self,
+        event_type: &SerializableEventType,
+        layout: KeyboardLayout,
+        expansions: &'a Expansions,
+    ) -> Option<&'a Expansion> {
+        let key = match event_type {
+            SerializableEventType::KeyPress(key) => *key,
+            SerializableEventType::KeyRelease(key) => {
+                self.held.retain(|held| held != key);
+                return None;
+            }
+            SerializableEventType::ButtonPress(_) => {
+                self.typed.clear();
+                return None;
+            }
+            _ => return None,
+        };
+
+        let repeat = self.held.contains(&key);
+        if !repeat {
+            self.held.push(key);
+        }
+        match key {
+            SerializableKey::ShiftLeft
+            | SerializableKey::ShiftRight
+            | SerializableKey::AltGr
+            | SerializableKey::Function => return None,
+            SerializableKey::CapsLock => {
+                if !repeat {
+                    self.caps_lock = !self.caps_lock;
+                }
+                return None;
+            }
+            SerializableKey::Backspace => {
+                self.typed.pop();
+                return None;
+            }
+            _ => {}
+        }
+
+        // Shortcuts like Ctrl+C don't type anything
+        let shortcut = self.held.iter().any(|held| {
+            matches!(
+                held,
+                SerializableKey::ControlLeft
+                    | SerializableKey::ControlRight
+                    | SerializableKey::Alt
+                    | SerializableKey::MetaLeft
+                    | SerializableKey::MetaRight
+            )
+        });
+        let stroke = Keystroke {
+            key,
+            shift: self.held_any(&[SerializableKey::ShiftLeft, SerializableKey::ShiftRight]),
+            altgr: self.held_any(&[SerializableKey::AltGr]),
+        };
+        let Some(c) = layout.character(stroke).filter(|c| !shortcut && *c != '\n') else {
+            self.typed.clear();
+            return None;
+        };
+        let c = if self.caps_lock && c.is_alphabetic() {
+            swap_case(c)
+        } else {
+            c
+        };
+
+        self.typed.push(c);
+        let longest = expansions.longest_trigger();
+        if self.typed.len() > longest {
+            self.typed.drain(..self.typed.len() - longest);
+        }
+
+        let typed: String = self.typed.iter().collect();
+        let expansion = expansions
+            .expansions
+            .iter()
+            .find(|expansion| typed.ends_with(&expansion.trigger))?;
+        self.typed.clear();
+        Some(expansion)
+    }
+
+    fn held_any(&self, keys: &[SerializableKey]) -> bool {
+        self.held.iter().any(|held| keys.contains(held))
+    }
+}
+
+fn swap_case(c: char) -> char {
+    if c.is_uppercase() {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c.to_uppercase().next().unwrap_or(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expansion(trigger: &str) -> Expansion {
+        Expansion {
+            trigger: trigger.to_string(),
+            text: Some("snippet".to_string()),
+            run: None,
+        }
+    }
+
+    fn expansions(triggers: &[&str]) -> Expansions {
+        Expansions {
+            expansions: triggers.iter().map(|trigger| expansion(trigger)).collect(),
+            ..Expansions::default()
+        }
+    }
+
+    #[test]
+    fn rejects_triggers_typed_with_the_record_or_run_keys() {
+        let keys = ControlKeys::default();
+        let us = KeyboardLayout::Us;
+        assert!(expansions(&[";sig", "..."]).validate(&keys, us).is_ok());
+        assert!(expansions(&["a,b"]).validate(&keys, us).is_err());
+        assert!(expansions(&["x/"]).validate(&keys, us).is_err());
+        // Shift+Comma is still the record key
+        assert!(expansions(&["<b"]).validate(&keys, us).is_err());
+        // On a German keyboard ; is Shift+Comma
+        assert!(expansions(&[";sig"])
+            .validate(&keys, KeyboardLayout::De)
+            .is_err());
+
+        let moved = ControlKeys {
+            record: SerializableKey::F9,
+            ..keys
+        };
+        assert!(expansions(&["a,b"]).validate(&moved, us).is_ok());
+    }
+
+    #[test]
+    fn rejects_triggers_that_can_never_fire() {
+        let keys = ControlKeys::default();
+        let us = KeyboardLayout::Us;
+        assert!(expansions(&[""]).validate(&keys, us).is_err());
+        assert!(expansions(&[";sig", "sig"]).validate(&keys, us).is_err());
+
+        let mut both = expansions(&[";x"]);
+        both.expansions[0].run = Some(PathBuf::from("x.bin"));
+        assert!(both.validate(&keys, us).is_err());
+    }
+}
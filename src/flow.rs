@@ -0,0 +1,528 @@
+use crate::serializable_event::{SerializableEvent, SerializableEventType};
+use crate::wait::{hex_color, parse_color};
+use crate::window::{WindowCondition, WindowMatcher};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+// Scripts are stored as a flat list of events, with control flow written as
+// marker steps in between (Repeat ... End, If ... Else ... End). `parse` turns
+// that list into a tree for playback, so a plain recording is a single block.
+
+// Something an If step checks when it's reached
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    // A variable currently has exactly this value
+    Variable {
+        name: String,
+        value: String,
+    },
+    // The pixel at (x, y) is within `tolerance` of `color`
+    Pixel {
+        x: i32,
+        y: i32,
+        color: [u8; 3],
+        tolerance: u8,
+    },
+    Window {
+        matcher: WindowMatcher,
+        condition: WindowCondition,
+    },
+    // Running a program exits with this code
+    ExitCode {
+        program: String,
+        args: Vec<String>,
+        code: i32,
+    },
+    Not(Box<Condition>),
+}
+
+// How conditions are written in the event table:
+//   name = value
+//   pixel X,Y #RRGGBB ±tolerance
+//   window open|focused title "..." and class "..."
+//   run program args... exits code
+//   not <condition>
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Variable { name, value } => write!(f, "{} = {}", name, value),
+            Condition::Pixel {
+                x,
+                y,
+                color,
+                tolerance,
+            } => write!(f, "pixel {},{} {} ±{}", x, y, hex_color(*color), tolerance),
+            Condition::Window { matcher, condition } => {
+                let condition = match condition {
+                    WindowCondition::Exists => "open",
+                    WindowCondition::Focused => "focused",
+                };
+                write!(f, "window {} {}", condition, matcher)
+            }
+            Condition::ExitCode {
+                program,
+                args,
+                code,
+            } => {
+                write!(f, "run {}", program)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, " exits {}", code)
+            }
+            Condition::Not(condition) => write!(f, "not {}", condition),
+        }
+    }
+}
+
+impl std::str::FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let (first, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let rest = rest.trim_start();
+
+        // A variable can be called "pixel" or "not" as well
+        if !rest.starts_with('=') {
+            match first.to_lowercase().as_str() {
+                "not" => return Ok(Condition::Not(Box::new(rest.parse()?))),
+                "pixel" => return parse_pixel(rest),
+                "window" => return parse_window(rest),
+                "run" => return parse_exit_code(rest),
+                _ => {}
+            }
+        }
+        match s.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() && !name.trim().contains(' ') => {
+                Ok(Condition::Variable {
+                    name: name.trim().to_string(),
+                    value: value.trim().to_string(),
+                })
+            }
+            _ => Err(format!(
+                "\"{}\" is not a condition, like name = value, pixel 10,20 #FF0000 ±10, \
+                 window open title \"notes\", run program exits 0 or not followed by one",
+                s
+            )),
+        }
+    }
+}
+
+fn parse_pixel(s: &str) -> Result<Condition, String> {
+    let usage = || format!("\"pixel {}\" should be like pixel 10,20 #FF0000 ±10", s);
+    let mut parts = s.split_whitespace();
+    let (x, y) = parts
+        .next()
+        .and_then(|position| position.split_once(','))
+        .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)))
+        .ok_or_else(usage)?;
+    let color = parse_color(parts.next().ok_or_else(usage)?)?;
+    let tolerance = match parts.next() {
+        Some(part) => part
+            .strip_prefix('±')
+            .or(part.strip_prefix("+-"))
+            .and_then(|tolerance| tolerance.parse().ok())
+            .ok_or_else(usage)?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return Err(usage());
+    }
+    Ok(Condition::Pixel {
+        x,
+        y,
+        color,
+        tolerance,
+    })
+}
+
+fn parse_window(s: &str) -> Result<Condition, String> {
+    let (condition, matcher) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+    let condition = match condition.to_lowercase().as_str() {
+        "open" => WindowCondition::Exists,
+        "focused" => WindowCondition::Focused,
+        _ => {
+            return Err(format!(
+                "\"window {}\" should start with window open or window focused",
+                s
+            ))
+        }
+    };
+    Ok(Condition::Window {
+        matcher: matcher.parse()?,
+        condition,
+    })
+}
+
+fn parse_exit_code(s: &str) -> Result<Condition, String> {
+    let usage = || format!("\"run {}\" should be like run program args exits 0", s);
+    let mut parts: Vec<&str> = s.split_whitespace().collect();
+    let code = parts
+        .pop()
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(usage)?;
+    if parts.pop() != Some("exits") || parts.is_empty() {
+        return Err(usage());
+    }
+    Ok(Condition::ExitCode {
+        program: parts[0].to_string(),
+        args: parts[1..].iter().map(|arg| arg.to_string()).collect(),
+        code,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    // When the event or marker was recorded, for keeping the recorded timing
+    pub time: SystemTime,
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum NodeKind {
+    // Input or a script step, played as it is
+    Event(SerializableEventType),
+    // Play `body` `count` times, or until stopped when there's no count
+    Repeat {
+        count: Option<u32>,
+        body: Vec<Node>,
+    },
+    If {
+        condition: Condition,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Label(String),
+    // Continue from a label in the same block or one around it
+    Jump(String),
+    // Stop playback with an exit code
+    Exit(i32),
+}
+
+enum Terminator {
+    Else,
+    End,
+}
+
+// Build the block tree of a flat event list, checking that every block is closed,
+// has something in it, and every jump has a label it can reach
+pub fn parse(events: &[SerializableEvent]) -> Result<Vec<Node>, String> {
+    let mut position = 0;
+    let (block, terminator) = parse_block(events, &mut position)?;
+    if let Some(terminator) = terminator {
+        let name = match terminator {
+            Terminator::Else => "Else",
+            Terminator::End => "End",
+        };
+        return Err(format!(
+            "{} at event {} doesn't belong to a Repeat or If",
+            name, position
+        ));
+    }
+
+    let mut labels = Vec::new();
+    collect_labels(&block, &mut labels)?;
+    check_jumps(&block, &mut Vec::new())?;
+    Ok(block)
+}
+
+fn parse_block(
+    events: &[SerializableEvent],
+    position: &mut usize,
+) -> Result<(Vec<Node>, Option<Terminator>), String> {
+    let mut nodes = Vec::new();
+    while let Some(event) = events.get(*position) {
+        *position += 1;
+        // Events are numbered from 1 in messages
+        let number = *position;
+
+        let kind = match &event.event_type {
+            SerializableEventType::Else => return Ok((nodes, Some(Terminator::Else))),
+            SerializableEventType::End => return Ok((nodes, Some(Terminator::End))),
+            SerializableEventType::Repeat { count } => {
+                let (body, terminator) = parse_block(events, position)?;
+                if !matches!(terminator, Some(Terminator::End)) {
+                    return Err(format!("Repeat at event {} has no End", number));
+                }
+                // An empty forever loop would never stop to check for the stop key
+                if body.is_empty() {
+                    return Err(format!("Repeat at event {} has nothing to repeat", number));
+                }
+                NodeKind::Repeat {
+                    count: *count,
+                    body,
+                }
+            }
+            SerializableEventType::If { condition } => {
+                let (then, terminator) = parse_block(events, position)?;
+                if then.is_empty() {
+                    return Err(format!("If at event {} has nothing to do", number));
+                }
+                let otherwise = match terminator {
+                    Some(Terminator::End) => Vec::new(),
+                    Some(Terminator::Else) => match parse_block(events, position)? {
+                        (otherwise, Some(Terminator::End)) if otherwise.is_empty() => {
+                            return Err(format!("Else of the If at event {} is empty", number))
+                        }
+                        (otherwise, Some(Terminator::End)) => otherwise,
+                        _ => return Err(format!("If at event {} has no End", number)),
+                    },
+                    None => return Err(format!("If at event {} has no End", number)),
+                };
+                NodeKind::If {
+                    condition: condition.clone(),
+                    then,
+                    otherwise,
+                }
+            }
+            SerializableEventType::Label { name } => NodeKind::Label(name.clone()),
+            SerializableEventType::Jump { label } => NodeKind::Jump(label.clone()),
+            SerializableEventType::Exit { code } => NodeKind::Exit(*code),
+            event_type => NodeKind::Event(event_type.clone()),
+        };
+        nodes.push(Node {
+            time: event.time,
+            kind,
+        });
+    }
+
+    Ok((nodes, None))
+}
+
+fn children(node: &Node) -> Vec<&[Node]> {
+    match &node.kind {
+        NodeKind::Repeat { body, .. } => vec![body],
+        NodeKind::If {
+            then, otherwise, ..
+        } => vec![then, otherwise],
+        _ => Vec::new(),
+    }
+}
+
+fn collect_labels<'a>(block: &'a [Node], labels: &mut Vec<&'a str>) -> Result<(), String> {
+    for node in block {
+        if let NodeKind::Label(name) = &node.kind {
+            if labels.contains(&name.as_str()) {
+                return Err(format!("Label \"{}\" is used more than once", name));
+            }
+            labels.push(name);
+        }
+        for child in children(node) {
+            collect_labels(child, labels)?;
+        }
+    }
+    Ok(())
+}
+
+// `scopes` holds the labels of every block around the current one
+fn check_jumps<'a>(block: &'a [Node], scopes: &mut Vec<&'a str>) -> Result<(), String> {
+    let outer = scopes.len();
+    scopes.extend(block.iter().filter_map(|node| match &node.kind {
+        NodeKind::Label(name) => Some(name.as_str()),
+        _ => None,
+    }));
+
+    for node in block {
+        if let NodeKind::Jump(label) = &node.kind {
+            if !scopes.contains(&label.as_str()) {
+                return Err(format!(
+                    "Jump to \"{}\" has no label in the same block or one around it",
+                    label
+                ));
+            }
+        }
+        for child in children(node) {
+            check_jumps(child, scopes)?;
+        }
+    }
+
+    scopes.truncate(outer);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serializable_event::SerializableKey;
+
+    fn events(types: Vec<SerializableEventType>) -> Vec<SerializableEvent> {
+        types
+            .into_iter()
+            .map(|event_type| SerializableEvent {
+                time: SystemTime::UNIX_EPOCH,
+                event_type,
+            })
+            .collect()
+    }
+
+    fn key() -> SerializableEventType {
+        SerializableEventType::KeyPress(SerializableKey::KeyA)
+    }
+
+    #[test]
+    fn nests_blocks() {
+        let block = parse(&events(vec![
+            key(),
+            SerializableEventType::Repeat { count: Some(2) },
+            key(),
+            SerializableEventType::If {
+                condition: Condition::Variable {
+                    name: "a".into(),
+                    value: "1".into(),
+                },
+            },
+            key(),
+            SerializableEventType::Else,
+            key(),
+            SerializableEventType::End,
+            SerializableEventType::End,
+        ]))
+        .unwrap();
+
+        assert_eq!(block.len(), 2);
+        let NodeKind::Repeat { count, body } = &block[1].kind else {
+            panic!("expected a Repeat, got {:?}", block[1].kind);
+        };
+        assert_eq!(*count, Some(2));
+        assert!(matches!(
+            &body[1].kind,
+            NodeKind::If { then, otherwise, .. } if then.len() == 1 && otherwise.len() == 1
+        ));
+    }
+
+    #[test]
+    fn rejects_unclosed_and_stray_markers() {
+        let unclosed = events(vec![SerializableEventType::Repeat { count: None }, key()]);
+        assert!(parse(&unclosed).unwrap_err().contains("has no End"));
+
+        let stray = events(vec![key(), SerializableEventType::End]);
+        assert!(parse(&stray).unwrap_err().contains("doesn't belong"));
+    }
+
+    #[test]
+    fn rejects_empty_blocks() {
+        let repeat = events(vec![
+            SerializableEventType::Repeat { count: None },
+            SerializableEventType::End,
+        ]);
+        assert!(parse(&repeat).unwrap_err().contains("nothing to repeat"));
+
+        let condition = Condition::Variable {
+            name: "a".into(),
+            value: "1".into(),
+        };
+        let then = events(vec![
+            SerializableEventType::If {
+                condition: condition.clone(),
+            },
+            SerializableEventType::End,
+        ]);
+        assert!(parse(&then).unwrap_err().contains("nothing to do"));
+
+        let otherwise = events(vec![
+            SerializableEventType::If { condition },
+            key(),
+            SerializableEventType::Else,
+            SerializableEventType::End,
+        ]);
+        assert!(parse(&otherwise).unwrap_err().contains("is empty"));
+    }
+
+    #[test]
+    fn checks_labels_and_jumps() {
+        let duplicate = events(vec![
+            SerializableEventType::Label { name: "a".into() },
+            SerializableEventType::Label { name: "a".into() },
+        ]);
+        assert!(parse(&duplicate).unwrap_err().contains("more than once"));
+
+        // A jump can leave a block but not go into one
+        let inward = events(vec![
+            SerializableEventType::Jump { label: "in".into() },
+            SerializableEventType::Repeat { count: Some(1) },
+            SerializableEventType::Label { name: "in".into() },
+            SerializableEventType::End,
+        ]);
+        assert!(parse(&inward).unwrap_err().contains("has no label"));
+
+        let outward = events(vec![
+            SerializableEventType::Label { name: "out".into() },
+            SerializableEventType::Repeat { count: Some(1) },
+            SerializableEventType::Jump {
+                label: "out".into(),
+            },
+            SerializableEventType::End,
+        ]);
+        assert!(parse(&outward).is_ok());
+    }
+
+    #[test]
+    fn conditions_parse_as_they_display() {
+        let conditions = [
+            Condition::Variable {
+                name: "row".into(),
+                value: "two words".into(),
+            },
+            Condition::Variable {
+                name: "empty".into(),
+                value: String::new(),
+            },
+            Condition::Pixel {
+                x: 10,
+                y: -20,
+                color: [255, 128, 0],
+                tolerance: 5,
+            },
+            Condition::Window {
+                matcher: WindowMatcher {
+                    title: Some("Save As".into()),
+                    class: None,
+                },
+                condition: WindowCondition::Focused,
+            },
+            Condition::ExitCode {
+                program: "test".into(),
+                args: vec!["-f".into(), "done.txt".into()],
+                code: 0,
+            },
+            Condition::Not(Box::new(Condition::Variable {
+                name: "not".into(),
+                value: "x".into(),
+            })),
+        ];
+        for condition in conditions {
+            assert_eq!(condition.to_string().parse(), Ok(condition));
+        }
+
+        assert_eq!(
+            "pixel 1,2 00FF00".parse(),
+            Ok(Condition::Pixel {
+                x: 1,
+                y: 2,
+                color: [0, 255, 0],
+                tolerance: 0,
+            })
+        );
+        assert_eq!(
+            "pixel = on".parse(),
+            Ok(Condition::Variable {
+                name: "pixel".into(),
+                value: "on".into(),
+            })
+        );
+        for bad in [
+            "",
+            "row",
+            "two words = 1",
+            "pixel 1 2 #000000",
+            "pixel 1,2 #000000 5",
+            "window shown title \"a\"",
+            "run exits 0",
+            "run test exits zero",
+            "not",
+        ] {
+            assert!(bad.parse::<Condition>().is_err(), "{}", bad);
+        }
+    }
+}
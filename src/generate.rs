@@ -0,0 +1,668 @@
+use crate::random::{time_seed, Random};
+use crate::script::{MouseAnchor, Script};
+use crate::serializable_event::{SerializableButton, SerializableEvent, SerializableEventType};
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+// How long the button stays down for each click of a single or double click
+const CLICK_LENGTH: Duration = Duration::from_millis(30);
+// Gap between the two clicks of a double click
+const DOUBLE_CLICK_GAP: Duration = Duration::from_millis(80);
+// Clicks in the loop of an auto-clicker that runs until stopped, so any jitter
+// doesn't repeat with every click
+const JITTER_BLOCK: u32 = 100;
+
+// Buttons offered for generated clicks
+pub const CLICK_BUTTONS: [SerializableButton; 3] = [
+    SerializableButton::Left,
+    SerializableButton::Right,
+    SerializableButton::Middle,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClickPosition {
+    // Wherever the cursor is when the script is played
+    Cursor,
+    At { x: f64, y: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClickKind {
+    #[default]
+    Single,
+    Double,
+    // Press and keep the button down for the auto-clicker's `hold`
+    Hold,
+}
+
+impl ClickKind {
+    pub const ALL: [ClickKind; 3] = [ClickKind::Single, ClickKind::Double, ClickKind::Hold];
+
+    // Time from pressing the button to letting go for the last time
+    fn length(self, hold: Duration) -> Duration {
+        match self {
+            ClickKind::Single => CLICK_LENGTH,
+            ClickKind::Double => CLICK_LENGTH * 2 + DOUBLE_CLICK_GAP,
+            ClickKind::Hold => hold,
+        }
+    }
+
+    // Button presses and releases of one click, from when it starts
+    fn events(
+        self,
+        button: SerializableButton,
+        hold: Duration,
+    ) -> Vec<(Duration, SerializableEventType)> {
+        let press = SerializableEventType::ButtonPress(button);
+        let release = SerializableEventType::ButtonRelease(button);
+        match self {
+            ClickKind::Single => vec![(Duration::ZERO, press), (CLICK_LENGTH, release)],
+            ClickKind::Double => vec![
+                (Duration::ZERO, press.clone()),
+                (CLICK_LENGTH, release.clone()),
+                (CLICK_LENGTH + DOUBLE_CLICK_GAP, press),
+                (CLICK_LENGTH * 2 + DOUBLE_CLICK_GAP, release),
+            ],
+            ClickKind::Hold => vec![(Duration::ZERO, press), (hold, release)],
+        }
+    }
+}
+
+impl std::fmt::Display for ClickKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ClickKind::Single => "Single",
+            ClickKind::Double => "Double",
+            ClickKind::Hold => "Hold",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ClickKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "single" => Ok(ClickKind::Single),
+            "double" => Ok(ClickKind::Double),
+            "hold" => Ok(ClickKind::Hold),
+            _ => Err(format!(
+                "Unknown click \"{}\" (expected single, double or hold)",
+                s
+            )),
+        }
+    }
+}
+
+// Left, right or middle, the only buttons every platform can click
+pub fn parse_button(s: &str) -> Result<SerializableButton, String> {
+    CLICK_BUTTONS
+        .into_iter()
+        .find(|button| button.to_string().eq_ignore_ascii_case(s))
+        .ok_or_else(|| format!("Unknown button \"{}\" (expected left, right or middle)", s))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopAfter {
+    Clicks(u32),
+    // Clicks that start within this long of the first one
+    Time(Duration),
+    // Loop until playback is stopped
+    Stopped,
+}
+
+// Builds a script that clicks over and over, instead of recording every click
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoClicker {
+    pub position: ClickPosition,
+    pub button: SerializableButton,
+    pub kind: ClickKind,
+    // How long a Hold click keeps the button down
+    pub hold: Duration,
+    // From the start of one click to the start of the next
+    pub interval: Duration,
+    // Most a click may come early or late
+    pub jitter_time: Duration,
+    // Most a click may land from the position, in pixels along each axis
+    pub jitter_distance: f64,
+    pub stop: StopAfter,
+    pub seed: u64,
+}
+
+impl Default for AutoClicker {
+    fn default() -> Self {
+        AutoClicker {
+            position: ClickPosition::Cursor,
+            button: SerializableButton::Left,
+            kind: ClickKind::Single,
+            hold: Duration::from_millis(500),
+            interval: Duration::from_millis(100),
+            jitter_time: Duration::ZERO,
+            jitter_distance: 0.0,
+            stop: StopAfter::Clicks(10),
+            seed: time_seed(),
+        }
+    }
+}
+
+impl AutoClicker {
+    // Every click has to finish before the next one can start, however the
+    // jitter falls
+    pub fn validate(&self) -> Result<(), String> {
+        let length = self.kind.length(self.hold);
+        if length + self.jitter_time * 2 >= self.interval {
+            return Err(format!(
+                "{}ms clicks with {}ms of jitter don't fit in {}ms",
+                length.as_millis(),
+                self.jitter_time.as_millis(),
+                self.interval.as_millis()
+            ));
+        }
+        if !(self.jitter_distance.is_finite() && self.jitter_distance >= 0.0) {
+            return Err(format!(
+                "Position jitter {} must be 0 or more",
+                self.jitter_distance
+            ));
+        }
+        if let ClickPosition::At { x, y } = self.position {
+            if !(x.is_finite() && y.is_finite()) {
+                return Err(format!("{}, {} is not a position", x, y));
+            }
+        }
+        match self.stop {
+            StopAfter::Clicks(0) => Err("Needs at least one click".to_string()),
+            StopAfter::Time(time) if time.is_zero() => {
+                Err("Needs to click for longer than 0ms".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The clicks as script events. Moves are only added when the position is
+    // fixed or jittered, jittered clicks at the cursor need MouseAnchor::Cursor.
+    pub fn events(&self) -> Result<Vec<SerializableEvent>, String> {
+        self.validate()?;
+
+        let start = SystemTime::now();
+        let mut random = Random::new(self.seed);
+        let (count, forever) = match self.stop {
+            StopAfter::Clicks(count) => (count, false),
+            StopAfter::Time(time) => {
+                let count = (time.as_secs_f64() / self.interval.as_secs_f64()).ceil();
+                (count.min(u32::MAX as f64) as u32, false)
+            }
+            StopAfter::Stopped if self.is_jittered() => (JITTER_BLOCK, true),
+            StopAfter::Stopped => (1, true),
+        };
+        let (x, y, moves) = match self.position {
+            ClickPosition::Cursor => (0.0, 0.0, self.jitter_distance > 0.0),
+            ClickPosition::At { x, y } => (x, y, true),
+        };
+
+        let mut events = Vec::new();
+        let mut push = |time: Duration, event_type| {
+            events.push(SerializableEvent {
+                time: start + time,
+                event_type,
+            })
+        };
+        // Jittered moves from the cursor are offsets from this first one
+        if moves && self.position == ClickPosition::Cursor {
+            push(Duration::ZERO, SerializableEventType::MouseMove { x, y });
+        }
+        if forever {
+            push(
+                Duration::ZERO,
+                SerializableEventType::Repeat { count: None },
+            );
+        }
+
+        let mut end = Duration::ZERO;
+        for i in 0..count {
+            // In a loop the wait comes first, so the loop can start straight over
+            let slot = self.interval * if forever { i + 1 } else { i };
+            let jitter = random.spread(self.jitter_time.as_secs_f64());
+            let time = Duration::from_secs_f64((slot.as_secs_f64() + jitter).max(0.0));
+
+            if moves {
+                let dx = random.spread(self.jitter_distance);
+                let dy = random.spread(self.jitter_distance);
+                push(
+                    time,
+                    SerializableEventType::MouseMove {
+                        x: x + dx,
+                        y: y + dy,
+                    },
+                );
+            }
+            for (offset, event_type) in self.kind.events(self.button, self.hold) {
+                push(time + offset, event_type);
+            }
+            end = time + self.kind.length(self.hold);
+        }
+
+        // Back to the start, so jittered moves don't wander off over many loops
+        if self.jitter_distance > 0.0 {
+            push(end, SerializableEventType::MouseMove { x, y });
+        }
+        if forever {
+            push(end, SerializableEventType::End);
+        }
+        Ok(events)
+    }
+
+    // A script of the clicks, ready to be played or saved
+    pub fn script(&self) -> Result<Script, String> {
+        let mut script = Script::new(self.events()?);
+        if self.position == ClickPosition::Cursor {
+            script.mouse_anchor = MouseAnchor::Cursor;
+        }
+        Ok(script)
+    }
+
+    fn is_jittered(&self) -> bool {
+        !self.jitter_time.is_zero() || self.jitter_distance > 0.0
+    }
+}
+
+// The order a grid's cells are visited in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GridOrder {
+    // Left to right along each row, top row first
+    #[default]
+    Rows,
+    // Top to bottom down each column, left column first
+    Columns,
+    // Along the rows, turning back at the end of each one
+    SnakeRows,
+    // Down the columns, turning back at the end of each one
+    SnakeColumns,
+}
+
+impl GridOrder {
+    pub const ALL: [GridOrder; 4] = [
+        GridOrder::Rows,
+        GridOrder::Columns,
+        GridOrder::SnakeRows,
+        GridOrder::SnakeColumns,
+    ];
+
+    // Row and column of every cell, in visiting order
+    fn cells(self, rows: u32, columns: u32) -> Vec<(u32, u32)> {
+        let (outer, inner) = match self {
+            GridOrder::Rows | GridOrder::SnakeRows => (rows, columns),
+            GridOrder::Columns | GridOrder::SnakeColumns => (columns, rows),
+        };
+        let snake = matches!(self, GridOrder::SnakeRows | GridOrder::SnakeColumns);
+
+        let mut cells = Vec::new();
+        for i in 0..outer {
+            for j in 0..inner {
+                let j = if snake && i % 2 == 1 {
+                    inner - 1 - j
+                } else {
+                    j
+                };
+                cells.push(match self {
+                    GridOrder::Rows | GridOrder::SnakeRows => (i, j),
+                    GridOrder::Columns | GridOrder::SnakeColumns => (j, i),
+                });
+            }
+        }
+        cells
+    }
+}
+
+impl std::fmt::Display for GridOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            GridOrder::Rows => "Rows",
+            GridOrder::Columns => "Columns",
+            GridOrder::SnakeRows => "Snake Rows",
+            GridOrder::SnakeColumns => "Snake Columns",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for GridOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rows" => Ok(GridOrder::Rows),
+            "columns" => Ok(GridOrder::Columns),
+            "snake-rows" | "snake" => Ok(GridOrder::SnakeRows),
+            "snake-columns" => Ok(GridOrder::SnakeColumns),
+            _ => Err(format!(
+                "Unknown order \"{}\" (expected rows, columns, snake-rows or snake-columns)",
+                s
+            )),
+        }
+    }
+}
+
+// What happens at each cell of a grid once the cursor is on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellAction {
+    // Only hover over it
+    Move,
+    Click(ClickKind),
+}
+
+// Builds a script that moves to every cell of a grid, like a spreadsheet or a
+// board, and clicks each one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridPattern {
+    // Centre of the first cell, Cursor is wherever the cursor is when played
+    pub origin: ClickPosition,
+    pub rows: u32,
+    pub columns: u32,
+    // Distance between the centres of neighbouring cells, negative goes up or
+    // left from the origin
+    pub spacing_x: f64,
+    pub spacing_y: f64,
+    pub order: GridOrder,
+    pub action: CellAction,
+    pub button: SerializableButton,
+    // How long a Hold click keeps the button down
+    pub hold: Duration,
+    // From arriving at one cell to arriving at the next
+    pub interval: Duration,
+}
+
+impl Default for GridPattern {
+    fn default() -> Self {
+        GridPattern {
+            origin: ClickPosition::Cursor,
+            rows: 3,
+            columns: 3,
+            spacing_x: 50.0,
+            spacing_y: 50.0,
+            order: GridOrder::Rows,
+            action: CellAction::Click(ClickKind::Single),
+            button: SerializableButton::Left,
+            hold: Duration::from_millis(500),
+            interval: Duration::from_millis(200),
+        }
+    }
+}
+
+impl GridPattern {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rows == 0 || self.columns == 0 {
+            return Err(format!(
+                "A {}×{} grid has no cells",
+                self.rows, self.columns
+            ));
+        }
+        if !(self.spacing_x.is_finite() && self.spacing_y.is_finite()) {
+            return Err(format!(
+                "{}, {} is not a spacing",
+                self.spacing_x, self.spacing_y
+            ));
+        }
+        if let ClickPosition::At { x, y } = self.origin {
+            if !(x.is_finite() && y.is_finite()) {
+                return Err(format!("{}, {} is not a position", x, y));
+            }
+        }
+        if let CellAction::Click(kind) = self.action {
+            let length = kind.length(self.hold);
+            if length >= self.interval {
+                return Err(format!(
+                    "{}ms clicks don't fit in {}ms per cell",
+                    length.as_millis(),
+                    self.interval.as_millis()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // A move to each cell followed by its action, the first cell's move comes
+    // first so MouseAnchor::Cursor places the grid at the cursor
+    pub fn events(&self) -> Result<Vec<SerializableEvent>, String> {
+        self.validate()?;
+
+        let start = SystemTime::now();
+        let (x, y) = match self.origin {
+            ClickPosition::Cursor => (0.0, 0.0),
+            ClickPosition::At { x, y } => (x, y),
+        };
+
+        let mut events = Vec::new();
+        for (i, (row, column)) in self
+            .order
+            .cells(self.rows, self.columns)
+            .into_iter()
+            .enumerate()
+        {
+            let time = start + self.interval * i as u32;
+            events.push(SerializableEvent {
+                time,
+                event_type: SerializableEventType::MouseMove {
+                    x: x + column as f64 * self.spacing_x,
+                    y: y + row as f64 * self.spacing_y,
+                },
+            });
+            if let CellAction::Click(kind) = self.action {
+                for (offset, event_type) in kind.events(self.button, self.hold) {
+                    events.push(SerializableEvent {
+                        time: time + offset,
+                        event_type,
+                    });
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    // A script of the grid, ready to be played or saved
+    pub fn script(&self) -> Result<Script, String> {
+        let mut script = Script::new(self.events()?);
+        if self.origin == ClickPosition::Cursor {
+            script.mouse_anchor = MouseAnchor::Cursor;
+        }
+        Ok(script)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(events: &[SerializableEvent]) -> Vec<&'static str> {
+        events
+            .iter()
+            .map(|event| match event.event_type {
+                SerializableEventType::MouseMove { .. } => "move",
+                SerializableEventType::ButtonPress(_) => "press",
+                SerializableEventType::ButtonRelease(_) => "release",
+                SerializableEventType::Repeat { .. } => "repeat",
+                SerializableEventType::End => "end",
+                _ => "other",
+            })
+            .collect()
+    }
+
+    fn offsets(events: &[SerializableEvent]) -> Vec<u128> {
+        events
+            .iter()
+            .map(|event| {
+                event
+                    .time
+                    .duration_since(events[0].time)
+                    .unwrap()
+                    .as_millis()
+            })
+            .collect()
+    }
+
+    fn clicker() -> AutoClicker {
+        AutoClicker {
+            stop: StopAfter::Clicks(3),
+            seed: 42,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn clicks_at_the_cursor() {
+        let events = clicker().events().unwrap();
+        assert_eq!(
+            kinds(&events),
+            ["press", "release", "press", "release", "press", "release"]
+        );
+        assert_eq!(offsets(&events), [0, 30, 100, 130, 200, 230]);
+        assert_eq!(
+            clicker().script().unwrap().mouse_anchor,
+            MouseAnchor::Cursor
+        );
+    }
+
+    #[test]
+    fn double_clicks_at_a_position() {
+        let events = AutoClicker {
+            position: ClickPosition::At { x: 10.0, y: 20.0 },
+            kind: ClickKind::Double,
+            interval: Duration::from_millis(500),
+            stop: StopAfter::Time(Duration::from_millis(900)),
+            ..clicker()
+        }
+        .events()
+        .unwrap();
+        assert_eq!(
+            kinds(&events),
+            [
+                "move", "press", "release", "press", "release", "move", "press", "release",
+                "press", "release"
+            ]
+        );
+        assert!(matches!(
+            events[0].event_type,
+            SerializableEventType::MouseMove { x, y } if x == 10.0 && y == 20.0
+        ));
+    }
+
+    #[test]
+    fn loops_until_stopped() {
+        let events = AutoClicker {
+            stop: StopAfter::Stopped,
+            ..clicker()
+        }
+        .events()
+        .unwrap();
+        assert_eq!(kinds(&events), ["repeat", "press", "release", "end"]);
+        // The wait comes first so the loop can start straight over
+        assert_eq!(offsets(&events), [0, 100, 130, 130]);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds_and_follows_the_seed() {
+        let jittered = AutoClicker {
+            position: ClickPosition::At { x: 100.0, y: 100.0 },
+            jitter_time: Duration::from_millis(20),
+            jitter_distance: 5.0,
+            stop: StopAfter::Clicks(50),
+            ..clicker()
+        };
+        let events = jittered.events().unwrap();
+        for event in &events {
+            if let SerializableEventType::MouseMove { x, y } = event.event_type {
+                assert!((x - 100.0).abs() <= 5.0 && (y - 100.0).abs() <= 5.0);
+            }
+        }
+        for (i, press) in events
+            .iter()
+            .filter(|event| matches!(event.event_type, SerializableEventType::ButtonPress(_)))
+            .enumerate()
+        {
+            let offset = press.time.duration_since(events[0].time).unwrap();
+            let slot = Duration::from_millis(100) * i as u32;
+            assert!(offset.abs_diff(slot) <= Duration::from_millis(40));
+        }
+
+        let again = jittered.events().unwrap();
+        assert_eq!(offsets(&events), offsets(&again));
+    }
+
+    #[test]
+    fn rejects_clicks_that_do_not_fit() {
+        let too_fast = AutoClicker {
+            interval: Duration::from_millis(30),
+            ..clicker()
+        };
+        assert!(too_fast.events().is_err());
+
+        let too_jittery = AutoClicker {
+            jitter_time: Duration::from_millis(40),
+            ..clicker()
+        };
+        assert!(too_jittery.validate().is_err());
+
+        let no_clicks = AutoClicker {
+            stop: StopAfter::Clicks(0),
+            ..clicker()
+        };
+        assert!(no_clicks.validate().is_err());
+    }
+
+    #[test]
+    fn grid_orders_visit_every_cell() {
+        assert_eq!(
+            GridOrder::Rows.cells(2, 3),
+            [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2)]
+        );
+        assert_eq!(
+            GridOrder::Columns.cells(2, 3),
+            [(0, 0), (1, 0), (0, 1), (1, 1), (0, 2), (1, 2)]
+        );
+        assert_eq!(
+            GridOrder::SnakeRows.cells(2, 3),
+            [(0, 0), (0, 1), (0, 2), (1, 2), (1, 1), (1, 0)]
+        );
+        assert_eq!(
+            GridOrder::SnakeColumns.cells(2, 3),
+            [(0, 0), (1, 0), (1, 1), (0, 1), (0, 2), (1, 2)]
+        );
+        assert_eq!("snake".parse::<GridOrder>(), Ok(GridOrder::SnakeRows));
+        assert!("diagonal".parse::<GridOrder>().is_err());
+    }
+
+    #[test]
+    fn grid_moves_to_each_cell_then_clicks() {
+        let grid = GridPattern {
+            origin: ClickPosition::At { x: 100.0, y: 50.0 },
+            rows: 2,
+            columns: 2,
+            spacing_x: 30.0,
+            spacing_y: -10.0,
+            ..Default::default()
+        };
+        let events = grid.events().unwrap();
+        assert_eq!(kinds(&events).len(), 12);
+        let moves: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event.event_type {
+                SerializableEventType::MouseMove { x, y } => Some((x, y)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            moves,
+            [(100.0, 50.0), (130.0, 50.0), (100.0, 40.0), (130.0, 40.0)]
+        );
+        assert_eq!(grid.script().unwrap().mouse_anchor, MouseAnchor::Absolute);
+
+        let hover = GridPattern {
+            action: CellAction::Move,
+            ..grid
+        };
+        assert_eq!(hover.events().unwrap().len(), 4);
+        assert!(GridPattern { rows: 0, ..grid }.validate().is_err());
+    }
+}
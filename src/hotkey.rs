@@ -0,0 +1,608 @@
+use crate::calls::resolve_call;
+use crate::config::{config_dir, load_config};
+use crate::serializable_event::{SerializableButton, SerializableEventType, SerializableKey};
+use crate::settings::ControlKeys;
+use serde::de::{value, IntoDeserializer};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+// Hotkeys are kept in hotkeys.toml in the config directory, for example:
+//
+//   while_running = "queue"
+//
+//   [[hotkeys]]
+//   chord = "Ctrl+Alt+KeyH"
+//   run = "hello.bin"
+//
+//   [[hotkeys]]
+//   chord = "Mouse8"
+//   run = "reports.toml"
+const HOTKEYS_FILE: &str = "hotkeys.toml";
+
+// The key or mouse button that completes a chord
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChordKey {
+    Key(SerializableKey),
+    Button(SerializableButton),
+}
+
+impl ChordKey {
+    // The key or button an event presses (true) or releases (false)
+    pub fn of(event_type: &SerializableEventType) -> Option<(ChordKey, bool)> {
+        match event_type {
+            SerializableEventType::KeyPress(key) => Some((ChordKey::Key(*key), true)),
+            SerializableEventType::KeyRelease(key) => Some((ChordKey::Key(*key), false)),
+            SerializableEventType::ButtonPress(button) => Some((ChordKey::Button(*button), true)),
+            SerializableEventType::ButtonRelease(button) => {
+                Some((ChordKey::Button(*button), false))
+            }
+            _ => None,
+        }
+    }
+}
+
+// A key or button pressed while holding exactly these modifiers. Left and right
+// modifiers are the same, AltGr isn't Alt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Chord {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub meta: bool,
+    pub key: ChordKey,
+}
+
+impl Chord {
+    pub fn key(key: ChordKey) -> Self {
+        Chord {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            meta: false,
+            key,
+        }
+    }
+
+    // Events that press the chord, modifiers first
+    pub fn press(&self) -> Vec<SerializableEventType> {
+        let mut events: Vec<SerializableEventType> = self
+            .modifier_keys()
+            .into_iter()
+            .map(SerializableEventType::KeyPress)
+            .collect();
+        events.push(match self.key {
+            ChordKey::Key(key) => SerializableEventType::KeyPress(key),
+            ChordKey::Button(button) => SerializableEventType::ButtonPress(button),
+        });
+        events
+    }
+
+    // Events that let go of the chord, in the opposite order to `press`
+    pub fn release(&self) -> Vec<SerializableEventType> {
+        let mut events = vec![match self.key {
+            ChordKey::Key(key) => SerializableEventType::KeyRelease(key),
+            ChordKey::Button(button) => SerializableEventType::ButtonRelease(button),
+        }];
+        events.extend(
+            self.modifier_keys()
+                .into_iter()
+                .rev()
+                .map(SerializableEventType::KeyRelease),
+        );
+        events
+    }
+
+    // A modifier on its own, which only makes sense as a remap target
+    pub fn is_modifier(&self) -> bool {
+        matches!(self.key, ChordKey::Key(key) if modifier(key).is_some())
+    }
+
+    // Whether holding `key` is part of the chord
+    pub fn uses_modifier(&self, key: SerializableKey) -> bool {
+        match modifier(key) {
+            Some(Modifier::Ctrl) => self.ctrl,
+            Some(Modifier::Alt) => self.alt,
+            Some(Modifier::Shift) => self.shift,
+            Some(Modifier::Meta) => self.meta,
+            None => false,
+        }
+    }
+
+    fn modifier_keys(&self) -> Vec<SerializableKey> {
+        [
+            (self.ctrl, SerializableKey::ControlLeft),
+            (self.alt, SerializableKey::Alt),
+            (self.shift, SerializableKey::ShiftLeft),
+            (self.meta, SerializableKey::MetaLeft),
+        ]
+        .into_iter()
+        .filter_map(|(held, key)| held.then_some(key))
+        .collect()
+    }
+}
+
+// Chords are written as modifiers and a key joined by +, like "Ctrl+Shift+F5".
+// Keys are SerializableKey names ("KeyA", "Return"), or a single letter or digit.
+// Buttons are MouseLeft, MouseRight, MouseMiddle or Mouse and a button number.
+// A modifier key like "ControlLeft" can stand on its own, but can't end a chord.
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let last = parts.pop().filter(|last| !last.is_empty());
+        let key = last.ok_or_else(|| format!("\"{}\" has no key", text))?;
+
+        let key = parse_chord_key(key)?;
+        if let ChordKey::Key(key) = key {
+            if modifier(key).is_some() && !parts.is_empty() {
+                return Err(format!(
+                    "\"{}\" ends with a modifier instead of a key",
+                    text
+                ));
+            }
+        }
+
+        let mut chord = Chord::key(key);
+        for part in parts {
+            let flag = match part.to_lowercase().as_str() {
+                "ctrl" | "control" => &mut chord.ctrl,
+                "alt" | "option" => &mut chord.alt,
+                "shift" => &mut chord.shift,
+                "meta" | "super" | "win" | "cmd" => &mut chord.meta,
+                _ => return Err(format!("\"{}\" is not a modifier in \"{}\"", part, text)),
+            };
+            *flag = true;
+        }
+        Ok(chord)
+    }
+}
+
+fn parse_chord_key(name: &str) -> Result<ChordKey, String> {
+    let button = match name.to_lowercase().as_str() {
+        "mouseleft" => Some(SerializableButton::Left),
+        "mouseright" => Some(SerializableButton::Right),
+        "mousemiddle" => Some(SerializableButton::Middle),
+        lower => lower
+            .strip_prefix("mouse")
+            .and_then(|number| number.parse().ok())
+            .map(SerializableButton::Unknown),
+    };
+    if let Some(button) = button {
+        return Ok(ChordKey::Button(button));
+    }
+
+    parse_key(name).map(ChordKey::Key)
+}
+
+// A SerializableKey by name, or a single letter or digit
+pub fn parse_key(name: &str) -> Result<SerializableKey, String> {
+    let mut chars = name.chars();
+    let expanded = match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => format!("Key{}", c.to_ascii_uppercase()),
+        (Some(c), None) if c.is_ascii_digit() => format!("Num{}", c),
+        _ => name.to_string(),
+    };
+    let deserializer: value::StrDeserializer<value::Error> = expanded.as_str().into_deserializer();
+    SerializableKey::deserialize(deserializer).map_err(|_| format!("Unknown key \"{}\"", name))
+}
+
+impl TryFrom<String> for Chord {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Chord> for String {
+    fn from(chord: Chord) -> Self {
+        chord.to_string()
+    }
+}
+
+impl std::fmt::Display for Chord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (held, name) in [
+            (self.ctrl, "Ctrl"),
+            (self.alt, "Alt"),
+            (self.shift, "Shift"),
+            (self.meta, "Meta"),
+        ] {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match self.key {
+            ChordKey::Key(key) => write!(f, "{:?}", key),
+            ChordKey::Button(SerializableButton::Left) => write!(f, "MouseLeft"),
+            ChordKey::Button(SerializableButton::Right) => write!(f, "MouseRight"),
+            ChordKey::Button(SerializableButton::Middle) => write!(f, "MouseMiddle"),
+            ChordKey::Button(SerializableButton::Unknown(number)) => write!(f, "Mouse{}", number),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Modifier {
+    Ctrl,
+    Alt,
+    Shift,
+    Meta,
+}
+
+fn modifier(key: SerializableKey) -> Option<Modifier> {
+    match key {
+        SerializableKey::ControlLeft | SerializableKey::ControlRight => Some(Modifier::Ctrl),
+        SerializableKey::Alt => Some(Modifier::Alt),
+        SerializableKey::ShiftLeft | SerializableKey::ShiftRight => Some(Modifier::Shift),
+        SerializableKey::MetaLeft | SerializableKey::MetaRight => Some(Modifier::Meta),
+        _ => None,
+    }
+}
+
+// Follows which keys are held from the global input events to spot chords
+#[derive(Debug, Clone, Default)]
+pub struct ChordTracker {
+    held: Vec<SerializableKey>,
+}
+
+impl ChordTracker {
+    pub fn new() -> Self {
+        ChordTracker::default()
+    }
+
+    // Keys held down right now, modifiers included
+    pub fn held(&self) -> &[SerializableKey] {
+        &self.held
+    }
+
+    // Feed every input event through here. Returns the chord a key or button
+    // press completes, ignoring the repeats of a key that's held down.
+    pub fn update(&mut self, event_type: &SerializableEventType) -> Option<Chord> {
+        let key = match event_type {
+            SerializableEventType::KeyPress(key) => {
+                if self.held.contains(key) {
+                    return None;
+                }
+                self.held.push(*key);
+                if modifier(*key).is_some() {
+                    return None;
+                }
+                ChordKey::Key(*key)
+            }
+            SerializableEventType::KeyRelease(key) => {
+                self.held.retain(|held| held != key);
+                return None;
+            }
+            SerializableEventType::ButtonPress(button) => ChordKey::Button(*button),
+            _ => return None,
+        };
+
+        let held = |wanted| self.held.iter().any(|key| modifier(*key) == Some(wanted));
+        Some(Chord {
+            ctrl: held(Modifier::Ctrl),
+            alt: held(Modifier::Alt),
+            shift: held(Modifier::Shift),
+            meta: held(Modifier::Meta),
+            key,
+        })
+    }
+}
+
+// What a hotkey does while another macro is playing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhileRunning {
+    // Nothing, the press is only logged
+    #[default]
+    Ignore,
+    // Play it once the current one has finished
+    Queue,
+    // Stop the current one and play it instead
+    Replace,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hotkey {
+    pub chord: Chord,
+    // Script or playlist (.toml) to play, relative to the config directory
+    pub run: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Hotkeys {
+    #[serde(default)]
+    pub while_running: WhileRunning,
+    #[serde(default)]
+    pub hotkeys: Vec<Hotkey>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Hotkeys {
+    pub fn path() -> Result<PathBuf, String> {
+        Ok(config_dir()?.join(HOTKEYS_FILE))
+    }
+
+    // No hotkeys file yet is the same as an empty one
+    pub fn load(path: &Path, keys: &ControlKeys) -> Result<Hotkeys, String> {
+        let mut hotkeys: Hotkeys = load_config(path)?;
+        hotkeys.validate(keys)?;
+        hotkeys.path = Some(path.to_path_buf());
+        Ok(hotkeys)
+    }
+
+    // A chord can only be bound once, and not to the record, stop and run keys
+    // with any modifiers or to a modifier on its own, or it would never be seen
+    pub fn validate(&self, keys: &ControlKeys) -> Result<(), String> {
+        for (i, hotkey) in self.hotkeys.iter().enumerate() {
+            if hotkey.chord.is_modifier() {
+                return Err(format!(
+                    "{} for {} is only a modifier, it needs a key",
+                    hotkey.chord,
+                    hotkey.run.display()
+                ));
+            }
+            if let ChordKey::Key(key) = hotkey.chord.key {
+                if keys.contains(key) {
+                    return Err(format!(
+                        "{} for {} uses {:?}, which is taken by the record, stop and run keys",
+                        hotkey.chord,
+                        hotkey.run.display(),
+                        key
+                    ));
+                }
+            }
+            if let Some(other) = self.hotkeys[..i]
+                .iter()
+                .find(|other| other.chord == hotkey.chord)
+            {
+                return Err(format!(
+                    "{} is bound to both {} and {}",
+                    hotkey.chord,
+                    other.run.display(),
+                    hotkey.run.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn find(&self, chord: &Chord) -> Option<&Hotkey> {
+        self.hotkeys.iter().find(|hotkey| hotkey.chord == *chord)
+    }
+
+    // Where a hotkey's script or playlist is
+    pub fn script_path(&self, hotkey: &Hotkey) -> PathBuf {
+        resolve_call(self.path.as_deref(), &hotkey.run)
+    }
+}
+
+// What the listener should do about a hotkey press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    // Start playing, nothing was
+    Start,
+    // Stop playing, the hotkey of the macro that's playing was pressed again
+    Stop,
+    Ignore,
+    // Leave the current run alone, the macro is played after it
+    Queue,
+    // Stop the current run, the macro is played after it
+    Replace,
+}
+
+// Macros asked for by hotkeys, shared by the listener that sees the presses and
+// the thread that plays them
+#[derive(Debug, Clone, Default)]
+pub struct HotkeyQueue {
+    playing: Option<PathBuf>,
+    next: Option<PathBuf>,
+}
+
+impl HotkeyQueue {
+    pub fn trigger(&mut self, path: PathBuf, running: bool, policy: WhileRunning) -> HotkeyAction {
+        if !running {
+            self.next = Some(path);
+            return HotkeyAction::Start;
+        }
+        if self.playing.as_ref() == Some(&path) {
+            self.next = None;
+            return HotkeyAction::Stop;
+        }
+
+        match policy {
+            WhileRunning::Ignore => HotkeyAction::Ignore,
+            WhileRunning::Queue => {
+                self.next = Some(path);
+                HotkeyAction::Queue
+            }
+            WhileRunning::Replace => {
+                self.next = Some(path);
+                HotkeyAction::Replace
+            }
+        }
+    }
+
+    // The macro to play now, if a hotkey asked for one
+    pub fn start_next(&mut self) -> Option<PathBuf> {
+        self.playing = self.next.take();
+        self.playing.clone()
+    }
+
+    pub fn finished(&mut self) {
+        self.playing = None;
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.next.is_some()
+    }
+
+    // Forget a queued macro, for when playback is stopped by hand
+    pub fn clear(&mut self) {
+        self.next = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_chords() {
+        let chord: Chord = "Ctrl+Alt+KeyH".parse().unwrap();
+        assert!(chord.ctrl && chord.alt && !chord.shift && !chord.meta);
+        assert_eq!(chord.key, ChordKey::Key(SerializableKey::KeyH));
+
+        let chord: Chord = "shift + super + 5".parse().unwrap();
+        assert!(chord.shift && chord.meta);
+        assert_eq!(chord.key, ChordKey::Key(SerializableKey::Num5));
+
+        let chord: Chord = "Mouse8".parse().unwrap();
+        assert_eq!(
+            chord,
+            Chord::key(ChordKey::Button(SerializableButton::Unknown(8)))
+        );
+        assert_eq!(
+            "MouseMiddle".parse::<Chord>().unwrap().key,
+            ChordKey::Button(SerializableButton::Middle)
+        );
+
+        let chord: Chord = "ControlLeft".parse().unwrap();
+        assert!(chord.is_modifier() && !chord.ctrl);
+        assert!(!"Ctrl+KeyC".parse::<Chord>().unwrap().is_modifier());
+    }
+
+    #[test]
+    fn rejects_bad_chords() {
+        for text in [
+            "",
+            "Ctrl+",
+            "Ctrl+ShiftLeft",
+            "Shift+ShiftLeft",
+            "Hyper+KeyA",
+            "Ctrl+NoSuchKey",
+        ] {
+            assert!(text.parse::<Chord>().is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn chords_display_as_they_parse() {
+        for text in [
+            "Ctrl+Shift+F5",
+            "Alt+Meta+KeyA",
+            "Mouse4",
+            "MouseLeft",
+            "Return",
+        ] {
+            assert_eq!(text.parse::<Chord>().unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn chords_press_and_release_in_order() {
+        use SerializableEventType::{KeyPress, KeyRelease};
+        use SerializableKey::{ControlLeft, KeyA, ShiftLeft};
+
+        // Event types have no PartialEq, their Debug output is compared instead
+        let chord: Chord = "Ctrl+Shift+KeyA".parse().unwrap();
+        assert_eq!(
+            format!("{:?}", chord.press()),
+            format!(
+                "{:?}",
+                [KeyPress(ControlLeft), KeyPress(ShiftLeft), KeyPress(KeyA)]
+            )
+        );
+        assert_eq!(
+            format!("{:?}", chord.release()),
+            format!(
+                "{:?}",
+                [
+                    KeyRelease(KeyA),
+                    KeyRelease(ShiftLeft),
+                    KeyRelease(ControlLeft)
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn tracker_spots_chords_once_per_press() {
+        let mut tracker = ChordTracker::new();
+        let press = SerializableEventType::KeyPress;
+        let release = SerializableEventType::KeyRelease;
+
+        assert_eq!(tracker.update(&press(SerializableKey::ControlRight)), None);
+        let chord = tracker.update(&press(SerializableKey::KeyH));
+        assert_eq!(chord, Some("Ctrl+KeyH".parse().unwrap()));
+        // Held keys repeat their press
+        assert_eq!(tracker.update(&press(SerializableKey::KeyH)), None);
+
+        tracker.update(&release(SerializableKey::KeyH));
+        tracker.update(&release(SerializableKey::ControlRight));
+        let chord = tracker.update(&SerializableEventType::ButtonPress(
+            SerializableButton::Left,
+        ));
+        assert_eq!(chord, Some("MouseLeft".parse().unwrap()));
+    }
+
+    #[test]
+    fn chord_keys_come_from_presses_and_releases() {
+        let key = ChordKey::Key(SerializableKey::KeyH);
+        let button = ChordKey::Button(SerializableButton::Left);
+        assert_eq!(
+            ChordKey::of(&SerializableEventType::KeyPress(SerializableKey::KeyH)),
+            Some((key, true))
+        );
+        assert_eq!(
+            ChordKey::of(&SerializableEventType::KeyRelease(SerializableKey::KeyH)),
+            Some((key, false))
+        );
+        assert_eq!(
+            ChordKey::of(&SerializableEventType::ButtonRelease(
+                SerializableButton::Left
+            )),
+            Some((button, false))
+        );
+        assert_eq!(
+            ChordKey::of(&SerializableEventType::MouseMove { x: 1.0, y: 2.0 }),
+            None
+        );
+    }
+
+    #[test]
+    fn validate_rejects_shortcut_keys_and_duplicates() {
+        let hotkey = |chord: &str, run: &str| Hotkey {
+            chord: chord.parse().unwrap(),
+            run: PathBuf::from(run),
+        };
+        let mut hotkeys = Hotkeys {
+            hotkeys: vec![hotkey("Ctrl+KeyH", "a.bin"), hotkey("Alt+Dot", "b.bin")],
+            ..Default::default()
+        };
+        let keys = ControlKeys::default();
+        assert!(hotkeys.validate(&keys).is_err());
+
+        // Moving the stop key frees up the one it used
+        let moved = ControlKeys {
+            stop: SerializableKey::F10,
+            ..keys
+        };
+        assert!(hotkeys.validate(&moved).is_ok());
+
+        hotkeys.hotkeys.push(hotkey("ctrl+h", "c.bin"));
+        assert!(hotkeys.validate(&moved).is_err());
+
+        let modifier_only = Hotkeys {
+            hotkeys: vec![hotkey("ShiftLeft", "a.bin")],
+            ..Default::default()
+        };
+        assert!(modifier_only.validate(&keys).is_err());
+    }
+}
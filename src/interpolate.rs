@@ -0,0 +1,231 @@
+use crate::serializable_event::{SerializableEvent, SerializableEventType};
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::SystemTime};
+
+// Shape of the path drawn between two recorded mouse positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Curve {
+    // Jump straight to each recorded position
+    #[default]
+    None,
+    // Straight line at constant speed
+    Linear,
+    // Straight line that speeds up and slows down at either end
+    Eased,
+    // Smooth curve through the neighbouring positions
+    Bezier,
+}
+
+impl Curve {
+    pub const ALL: [Curve; 4] = [Curve::None, Curve::Linear, Curve::Eased, Curve::Bezier];
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Curve::None => "Off",
+            Curve::Linear => "Linear",
+            Curve::Eased => "Eased",
+            Curve::Bezier => "Bézier",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interpolation {
+    pub curve: Curve,
+    // Intermediate moves generated per second of recorded time
+    pub sample_rate: u32,
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Interpolation {
+            curve: Curve::None,
+            sample_rate: 60,
+        }
+    }
+}
+
+impl Interpolation {
+    pub fn is_enabled(&self) -> bool {
+        self.curve != Curve::None && self.sample_rate > 0
+    }
+}
+
+// Fill the gaps between MouseMove events with generated moves so the cursor
+// glides instead of teleporting. The pointer is followed across clicks and key
+// presses, with the generated moves placed after them, so those still happen
+// exactly where recorded. Script steps can move the pointer or repeat, so
+// nothing is joined across them.
+pub fn interpolate_mouse_moves(
+    events: &[SerializableEvent],
+    interpolation: &Interpolation,
+) -> Vec<SerializableEvent> {
+    if !interpolation.is_enabled() {
+        return events.to_vec();
+    }
+
+    let mut output = Vec::with_capacity(events.len());
+    // Where the pointer is, and where it was before that
+    let mut last = None;
+    let mut before = None;
+    for (i, event) in events.iter().enumerate() {
+        if event.event_type.is_step() {
+            last = None;
+            before = None;
+        } else if let Some(to) = position(event) {
+            if let Some(from) = last {
+                // Neighbours shape the Bézier control points, ends of a run use themselves
+                let after = events[i + 1..]
+                    .iter()
+                    .take_while(|event| !event.event_type.is_step())
+                    .find_map(position)
+                    .unwrap_or(to);
+
+                push_samples(
+                    &mut output,
+                    interpolation,
+                    [before.unwrap_or(from), from, to, after],
+                    events[i - 1].time,
+                    event.time,
+                );
+            }
+            before = last;
+            last = Some(to);
+        }
+        output.push(event.clone());
+    }
+
+    output
+}
+
+fn push_samples(
+    output: &mut Vec<SerializableEvent>,
+    interpolation: &Interpolation,
+    points: [(f64, f64); 4],
+    from_time: SystemTime,
+    to_time: SystemTime,
+) {
+    let [before, from, to, after] = points;
+    if from == to {
+        return;
+    }
+
+    let duration = to_time.duration_since(from_time).unwrap_or_default();
+    let samples = (duration.as_secs_f64() * interpolation.sample_rate as f64) as u32;
+
+    for j in 1..samples {
+        let t = j as f64 / samples as f64;
+        let (x, y) = match interpolation.curve {
+            Curve::None | Curve::Linear => lerp(from, to, t),
+            Curve::Eased => lerp(from, to, t * t * (3.0 - 2.0 * t)),
+            Curve::Bezier => {
+                // Catmull-Rom tangents turned into cubic Bézier control points
+                let control_1 = (
+                    from.0 + (to.0 - before.0) / 6.0,
+                    from.1 + (to.1 - before.1) / 6.0,
+                );
+                let control_2 = (
+                    to.0 - (after.0 - from.0) / 6.0,
+                    to.1 - (after.1 - from.1) / 6.0,
+                );
+                cubic_bezier(from, control_1, control_2, to, t)
+            }
+        };
+
+        output.push(SerializableEvent {
+            time: from_time + duration.mul_f64(t),
+            event_type: SerializableEventType::MouseMove { x, y },
+        });
+    }
+}
+
+fn position(event: &SerializableEvent) -> Option<(f64, f64)> {
+    match event.event_type {
+        SerializableEventType::MouseMove { x, y } => Some((x, y)),
+        _ => None,
+    }
+}
+
+fn lerp(a: (f64, f64), b: (f64, f64), t: f64) -> (f64, f64) {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+fn cubic_bezier(
+    p0: (f64, f64),
+    p1: (f64, f64),
+    p2: (f64, f64),
+    p3: (f64, f64),
+    t: f64,
+) -> (f64, f64) {
+    let u = 1.0 - t;
+    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+    (
+        a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+        a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serializable_event::SerializableButton;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn event(millis: u64, event_type: SerializableEventType) -> SerializableEvent {
+        SerializableEvent {
+            time: UNIX_EPOCH + Duration::from_millis(millis),
+            event_type,
+        }
+    }
+
+    fn linear() -> Interpolation {
+        Interpolation {
+            curve: Curve::Linear,
+            sample_rate: 10,
+        }
+    }
+
+    #[test]
+    fn joins_moves_across_clicks() {
+        let events = vec![
+            event(0, SerializableEventType::MouseMove { x: 0.0, y: 0.0 }),
+            event(
+                100,
+                SerializableEventType::ButtonPress(SerializableButton::Left),
+            ),
+            event(
+                200,
+                SerializableEventType::ButtonRelease(SerializableButton::Left),
+            ),
+            event(1200, SerializableEventType::MouseMove { x: 100.0, y: 0.0 }),
+        ];
+        let output = interpolate_mouse_moves(&events, &linear());
+
+        // The click stays at the first position, the glide follows it
+        assert!(matches!(
+            output[2].event_type,
+            SerializableEventType::ButtonRelease(_)
+        ));
+        let samples = &output[3..output.len() - 1];
+        assert_eq!(samples.len(), 9);
+        assert!(samples.iter().all(|sample| sample.time > events[2].time));
+        assert!(matches!(
+            samples[0].event_type,
+            SerializableEventType::MouseMove { x, .. } if (x - 10.0).abs() < 1e-9
+        ));
+    }
+
+    #[test]
+    fn does_not_join_across_steps() {
+        let events = vec![
+            event(0, SerializableEventType::MouseMove { x: 0.0, y: 0.0 }),
+            event(100, SerializableEventType::Repeat { count: Some(2) }),
+            event(1200, SerializableEventType::MouseMove { x: 100.0, y: 0.0 }),
+            event(1300, SerializableEventType::End),
+        ];
+        assert_eq!(interpolate_mouse_moves(&events, &linear()).len(), 4);
+    }
+}
@@ -0,0 +1,654 @@
+use crate::serializable_event::{SerializableEvent, SerializableEventType, SerializableKey};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+// Keyboard layout of the machine a script is typed on. Keys are stored as
+// physical positions, so the same key types different characters per layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum KeyboardLayout {
+    #[default]
+    Us,
+    Uk,
+    De,
+    FrAzerty,
+}
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 4] = [
+        KeyboardLayout::Us,
+        KeyboardLayout::Uk,
+        KeyboardLayout::De,
+        KeyboardLayout::FrAzerty,
+    ];
+
+    fn table(&self) -> &'static [Row] {
+        match self {
+            KeyboardLayout::Us => US,
+            KeyboardLayout::Uk => UK,
+            KeyboardLayout::De => DE,
+            KeyboardLayout::FrAzerty => FR_AZERTY,
+        }
+    }
+
+    // How to type `c`, preferring the plain key over Shift over AltGr
+    pub fn keystroke(&self, c: char) -> Option<Keystroke> {
+        if let Some(key) = whitespace_key(c) {
+            return Some(Keystroke::plain(key));
+        }
+
+        self.table()
+            .iter()
+            .find_map(|&(key, [plain, shift, altgr])| {
+                if plain == c {
+                    Some(Keystroke::plain(key))
+                } else if shift == c {
+                    Some(Keystroke {
+                        shift: true,
+                        ..Keystroke::plain(key)
+                    })
+                } else if altgr == c {
+                    Some(Keystroke {
+                        altgr: true,
+                        ..Keystroke::plain(key)
+                    })
+                } else {
+                    None
+                }
+            })
+    }
+
+    // The character a key types with the given modifiers, if any
+    pub fn character(&self, stroke: Keystroke) -> Option<char> {
+        if !stroke.shift && !stroke.altgr {
+            if let Some(c) = WHITESPACE.iter().find(|(_, key)| *key == stroke.key) {
+                return Some(c.0);
+            }
+        }
+
+        let (_, chars) = self.table().iter().find(|(key, _)| *key == stroke.key)?;
+        let c = match (stroke.shift, stroke.altgr) {
+            (false, false) => chars[0],
+            (true, false) => chars[1],
+            (false, true) => chars[2],
+            (true, true) => NONE,
+        };
+        (c != NONE).then_some(c)
+    }
+
+    // Keystrokes for every character of `text`, along with the characters this
+    // layout has no key for (each listed once). Those are left out of the keystrokes.
+    pub fn keystrokes(&self, text: &str) -> (Vec<Keystroke>, Vec<char>) {
+        let mut strokes = Vec::with_capacity(text.len());
+        let mut missing = Vec::new();
+        for c in text.chars() {
+            match self.keystroke(c) {
+                Some(stroke) => strokes.push(stroke),
+                None if !missing.contains(&c) => missing.push(c),
+                None => {}
+            }
+        }
+        (strokes, missing)
+    }
+}
+
+impl fmt::Display for KeyboardLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KeyboardLayout::Us => "US",
+            KeyboardLayout::Uk => "UK",
+            KeyboardLayout::De => "DE",
+            KeyboardLayout::FrAzerty => "FR",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for KeyboardLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "us" => Ok(KeyboardLayout::Us),
+            "uk" | "gb" => Ok(KeyboardLayout::Uk),
+            "de" => Ok(KeyboardLayout::De),
+            "fr" | "azerty" => Ok(KeyboardLayout::FrAzerty),
+            _ => Err(format!(
+                "Unknown keyboard layout \"{}\" (expected us, uk, de or fr)",
+                s
+            )),
+        }
+    }
+}
+
+// A recorded key a layout translation had to leave as it was
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmappedKey {
+    pub stroke: Keystroke,
+    // What the key typed on the original layout, None if it typed nothing
+    pub character: Option<char>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TranslationReport {
+    // Key presses that now use a different key or different modifiers
+    pub translated: usize,
+    // Each key that couldn't be translated, listed once
+    pub unmapped: Vec<UnmappedKey>,
+}
+
+impl fmt::Display for TranslationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "translated {} key presses", self.translated)?;
+        if self.unmapped.is_empty() {
+            return Ok(());
+        }
+
+        let unmapped: Vec<String> = self
+            .unmapped
+            .iter()
+            .map(|unmapped| match unmapped.character {
+                Some(c) => format!("{:?}", c),
+                None => format!("{:?}", unmapped.stroke.key),
+            })
+            .collect();
+        write!(f, ", could not map {}", unmapped.join(", "))
+    }
+}
+
+// Rewrite recorded key presses so they type the same characters on another
+// layout. Keys that don't type a character (arrows, Ctrl, F keys) are kept, and
+// held modifiers count, so Ctrl+Z stays Ctrl+Z.
+//
+// A key that needs different Shift/AltGr on the new layout is turned into a
+// complete tap at the time it was pressed, with the modifiers switched around it,
+// since other keys may be pressed before it is let go.
+pub fn translate_layout(
+    events: &[SerializableEvent],
+    from: KeyboardLayout,
+    to: KeyboardLayout,
+) -> (Vec<SerializableEvent>, TranslationReport) {
+    let mut report = TranslationReport::default();
+    if from == to {
+        return (events.to_vec(), report);
+    }
+
+    let mut output = Vec::with_capacity(events.len());
+    // Modifier keys currently held in the recording
+    let mut held: Vec<SerializableKey> = Vec::new();
+    // What each held recorded key was replaced with, None if it was already tapped
+    let mut replaced: HashMap<SerializableKey, Option<SerializableKey>> = HashMap::new();
+
+    for event in events {
+        let push = |output: &mut Vec<SerializableEvent>, event_type| {
+            output.push(SerializableEvent {
+                time: event.time,
+                event_type,
+            })
+        };
+
+        match event.event_type {
+            SerializableEventType::KeyPress(key) if is_modifier(key) => {
+                if !held.contains(&key) {
+                    held.push(key);
+                }
+                output.push(event.clone());
+            }
+            SerializableEventType::KeyRelease(key) if is_modifier(key) => {
+                held.retain(|&k| k != key);
+                output.push(event.clone());
+            }
+            SerializableEventType::KeyPress(key) => {
+                let stroke = Keystroke {
+                    key,
+                    shift: held.iter().any(|&k| is_shift(k)),
+                    altgr: held.contains(&SerializableKey::AltGr),
+                };
+                let character = from.character(stroke);
+                let target = character.and_then(|c| to.keystroke(c));
+
+                match target {
+                    Some(target) if target == stroke => {
+                        output.push(event.clone());
+                    }
+                    Some(target)
+                        if target.shift == stroke.shift && target.altgr == stroke.altgr =>
+                    {
+                        report.translated += 1;
+                        replaced.insert(key, Some(target.key));
+                        push(&mut output, SerializableEventType::KeyPress(target.key));
+                    }
+                    Some(target) => {
+                        report.translated += 1;
+                        replaced.insert(key, None);
+                        for event_type in switched_tap(&held, target) {
+                            push(&mut output, event_type);
+                        }
+                    }
+                    None => {
+                        // Keys that type nothing anywhere, like Return, aren't worth reporting
+                        if character.is_some() || to.character(stroke).is_some() {
+                            let unmapped = UnmappedKey { stroke, character };
+                            if !report.unmapped.contains(&unmapped) {
+                                report.unmapped.push(unmapped);
+                            }
+                        }
+                        output.push(event.clone());
+                    }
+                }
+            }
+            SerializableEventType::KeyRelease(key) => match replaced.remove(&key) {
+                Some(Some(target)) => push(&mut output, SerializableEventType::KeyRelease(target)),
+                Some(None) => {}
+                None => output.push(event.clone()),
+            },
+            _ => output.push(event.clone()),
+        }
+    }
+
+    (output, report)
+}
+
+fn is_shift(key: SerializableKey) -> bool {
+    matches!(
+        key,
+        SerializableKey::ShiftLeft | SerializableKey::ShiftRight
+    )
+}
+
+fn is_modifier(key: SerializableKey) -> bool {
+    is_shift(key) || key == SerializableKey::AltGr
+}
+
+// Tap `target` with exactly its modifiers, putting the held ones back afterwards
+fn switched_tap(held: &[SerializableKey], target: Keystroke) -> Vec<SerializableEventType> {
+    let unwanted: Vec<SerializableKey> = held
+        .iter()
+        .copied()
+        .filter(|&key| {
+            (is_shift(key) && !target.shift) || (key == SerializableKey::AltGr && !target.altgr)
+        })
+        .collect();
+    let needed = Keystroke {
+        shift: target.shift && !held.iter().any(|&k| is_shift(k)),
+        altgr: target.altgr && !held.contains(&SerializableKey::AltGr),
+        ..target
+    };
+
+    let mut events: Vec<SerializableEventType> = unwanted
+        .iter()
+        .map(|&key| SerializableEventType::KeyRelease(key))
+        .collect();
+    events.extend(needed.event_types());
+    events.extend(
+        unwanted
+            .iter()
+            .map(|&key| SerializableEventType::KeyPress(key)),
+    );
+    events
+}
+
+// A key along with the modifiers held down while it's pressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keystroke {
+    pub key: SerializableKey,
+    pub shift: bool,
+    pub altgr: bool,
+}
+
+impl Keystroke {
+    pub fn plain(key: SerializableKey) -> Self {
+        Keystroke {
+            key,
+            shift: false,
+            altgr: false,
+        }
+    }
+
+    // Press and release events for this keystroke, modifiers wrapped around the key
+    pub fn event_types(&self) -> Vec<SerializableEventType> {
+        let mut modifiers = Vec::new();
+        if self.shift {
+            modifiers.push(SerializableKey::ShiftLeft);
+        }
+        if self.altgr {
+            modifiers.push(SerializableKey::AltGr);
+        }
+
+        let mut events: Vec<SerializableEventType> = modifiers
+            .iter()
+            .map(|&key| SerializableEventType::KeyPress(key))
+            .collect();
+        events.push(SerializableEventType::KeyPress(self.key));
+        events.push(SerializableEventType::KeyRelease(self.key));
+        events.extend(
+            modifiers
+                .iter()
+                .rev()
+                .map(|&key| SerializableEventType::KeyRelease(key)),
+        );
+        events
+    }
+}
+
+// Layout independent keys that type a character
+const WHITESPACE: [(char, SerializableKey); 3] = [
+    (' ', SerializableKey::Space),
+    ('\n', SerializableKey::Return),
+    ('\t', SerializableKey::Tab),
+];
+
+fn whitespace_key(c: char) -> Option<SerializableKey> {
+    WHITESPACE
+        .iter()
+        .find(|(whitespace, _)| *whitespace == c)
+        .map(|&(_, key)| key)
+}
+
+// A physical key and what it types on its own, with Shift and with AltGr. NONE
+// marks nothing, dead keys are left out as well since they don't type on their own.
+type Row = (SerializableKey, [char; 3]);
+
+const NONE: char = '\0';
+
+use SerializableKey::*;
+
+const US: &[Row] = &[
+    (BackQuote, ['`', '~', NONE]),
+    (Num1, ['1', '!', NONE]),
+    (Num2, ['2', '@', NONE]),
+    (Num3, ['3', '#', NONE]),
+    (Num4, ['4', '$', NONE]),
+    (Num5, ['5', '%', NONE]),
+    (Num6, ['6', '^', NONE]),
+    (Num7, ['7', '&', NONE]),
+    (Num8, ['8', '*', NONE]),
+    (Num9, ['9', '(', NONE]),
+    (Num0, ['0', ')', NONE]),
+    (Minus, ['-', '_', NONE]),
+    (Equal, ['=', '+', NONE]),
+    (KeyQ, ['q', 'Q', NONE]),
+    (KeyW, ['w', 'W', NONE]),
+    (KeyE, ['e', 'E', NONE]),
+    (KeyR, ['r', 'R', NONE]),
+    (KeyT, ['t', 'T', NONE]),
+    (KeyY, ['y', 'Y', NONE]),
+    (KeyU, ['u', 'U', NONE]),
+    (KeyI, ['i', 'I', NONE]),
+    (KeyO, ['o', 'O', NONE]),
+    (KeyP, ['p', 'P', NONE]),
+    (LeftBracket, ['[', '{', NONE]),
+    (RightBracket, [']', '}', NONE]),
+    (BackSlash, ['\\', '|', NONE]),
+    (KeyA, ['a', 'A', NONE]),
+    (KeyS, ['s', 'S', NONE]),
+    (KeyD, ['d', 'D', NONE]),
+    (KeyF, ['f', 'F', NONE]),
+    (KeyG, ['g', 'G', NONE]),
+    (KeyH, ['h', 'H', NONE]),
+    (KeyJ, ['j', 'J', NONE]),
+    (KeyK, ['k', 'K', NONE]),
+    (KeyL, ['l', 'L', NONE]),
+    (SemiColon, [';', ':', NONE]),
+    (Quote, ['\'', '"', NONE]),
+    (KeyZ, ['z', 'Z', NONE]),
+    (KeyX, ['x', 'X', NONE]),
+    (KeyC, ['c', 'C', NONE]),
+    (KeyV, ['v', 'V', NONE]),
+    (KeyB, ['b', 'B', NONE]),
+    (KeyN, ['n', 'N', NONE]),
+    (KeyM, ['m', 'M', NONE]),
+    (Comma, [',', '<', NONE]),
+    (Dot, ['.', '>', NONE]),
+    (Slash, ['/', '?', NONE]),
+];
+
+const UK: &[Row] = &[
+    (BackQuote, ['`', '¬', '¦']),
+    (Num1, ['1', '!', NONE]),
+    (Num2, ['2', '"', NONE]),
+    (Num3, ['3', '£', NONE]),
+    (Num4, ['4', '$', '€']),
+    (Num5, ['5', '%', NONE]),
+    (Num6, ['6', '^', NONE]),
+    (Num7, ['7', '&', NONE]),
+    (Num8, ['8', '*', NONE]),
+    (Num9, ['9', '(', NONE]),
+    (Num0, ['0', ')', NONE]),
+    (Minus, ['-', '_', NONE]),
+    (Equal, ['=', '+', NONE]),
+    (KeyQ, ['q', 'Q', NONE]),
+    (KeyW, ['w', 'W', NONE]),
+    (KeyE, ['e', 'E', NONE]),
+    (KeyR, ['r', 'R', NONE]),
+    (KeyT, ['t', 'T', NONE]),
+    (KeyY, ['y', 'Y', NONE]),
+    (KeyU, ['u', 'U', NONE]),
+    (KeyI, ['i', 'I', NONE]),
+    (KeyO, ['o', 'O', NONE]),
+    (KeyP, ['p', 'P', NONE]),
+    (LeftBracket, ['[', '{', NONE]),
+    (RightBracket, [']', '}', NONE]),
+    (KeyA, ['a', 'A', NONE]),
+    (KeyS, ['s', 'S', NONE]),
+    (KeyD, ['d', 'D', NONE]),
+    (KeyF, ['f', 'F', NONE]),
+    (KeyG, ['g', 'G', NONE]),
+    (KeyH, ['h', 'H', NONE]),
+    (KeyJ, ['j', 'J', NONE]),
+    (KeyK, ['k', 'K', NONE]),
+    (KeyL, ['l', 'L', NONE]),
+    (SemiColon, [';', ':', NONE]),
+    (Quote, ['\'', '@', NONE]),
+    // The ISO key next to Return
+    (BackSlash, ['#', '~', NONE]),
+    (IntlBackslash, ['\\', '|', NONE]),
+    (KeyZ, ['z', 'Z', NONE]),
+    (KeyX, ['x', 'X', NONE]),
+    (KeyC, ['c', 'C', NONE]),
+    (KeyV, ['v', 'V', NONE]),
+    (KeyB, ['b', 'B', NONE]),
+    (KeyN, ['n', 'N', NONE]),
+    (KeyM, ['m', 'M', NONE]),
+    (Comma, [',', '<', NONE]),
+    (Dot, ['.', '>', NONE]),
+    (Slash, ['/', '?', NONE]),
+];
+
+const DE: &[Row] = &[
+    (BackQuote, [NONE, '°', NONE]),
+    (Num1, ['1', '!', NONE]),
+    (Num2, ['2', '"', '²']),
+    (Num3, ['3', '§', '³']),
+    (Num4, ['4', '$', NONE]),
+    (Num5, ['5', '%', NONE]),
+    (Num6, ['6', '&', NONE]),
+    (Num7, ['7', '/', '{']),
+    (Num8, ['8', '(', '[']),
+    (Num9, ['9', ')', ']']),
+    (Num0, ['0', '=', '}']),
+    (Minus, ['ß', '?', '\\']),
+    (KeyQ, ['q', 'Q', '@']),
+    (KeyW, ['w', 'W', NONE]),
+    (KeyE, ['e', 'E', '€']),
+    (KeyR, ['r', 'R', NONE]),
+    (KeyT, ['t', 'T', NONE]),
+    (KeyY, ['z', 'Z', NONE]),
+    (KeyU, ['u', 'U', NONE]),
+    (KeyI, ['i', 'I', NONE]),
+    (KeyO, ['o', 'O', NONE]),
+    (KeyP, ['p', 'P', NONE]),
+    (LeftBracket, ['ü', 'Ü', NONE]),
+    (RightBracket, ['+', '*', '~']),
+    (KeyA, ['a', 'A', NONE]),
+    (KeyS, ['s', 'S', NONE]),
+    (KeyD, ['d', 'D', NONE]),
+    (KeyF, ['f', 'F', NONE]),
+    (KeyG, ['g', 'G', NONE]),
+    (KeyH, ['h', 'H', NONE]),
+    (KeyJ, ['j', 'J', NONE]),
+    (KeyK, ['k', 'K', NONE]),
+    (KeyL, ['l', 'L', NONE]),
+    (SemiColon, ['ö', 'Ö', NONE]),
+    (Quote, ['ä', 'Ä', NONE]),
+    (BackSlash, ['#', '\'', NONE]),
+    (IntlBackslash, ['<', '>', '|']),
+    (KeyZ, ['y', 'Y', NONE]),
+    (KeyX, ['x', 'X', NONE]),
+    (KeyC, ['c', 'C', NONE]),
+    (KeyV, ['v', 'V', NONE]),
+    (KeyB, ['b', 'B', NONE]),
+    (KeyN, ['n', 'N', NONE]),
+    (KeyM, ['m', 'M', 'µ']),
+    (Comma, [',', ';', NONE]),
+    (Dot, ['.', ':', NONE]),
+    (Slash, ['-', '_', NONE]),
+];
+
+const FR_AZERTY: &[Row] = &[
+    (BackQuote, ['²', NONE, NONE]),
+    (Num1, ['&', '1', NONE]),
+    (Num2, ['é', '2', NONE]),
+    (Num3, ['"', '3', '#']),
+    (Num4, ['\'', '4', '{']),
+    (Num5, ['(', '5', '[']),
+    (Num6, ['-', '6', '|']),
+    (Num7, ['è', '7', NONE]),
+    (Num8, ['_', '8', '\\']),
+    (Num9, ['ç', '9', NONE]),
+    (Num0, ['à', '0', '@']),
+    (Minus, [')', '°', ']']),
+    (Equal, ['=', '+', '}']),
+    (KeyQ, ['a', 'A', NONE]),
+    (KeyW, ['z', 'Z', NONE]),
+    (KeyE, ['e', 'E', '€']),
+    (KeyR, ['r', 'R', NONE]),
+    (KeyT, ['t', 'T', NONE]),
+    (KeyY, ['y', 'Y', NONE]),
+    (KeyU, ['u', 'U', NONE]),
+    (KeyI, ['i', 'I', NONE]),
+    (KeyO, ['o', 'O', NONE]),
+    (KeyP, ['p', 'P', NONE]),
+    (RightBracket, ['$', '£', '¤']),
+    (KeyA, ['q', 'Q', NONE]),
+    (KeyS, ['s', 'S', NONE]),
+    (KeyD, ['d', 'D', NONE]),
+    (KeyF, ['f', 'F', NONE]),
+    (KeyG, ['g', 'G', NONE]),
+    (KeyH, ['h', 'H', NONE]),
+    (KeyJ, ['j', 'J', NONE]),
+    (KeyK, ['k', 'K', NONE]),
+    (KeyL, ['l', 'L', NONE]),
+    (SemiColon, ['m', 'M', NONE]),
+    (Quote, ['ù', '%', NONE]),
+    (BackSlash, ['*', 'µ', NONE]),
+    (IntlBackslash, ['<', '>', NONE]),
+    (KeyZ, ['w', 'W', NONE]),
+    (KeyX, ['x', 'X', NONE]),
+    (KeyC, ['c', 'C', NONE]),
+    (KeyV, ['v', 'V', NONE]),
+    (KeyB, ['b', 'B', NONE]),
+    (KeyN, ['n', 'N', NONE]),
+    (KeyM, [',', '?', NONE]),
+    (Comma, [';', '.', NONE]),
+    (Dot, [':', '/', NONE]),
+    (Slash, ['!', '§', NONE]),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+    use SerializableEventType::{KeyPress, KeyRelease};
+
+    fn events(event_types: &[SerializableEventType]) -> Vec<SerializableEvent> {
+        event_types
+            .iter()
+            .enumerate()
+            .map(|(i, event_type)| SerializableEvent {
+                time: UNIX_EPOCH + Duration::from_millis(i as u64),
+                event_type: event_type.clone(),
+            })
+            .collect()
+    }
+
+    // Event types have no PartialEq, their Debug output is compared instead
+    fn types(events: &[SerializableEvent]) -> String {
+        let event_types: Vec<_> = events.iter().map(|event| &event.event_type).collect();
+        format!("{:?}", event_types)
+    }
+
+    fn translated(
+        event_types: &[SerializableEventType],
+        from: KeyboardLayout,
+        to: KeyboardLayout,
+    ) -> (String, TranslationReport) {
+        let (events, report) = translate_layout(&events(event_types), from, to);
+        (types(&events), report)
+    }
+
+    #[test]
+    fn same_layout_is_unchanged() {
+        let recorded = [KeyPress(KeyZ), KeyRelease(KeyZ)];
+        let (output, report) = translated(&recorded, KeyboardLayout::Us, KeyboardLayout::Us);
+        assert_eq!(output, format!("{:?}", recorded));
+        assert_eq!(report, TranslationReport::default());
+    }
+
+    #[test]
+    fn swaps_keys_that_type_the_same_character() {
+        let recorded = [KeyPress(KeyZ), KeyRelease(KeyZ)];
+        let (output, report) = translated(&recorded, KeyboardLayout::Us, KeyboardLayout::De);
+        assert_eq!(output, format!("{:?}", [KeyPress(KeyY), KeyRelease(KeyY)]));
+        assert_eq!(report.translated, 1);
+    }
+
+    #[test]
+    fn switches_modifiers_around_the_key() {
+        // Shift+2 types @ on US, which is AltGr+Q on DE
+        let recorded = [
+            KeyPress(ShiftLeft),
+            KeyPress(Num2),
+            KeyRelease(Num2),
+            KeyRelease(ShiftLeft),
+        ];
+        let (output, report) = translated(&recorded, KeyboardLayout::Us, KeyboardLayout::De);
+        let expected = [
+            KeyPress(ShiftLeft),
+            KeyRelease(ShiftLeft),
+            KeyPress(AltGr),
+            KeyPress(KeyQ),
+            KeyRelease(KeyQ),
+            KeyRelease(AltGr),
+            KeyPress(ShiftLeft),
+            KeyRelease(ShiftLeft),
+        ];
+        assert_eq!(output, format!("{:?}", expected));
+        assert_eq!(report.translated, 1);
+    }
+
+    #[test]
+    fn keeps_keys_that_type_nothing() {
+        let recorded = [
+            KeyPress(ControlLeft),
+            KeyPress(Return),
+            KeyRelease(Return),
+            KeyRelease(ControlLeft),
+        ];
+        let (output, report) = translated(&recorded, KeyboardLayout::Us, KeyboardLayout::De);
+        assert_eq!(output, format!("{:?}", recorded));
+        assert!(report.unmapped.is_empty());
+    }
+
+    #[test]
+    fn typing_text_round_trips_through_characters() {
+        for layout in KeyboardLayout::ALL {
+            let (strokes, missing) = layout.keystrokes("Hello, world 42!");
+            assert!(missing.is_empty(), "{} is missing {:?}", layout, missing);
+            let typed: String = strokes
+                .iter()
+                .map(|stroke| layout.character(*stroke).unwrap())
+                .collect();
+            assert_eq!(typed, "Hello, world 42!");
+        }
+    }
+}
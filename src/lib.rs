@@ -1,3 +1,5 @@
+#[cfg(target_os = "windows")]
+use rdev::grab;
 #[cfg(not(target_os = "macos"))]
 use rdev::{listen, simulate, SimulateError};
 use rdev::{Event, EventType, Key};
@@ -18,26 +20,115 @@ use chrono::{offset::Utc, DateTime};
 #[path = "macos_events.rs"]
 mod macos_events;
 
+pub mod calls;
+pub mod capture;
+pub mod config;
+pub mod editor;
+pub mod expansion;
+pub mod flow;
+pub mod generate;
+pub mod hotkey;
+pub mod interpolate;
+pub mod layout;
+pub mod mouse_path;
+pub mod player;
+pub mod playlist;
+pub mod random;
+pub mod remap;
+pub mod schedule;
+pub mod screen;
+pub mod script;
+pub mod scripting;
+pub mod serializable_event;
+pub mod settings;
+pub mod simplify;
+pub mod template;
+pub mod timeline;
+pub mod variables;
+pub mod wait;
+pub mod window;
+
+// Last cursor position seen by the listener, used to anchor relative playback
+#[cfg(not(target_os = "macos"))]
+static LAST_CURSOR_POSITION: Mutex<Option<(f64, f64)>> = Mutex::new(None);
+
+// What the listener does with an input event. Events can only be kept from
+// other programs where input can be grabbed (Windows and macOS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grab {
+    // Other programs get it, and so does the channel
+    Pass,
+    // Only the channel gets it, for hotkeys that shouldn't type into anything
+    Hide,
+    // Nobody gets it, for remapped keys
+    Swallow,
+}
+
 // Spawn new thread to listen for any keyboard or mouse input
-// Sends events through a tunnel that must be set up before calling this function
-pub fn spawn_event_listener(sendch: Sender<Event>) {
+// Sends events through a tunnel that must be set up before calling this function.
+// `grab_event` decides what becomes of each event.
+pub fn spawn_event_listener(
+    sendch: Sender<Event>,
+    grab_event: impl Fn(&Event) -> Grab + Send + Sync + 'static,
+) {
     #[cfg(target_os = "macos")]
     {
         // On macOS, use CGEventTap for reliable event capture
-        macos_events::start_macos_event_tap(sendch);
+        macos_events::start_macos_event_tap(sendch, grab_event);
     }
 
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(target_os = "windows")]
     {
         let _listener = thread::spawn(move || {
-            listen(move |event| {
-                sendch
-                    .send(event)
-                    .unwrap_or_else(|e| log(format!("Could not send event {:?}", e).as_str()));
-            })
-            .expect("Could not listen");
+            let grab_event = Arc::new(grab_event);
+            let grab_sendch = sendch.clone();
+            let grab_decide = Arc::clone(&grab_event);
+            let grabbed = grab(move |event| {
+                let decided = grab_decide(&event);
+                if decided != Grab::Swallow {
+                    forward(&grab_sendch, event.clone());
+                }
+                (decided == Grab::Pass).then_some(event)
+            });
+
+            // Input can still be watched when it can't be grabbed
+            if let Err(e) = grabbed {
+                log(&format!(
+                    "Could not grab input, remapped keys will go through as well: {:?}",
+                    e
+                ));
+                listen_only(sendch, move |event| grab_event(event));
+            }
         });
     }
+
+    // The rdev fork has no grab for Linux, so input is only watched there
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        let _listener = thread::spawn(move || listen_only(sendch, grab_event));
+    }
+}
+
+// Watch input without keeping any of it from other programs. What `grab_event`
+// swallows still isn't sent.
+#[cfg(not(target_os = "macos"))]
+fn listen_only(sendch: Sender<Event>, grab_event: impl Fn(&Event) -> Grab + 'static) {
+    listen(move |event| {
+        if grab_event(&event) != Grab::Swallow {
+            forward(&sendch, event);
+        }
+    })
+    .expect("Could not listen");
+}
+
+#[cfg(not(target_os = "macos"))]
+fn forward(sendch: &Sender<Event>, event: Event) {
+    if let EventType::MouseMove { x, y } = event.event_type {
+        *LAST_CURSOR_POSITION.lock().unwrap() = Some((x, y));
+    }
+    sendch
+        .send(event)
+        .unwrap_or_else(|e| log(format!("Could not send event {:?}", e).as_str()));
 }
 
 // Listen for events from a tunnel sender and set appropriate flags for main program
@@ -121,6 +212,55 @@ pub fn send_event(event_type: &EventType) {
     }
 }
 
+// Current cursor position, if it is known yet
+pub fn cursor_position() -> Option<(f64, f64)> {
+    #[cfg(target_os = "macos")]
+    {
+        macos_events::cursor_position()
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        query_pointer().or_else(|| *LAST_CURSOR_POSITION.lock().unwrap())
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        query_cursor().or_else(|| *LAST_CURSOR_POSITION.lock().unwrap())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        *LAST_CURSOR_POSITION.lock().unwrap()
+    }
+}
+
+// Ask Windows where the cursor is, so the position is known before it moves
+#[cfg(target_os = "windows")]
+fn query_cursor() -> Option<(f64, f64)> {
+    use winapi::shared::windef::POINT;
+    use winapi::um::winuser::GetCursorPos;
+
+    let mut point = POINT { x: 0, y: 0 };
+    // GetCursorPos only writes to the point it's handed
+    if unsafe { GetCursorPos(&mut point) } == 0 {
+        return None;
+    }
+    Some((point.x as f64, point.y as f64))
+}
+
+// Ask the X server where the pointer is, so the position is known before it moves
+#[cfg(target_os = "linux")]
+fn query_pointer() -> Option<(f64, f64)> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::ConnectionExt as _;
+
+    let (conn, screen_num) = x11rb::connect(None).ok()?;
+    let root = conn.setup().roots[screen_num].root;
+    let reply = conn.query_pointer(root).ok()?.reply().ok()?;
+    Some((reply.root_x as f64, reply.root_y as f64))
+}
+
 pub fn log(message: &str) {
     println!("{}: {}", get_time(), message);
 }
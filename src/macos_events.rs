@@ -15,7 +15,10 @@ use std::sync::mpsc::Sender;
 use std::time::SystemTime;
 
 #[cfg(target_os = "macos")]
-pub fn start_macos_event_tap(sender: Sender<Event>) {
+pub fn start_macos_event_tap(
+    sender: Sender<Event>,
+    grab_event: impl Fn(&Event) -> crate::Grab + Send + 'static,
+) {
     use std::thread;
 
     thread::spawn(move || {
@@ -40,11 +43,18 @@ pub fn start_macos_event_tap(sender: Sender<Event>) {
         match CGEventTap::new(
             CGEventTapLocation::HID,
             CGEventTapPlacement::HeadInsertEventTap,
-            CGEventTapOptions::ListenOnly,
+            CGEventTapOptions::Default,
             event_types,
             move |_proxy, event_type, cg_event| {
                 if let Some(event) = convert_cg_event_to_rdev(event_type, &cg_event) {
-                    let _ = sender_clone.send(event);
+                    // Hidden and swallowed events never reach other programs
+                    let grab = grab_event(&event);
+                    if grab != crate::Grab::Swallow {
+                        let _ = sender_clone.send(event);
+                    }
+                    if grab != crate::Grab::Pass {
+                        return None;
+                    }
                 }
                 // Pass through the event unchanged
                 Some(cg_event.clone())
@@ -233,6 +243,13 @@ static RIGHT_BUTTON_DOWN: AtomicBool = AtomicBool::new(false);
 #[cfg(target_os = "macos")]
 static MIDDLE_BUTTON_DOWN: AtomicBool = AtomicBool::new(false);
 
+#[cfg(target_os = "macos")]
+pub fn cursor_position() -> Option<(f64, f64)> {
+    let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState).ok()?;
+    let location = CGEvent::new(source).ok()?.location();
+    Some((location.x, location.y))
+}
+
 #[cfg(target_os = "macos")]
 pub fn simulate_macos_event(event_type: &EventType) -> Result<(), String> {
     let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
@@ -1,29 +1,69 @@
 #![windows_subsystem = "windows"]
 
-mod serializable_event;
-use serializable_event::SerializableEvent;
-
-use iced::widget::{button, checkbox, column, container, row, text, text_input, Column};
-use iced::{Alignment, Application, Command, Element, Length, Settings, Theme};
+mod chart;
+mod cli;
+
+use chart::{PathChart, TimelineChart};
+use chrono::Local;
+use iced::widget::{
+    button, canvas, checkbox, column, container, mouse_area, pick_list, row, scrollable, text,
+    text_input, Column,
+};
+use iced::{Alignment, Application, Command, Element, Length, Theme};
 #[cfg(not(target_os = "macos"))]
 use rdev::simulate;
 #[cfg(not(target_os = "macos"))]
 use rdev::SimulateError;
-use rdev::{Event, EventType, Key};
-
+use rdev::{Event, EventType};
+
+use simplyscriptor2::calls::relative_path;
+use simplyscriptor2::editor::{EventEditor, EventField, EventRow, NewEvent};
+use simplyscriptor2::expansion::{Expansions, TextTracker};
+use simplyscriptor2::flow;
+use simplyscriptor2::generate::{
+    AutoClicker, CellAction, ClickKind, GridOrder, GridPattern, StopAfter, CLICK_BUTTONS,
+};
+use simplyscriptor2::hotkey::{
+    Chord, ChordKey, ChordTracker, HotkeyAction, HotkeyQueue, Hotkeys, WhileRunning,
+};
+use simplyscriptor2::interpolate::{interpolate_mouse_moves, Curve, Interpolation};
+use simplyscriptor2::layout::{KeyboardLayout, Keystroke};
+use simplyscriptor2::mouse_path::MousePath;
+use simplyscriptor2::player::{Flow, Player};
+use simplyscriptor2::playlist::{is_playlist, Playlist, PlaylistProgress};
+use simplyscriptor2::remap::{Remapper, Remaps};
+use simplyscriptor2::schedule::{ScheduledRun, Scheduler};
+use simplyscriptor2::screen::{ScreenGeometry, ScreenScaling};
+use simplyscriptor2::script::{MouseAnchor, Script};
+use simplyscriptor2::serializable_event::{
+    SerializableButton, SerializableEvent, SerializableEventType, SerializableKey,
+};
+use simplyscriptor2::settings::{ControlKeys, Settings, WindowPosition};
+use simplyscriptor2::simplify::SimplifyOptions;
+use simplyscriptor2::timeline::{self, Timeline};
+use simplyscriptor2::variables::DataTable;
+use simplyscriptor2::wait::WaitError;
+use simplyscriptor2::window::{SystemWindows, WindowMatcher, WindowSystem, WindowTracker};
 use simplyscriptor2::*;
 use std::{
-    fs::File,
-    io::Read,
-    io::Write,
     sync::{
         atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender},
         Arc, Mutex,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
+// Width of the controls, the event table opens to the right of them
+const CONTROLS_WIDTH: f32 = 200.0;
+const EDITOR_WIDTH: f32 = 560.0;
+// Starting height, and the least that fits the controls
+const WINDOW_HEIGHT: f32 = 698.0;
+// Rows of the event table shown at once, long recordings are split into pages
+const EVENT_PAGE_ROWS: usize = 100;
+const TIMELINE_HEIGHT: f32 = 150.0;
+
 fn load_icon() -> Option<iced::window::Icon> {
     let icon_bytes = include_bytes!("../resource/icons/simply-scriptor-no-line-256x256.png");
 
@@ -41,55 +81,306 @@ fn load_icon() -> Option<iced::window::Icon> {
 }
 
 fn main() -> iced::Result {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
     // Set up the event channel before anything else
     let (tx, rx) = std::sync::mpsc::channel::<Event>();
 
-    // Main behavior flags, properties, and events vector
-    let events = Arc::new(Mutex::new(Vec::new()));
+    // Options from the last time the window was open. A file that couldn't be
+    // loaded isn't saved over, so nothing in it is lost while it gets fixed.
+    let loaded = Settings::path().and_then(|path| Settings::load(&path));
+    let settings_writable = loaded.is_ok();
+    let settings = or_default("settings", loaded);
+    if !settings_writable {
+        log("Settings won't be saved until settings.toml is fixed");
+    }
+    let keys = settings.keys;
+
+    // Main behavior flags, properties, and the loaded script
+    let script = Arc::new(Mutex::new(Script::default()));
+    let playlist = Arc::new(Mutex::new(None));
+    let playlist_progress = Arc::new(Mutex::new(None));
+    // Part of the script picked on the timeline, played once on the next run
+    let part = Arc::new(Mutex::new(None));
+    // Recorded time of the event of the loaded script being played
+    let playhead = Arc::new(Mutex::new(None));
     let record = Arc::new(AtomicBool::new(false));
     let run = Arc::new(AtomicBool::new(false));
-    let infinite_loop = Arc::new(AtomicBool::new(true));
-    let loop_count = Arc::new(Mutex::new(1));
-    let delay = Arc::new(AtomicBool::new(true));
+    let infinite_loop = Arc::new(AtomicBool::new(settings.infinite_loop));
+    let loop_count = Arc::new(Mutex::new(settings.loop_count));
+    let delay = Arc::new(AtomicBool::new(settings.natural_delay));
+    let keyboard_layout = Arc::new(Mutex::new(settings.keyboard_layout));
+    let simplify_on_record = Arc::new(AtomicBool::new(false));
+    let expand_text = Arc::new(AtomicBool::new(false));
+    let remap = Arc::new(AtomicBool::new(false));
     let halt_actions = Arc::new(AtomicBool::new(false));
+    let scheduler = Arc::new(Mutex::new(or_default("schedules", Scheduler::load())));
+    let hotkeys = Arc::new(Mutex::new(load_hotkeys(&keys)));
+    let hotkey_queue = Arc::new(Mutex::new(HotkeyQueue::default()));
+    let expansions = Arc::new(Mutex::new(load_expansions(
+        &keys,
+        *keyboard_layout.lock().unwrap(),
+    )));
+    // Reloaded hotkeys and remaps are handed to the grab callback over a channel
+    let (grab_config, grab_updates) = std::sync::mpsc::channel::<GrabConfig>();
+    let _ = grab_config.send(GrabConfig::new(
+        &hotkeys.lock().unwrap(),
+        load_remaps(&keys),
+    ));
+
+    // Set while an expansion types, so its own keys aren't taken as typing
+    let expanding = Arc::new(AtomicBool::new(false));
+
+    // Remapping happens as input is grabbed, so the remapped keys can be
+    // swallowed. What they're remapped to is sent from another thread, not
+    // from inside the grab.
+    let (remap_tx, remap_rx) = std::sync::mpsc::channel::<Vec<SerializableEventType>>();
+    thread::spawn(move || {
+        for events in remap_rx.iter() {
+            send_remapped(events);
+        }
+    });
+    // Every input event waits on the grab callback, so it only reads flags and
+    // its own state. Only the grab thread locks `grabbing`, it never waits.
+    let grabbing = Mutex::new(Grabbing {
+        chord_tracker: ChordTracker::new(),
+        remapper: Remapper::new(),
+        held_back: Vec::new(),
+        config: GrabConfig::default(),
+        updates: grab_updates,
+    });
+    let record_clone = Arc::clone(&record);
+    let remap_clone = Arc::clone(&remap);
+    let run_clone = Arc::clone(&run);
+    let expanding_clone = Arc::clone(&expanding);
+    let scheduled_clone = scheduler.lock().unwrap().playing();
+    let halt_actions_clone = Arc::clone(&halt_actions);
+    let grab_event = move |event: &Event| {
+        if halt_actions_clone.load(Ordering::Relaxed) {
+            return Grab::Pass;
+        }
+        let mut grabbing = grabbing.lock().unwrap();
+        let Grabbing {
+            chord_tracker,
+            remapper,
+            held_back,
+            config,
+            updates,
+        } = &mut *grabbing;
+        if let Some(latest) = updates.try_iter().last() {
+            *config = latest;
+        }
+        let event_type = SerializableEventType::from(event.event_type);
+        let chord = chord_tracker.update(&event_type);
+        let mut release_all = || {
+            let events = remapper.release_all();
+            if !events.is_empty() {
+                let _ = remap_tx.send(events);
+            }
+        };
+
+        // The key that completed a hotkey or the toggle is held back until it's
+        // let go of, repeats included
+        if let Some((key, pressed)) = ChordKey::of(&event_type) {
+            if let Some(i) = held_back.iter().position(|(held, _)| *held == key) {
+                let grab = held_back[i].1;
+                if !pressed {
+                    held_back.remove(i);
+                }
+                return grab;
+            }
+        }
+
+        // Remapping is switched on and off by its own chord, even while recording
+        if let Some(chord) = chord.filter(|chord| Some(*chord) == config.remaps.toggle) {
+            let on = !remap_clone.load(Ordering::Relaxed);
+            remap_clone.store(on, Ordering::Relaxed);
+            if on {
+                log("Remapping keys...");
+            } else {
+                log("Stopped remapping keys...");
+                release_all();
+            }
+            held_back.push((chord.key, Grab::Swallow));
+            return Grab::Swallow;
+        }
+
+        // Hotkeys still reach the receiver to be played, just not the focused
+        // program. They're left alone while recording, like the receiver does.
+        if let Some(chord) = chord
+            .filter(|chord| config.hotkeys.contains(chord) && !record_clone.load(Ordering::Relaxed))
+        {
+            held_back.push((chord.key, Grab::Hide));
+            return Grab::Hide;
+        }
+
+        // Played and expanded keys are left as they are
+        if remap_clone.load(Ordering::Relaxed)
+            && !run_clone.load(Ordering::Relaxed)
+            && !expanding_clone.load(Ordering::Relaxed)
+            && scheduled_clone.load(Ordering::Relaxed) == 0
+        {
+            if let Some(events) =
+                remapper.update(&event_type, chord, chord_tracker.held(), &config.remaps)
+            {
+                let _ = remap_tx.send(events);
+                return Grab::Swallow;
+            }
+        } else {
+            release_all();
+        }
+        Grab::Pass
+    };
 
     // Clone for the event receiver thread
     let record_clone = Arc::clone(&record);
     let run_clone = Arc::clone(&run);
-    let events_clone = Arc::clone(&events);
+    let script_clone = Arc::clone(&script);
+    let simplify_on_record_clone = Arc::clone(&simplify_on_record);
     let halt_actions_clone = Arc::clone(&halt_actions);
+    let keyboard_layout_clone = Arc::clone(&keyboard_layout);
+    let scheduler_clone = Arc::clone(&scheduler);
+    let hotkeys_clone = Arc::clone(&hotkeys);
+    let hotkey_queue_clone = Arc::clone(&hotkey_queue);
+    let expansions_clone = Arc::clone(&expansions);
+    let expand_text_clone = Arc::clone(&expand_text);
 
     // Spawn event receiver thread that processes rdev events
     thread::spawn(move || {
+        let mut window_tracker = WindowTracker::new();
+        let mut chord_tracker = ChordTracker::new();
+        let mut text_tracker = TextTracker::new();
         for event in rx.iter() {
             if halt_actions_clone.load(Ordering::Relaxed) {
                 continue;
             }
 
-            // Handle keyboard shortcuts
-            if event.event_type == EventType::KeyRelease(Key::Comma)
-                && !record_clone.load(Ordering::Relaxed)
+            let event_type = SerializableEventType::from(event.event_type);
+            let chord = chord_tracker.update(&event_type);
+
+            // Text expansion only watches typing while nothing else is playing
+            let keyboard_layout = *keyboard_layout_clone.lock().unwrap();
+            let expansions = expansions_clone.lock().unwrap();
+            let expansion = text_tracker.update(&event_type, keyboard_layout, &expansions);
+            if !expand_text_clone.load(Ordering::Relaxed)
+                || expanding.load(Ordering::Relaxed)
+                || record_clone.load(Ordering::Relaxed)
+                || run_clone.load(Ordering::Relaxed)
             {
+                text_tracker.clear();
+            } else if let Some(expansion) = expansion {
+                log(&format!("Expanding {}", expansion.trigger));
+                let events = expansion.events(expansions.per_char_delay());
+                let then_run = expansion
+                    .run
+                    .as_ref()
+                    .map(|run| expansions.script_path(run));
+                expanding.store(true, Ordering::Relaxed);
+
+                let expanding = Arc::clone(&expanding);
+                let run = Arc::clone(&run_clone);
+                let hotkey_queue = Arc::clone(&hotkey_queue_clone);
+                thread::spawn(move || {
+                    expand(events, keyboard_layout, &expanding);
+                    if let Some(path) = then_run {
+                        let action =
+                            hotkey_queue
+                                .lock()
+                                .unwrap()
+                                .trigger(path, false, WhileRunning::Ignore);
+                        if action == HotkeyAction::Start {
+                            run.store(true, Ordering::Relaxed);
+                        }
+                    }
+                });
+                continue;
+            }
+            drop(expansions);
+
+            // Hotkeys are left alone while recording, they'd end up in the script
+            if let Some(chord) = chord.filter(|_| !record_clone.load(Ordering::Relaxed)) {
+                let hotkeys = hotkeys_clone.lock().unwrap();
+                if let Some(hotkey) = hotkeys.find(&chord) {
+                    let path = hotkeys.script_path(hotkey);
+                    let running = run_clone.load(Ordering::Relaxed);
+                    let action = hotkey_queue_clone.lock().unwrap().trigger(
+                        path.clone(),
+                        running,
+                        hotkeys.while_running,
+                    );
+                    match action {
+                        HotkeyAction::Start => {
+                            log(&format!("{}: Running {}...", chord, path.display()));
+                            run_clone.store(true, Ordering::Relaxed);
+                        }
+                        HotkeyAction::Stop => {
+                            log("Stopped running...");
+                            run_clone.store(false, Ordering::Relaxed);
+                        }
+                        HotkeyAction::Ignore => {
+                            log(&format!("{}: Ignored, another macro is playing", chord))
+                        }
+                        HotkeyAction::Queue => log(&format!(
+                            "{}: {} will play after the current macro",
+                            chord,
+                            path.display()
+                        )),
+                        HotkeyAction::Replace => {
+                            log(&format!(
+                                "{}: Stopping to play {}...",
+                                chord,
+                                path.display()
+                            ));
+                            run_clone.store(false, Ordering::Relaxed);
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            // Handle keyboard shortcuts
+            let released = match event_type {
+                SerializableEventType::KeyRelease(key) => Some(key),
+                _ => None,
+            };
+            if released == Some(keys.record) && !record_clone.load(Ordering::Relaxed) {
                 record_clone.store(true, Ordering::Relaxed);
                 log("Recording...");
-                events_clone.lock().unwrap().clear();
+                let keyboard_layout = *keyboard_layout_clone.lock().unwrap();
+                script_clone
+                    .lock()
+                    .unwrap()
+                    .reset_for_recording(keyboard_layout);
                 continue;
             }
 
-            if event.event_type == EventType::KeyRelease(Key::Dot)
-                && record_clone.load(Ordering::Relaxed)
-            {
+            if released == Some(keys.stop) && record_clone.load(Ordering::Relaxed) {
                 record_clone.store(false, Ordering::Relaxed);
                 log("Stopped recording...");
+                if simplify_on_record_clone.load(Ordering::Relaxed) {
+                    simplify_script(&script_clone);
+                }
                 continue;
             }
 
-            if event.event_type == EventType::KeyRelease(Key::Slash) {
-                if !run_clone.load(Ordering::Relaxed) && !record_clone.load(Ordering::Relaxed) {
+            if released == Some(keys.run) {
+                let mut scheduler = scheduler_clone.lock().unwrap();
+                if scheduler.running() > 0 && !run_clone.load(Ordering::Relaxed) {
+                    log("Stopped scheduled runs...");
+                    if let Err(e) = scheduler.stop_all(Local::now()) {
+                        log(&format!("Error: {}", e));
+                    }
+                } else if !run_clone.load(Ordering::Relaxed)
+                    && !record_clone.load(Ordering::Relaxed)
+                {
                     log("Running...");
                     run_clone.store(true, Ordering::Relaxed);
                 } else if run_clone.load(Ordering::Relaxed) {
                     log("Stopped running...");
+                    hotkey_queue_clone.lock().unwrap().clear();
                     run_clone.store(false, Ordering::Relaxed);
                 }
                 continue;
@@ -97,75 +388,239 @@ fn main() -> iced::Result {
 
             // Record events
             if record_clone.load(Ordering::Relaxed) && !run_clone.load(Ordering::Relaxed) {
-                events_clone.lock().unwrap().push(event);
+                let mut script = script_clone.lock().unwrap();
+                if script.events.is_empty() {
+                    window_tracker.reset();
+                }
+
+                // Note which window the following events happen in
+                let throttle = matches!(event.event_type, EventType::MouseMove { .. });
+                if let Some(window) = window_tracker.changed(throttle) {
+                    script.events.push(SerializableEvent {
+                        time: event.time,
+                        event_type: SerializableEventType::ActiveWindow {
+                            title: window.title,
+                            class: window.class,
+                            geometry: window.geometry,
+                        },
+                    });
+                }
+
+                script.events.push(event.into());
             }
         }
     });
 
     let run_ref = Arc::clone(&run);
-    let events_ref = Arc::clone(&events);
+    let script_ref = Arc::clone(&script);
+    let playlist_ref = Arc::clone(&playlist);
+    let playlist_progress_ref = Arc::clone(&playlist_progress);
+    let part_ref = Arc::clone(&part);
+    let playhead_ref = Arc::clone(&playhead);
+    let hotkey_queue_ref = Arc::clone(&hotkey_queue);
     let infinite_loop_ref = Arc::clone(&infinite_loop);
     let loop_count_ref = Arc::clone(&loop_count);
     let delay_ref = Arc::clone(&delay);
+    let keyboard_layout_ref = Arc::clone(&keyboard_layout);
 
     thread::spawn(move || {
         event_loop(
-            events_ref,
+            script_ref,
+            playlist_ref,
+            playlist_progress_ref,
+            part_ref,
+            playhead_ref,
+            hotkey_queue_ref,
             run_ref,
             infinite_loop_ref,
             loop_count_ref,
             delay_ref,
+            keyboard_layout_ref,
+            keys.stop,
+        );
+    });
+
+    let scheduler_ref = Arc::clone(&scheduler);
+    let record_ref = Arc::clone(&record);
+    let run_ref = Arc::clone(&run);
+    let delay_ref = Arc::clone(&delay);
+    let keyboard_layout_ref = Arc::clone(&keyboard_layout);
+    let playlist_progress_ref = Arc::clone(&playlist_progress);
+
+    thread::spawn(move || {
+        // Scheduled runs wait while recording, and a run from the GUI takes a slot
+        let busy = move || {
+            (!record_ref.load(Ordering::Relaxed)).then(|| run_ref.load(Ordering::Relaxed) as usize)
+        };
+        schedule_loop(
+            scheduler_ref,
+            busy,
+            delay_ref,
+            keyboard_layout_ref,
+            keys.stop,
+            playlist_progress_ref,
         );
     });
 
     // Start event listener - platform specific
-    spawn_event_listener(tx);
+    spawn_event_listener(tx, grab_event);
 
-    ScriptorApp::run(Settings {
+    // Back where it was, unless that's off every screen now
+    let position = settings.window.filter(|window| on_screen(*window)).map_or(
+        iced::window::Position::default(),
+        |window| {
+            iced::window::Position::Specific(iced::Point::new(window.x as f32, window.y as f32))
+        },
+    );
+
+    let flags = AppFlags {
+        script,
+        playlist,
+        playlist_progress,
+        part,
+        playhead,
+        record,
+        run,
+        infinite_loop,
+        loop_count,
+        delay,
+        simplify_on_record,
+        halt_actions,
+        keyboard_layout,
+        scheduler,
+        hotkeys,
+        expansions,
+        expand_text,
+        grab_config,
+        remap,
+        settings,
+        settings_writable,
+    };
+    ScriptorApp::run(iced::Settings {
         window: iced::window::Settings {
-            size: iced::Size::new(200.0, 270.0),
-            resizable: false,
+            size: iced::Size::new(CONTROLS_WIDTH, WINDOW_HEIGHT),
+            min_size: Some(iced::Size::new(CONTROLS_WIDTH, WINDOW_HEIGHT)),
+            position,
+            resizable: true,
             icon: load_icon(),
             decorations: true,
-            max_size: Some(iced::Size::new(200.0, 270.0)),
             ..Default::default()
         },
-        flags: AppFlags {
-            events,
-            record,
-            run,
-            infinite_loop,
-            loop_count,
-            delay,
-            halt_actions,
-        },
-        ..Settings::default()
+        ..iced::Settings::with_flags(flags)
     })
 }
 
+// What the grab callback keeps from one input event to the next
+struct Grabbing {
+    chord_tracker: ChordTracker,
+    remapper: Remapper,
+    // Keys whose press was hidden or swallowed, and what happens to them until
+    // they're released
+    held_back: Vec<(ChordKey, Grab)>,
+    config: GrabConfig,
+    updates: Receiver<GrabConfig>,
+}
+
+// The parts of the config files the grab callback needs
 #[derive(Default)]
+struct GrabConfig {
+    hotkeys: Vec<Chord>,
+    remaps: Remaps,
+}
+
+impl GrabConfig {
+    fn new(hotkeys: &Hotkeys, remaps: Remaps) -> Self {
+        GrabConfig {
+            hotkeys: hotkeys.hotkeys.iter().map(|hotkey| hotkey.chord).collect(),
+            remaps,
+        }
+    }
+}
+
 struct AppFlags {
-    events: Arc<Mutex<Vec<Event>>>,
+    script: Arc<Mutex<Script>>,
+    playlist: Arc<Mutex<Option<Playlist>>>,
+    playlist_progress: Arc<Mutex<Option<PlaylistProgress>>>,
+    part: Arc<Mutex<Option<Script>>>,
+    playhead: Arc<Mutex<Option<SystemTime>>>,
     record: Arc<AtomicBool>,
     run: Arc<AtomicBool>,
     infinite_loop: Arc<AtomicBool>,
     loop_count: Arc<Mutex<i32>>,
     delay: Arc<AtomicBool>,
+    simplify_on_record: Arc<AtomicBool>,
     halt_actions: Arc<AtomicBool>,
+    keyboard_layout: Arc<Mutex<KeyboardLayout>>,
+    scheduler: Arc<Mutex<Scheduler>>,
+    hotkeys: Arc<Mutex<Hotkeys>>,
+    expansions: Arc<Mutex<Expansions>>,
+    expand_text: Arc<AtomicBool>,
+    grab_config: Sender<GrabConfig>,
+    remap: Arc<AtomicBool>,
+    settings: Settings,
+    settings_writable: bool,
 }
 
 struct ScriptorApp {
-    events: Arc<Mutex<Vec<Event>>>,
+    script: Arc<Mutex<Script>>,
+    playlist: Arc<Mutex<Option<Playlist>>>,
+    playlist_progress: Arc<Mutex<Option<PlaylistProgress>>>,
+    part: Arc<Mutex<Option<Script>>>,
+    playhead: Arc<Mutex<Option<SystemTime>>>,
     record: Arc<AtomicBool>,
     run: Arc<AtomicBool>,
     infinite_loop: Arc<AtomicBool>,
     loop_count: Arc<Mutex<i32>>,
     delay: Arc<AtomicBool>,
+    simplify_on_record: Arc<AtomicBool>,
     halt_actions: Arc<AtomicBool>,
+    keyboard_layout: Arc<Mutex<KeyboardLayout>>,
+    scheduler: Arc<Mutex<Scheduler>>,
+    hotkeys: Arc<Mutex<Hotkeys>>,
+    expansions: Arc<Mutex<Expansions>>,
+    expand_text: Arc<AtomicBool>,
+    // Sends reloaded hotkeys and remaps to the grab callback
+    grab_config: Sender<GrabConfig>,
+    remap: Arc<AtomicBool>,
+    settings: Settings,
+    // Saved on the next tick, so a window being dragged isn't saved every pixel
+    settings_changed: bool,
+    // False when settings.toml failed to load
+    settings_writable: bool,
     script_file_name: String,
     minimize_on_action: bool,
     infinite_loop_checked: bool,
     delay_checked: bool,
+    mouse_anchor: MouseAnchor,
+    simplify_checked: bool,
+    expand_text_checked: bool,
+    screen_scaling: ScreenScaling,
+    interpolation: Interpolation,
+    recorded_layout: KeyboardLayout,
+    keyboard_layout_value: KeyboardLayout,
+    data_file_name: String,
+    playlist_file_name: String,
+    clicker: AutoClicker,
+    grid: GridPattern,
+    editor_open: bool,
+    // Kept when the event table opens or closes, the user may have resized it
+    window_height: f32,
+    editor: EventEditor,
+    editor_page: usize,
+    editor_selected: Option<usize>,
+    editor_dragging: Option<usize>,
+    // What's being typed into a table cell, it's applied on Enter
+    editor_draft: Option<(usize, EventField, String)>,
+    editor_new_event: NewEvent,
+    // Open windows offered for the selected Wait Window step
+    editor_windows: Vec<WindowMatcher>,
+    // Stretch of the timeline shown, all of it when None
+    timeline_view: Option<(Duration, Duration)>,
+    timeline_selection: Option<(Duration, Duration)>,
+    timeline_cursor: Option<Duration>,
+    timeline_stretch: f64,
+    // Show the mouse path in place of the timeline
+    path_open: bool,
     loop_count_value: i32,
     was_recording: bool,
     was_running: bool,
@@ -179,16 +634,402 @@ enum Message {
     Run,
     Open,
     Save,
+    OpenData,
+    ClearData,
+    OpenPlaylist,
+    ClearPlaylist,
+    ReloadConfig,
+    Simplify,
     InfiniteLoopToggled(bool),
     DelayToggled(bool),
     MinimizeToggled(bool),
+    MouseAnchorSelected(MouseAnchor),
+    SimplifyToggled(bool),
+    ExpandTextToggled(bool),
+    RemapToggled(bool),
+    ScreenScalingSelected(ScreenScaling),
+    CurveSelected(Curve),
+    SampleRateInputChanged(String),
+    RecordedLayoutSelected(KeyboardLayout),
+    KeyboardLayoutSelected(KeyboardLayout),
+    ClickButtonSelected(SerializableButton),
+    ClickKindSelected(ClickKind),
+    ClickIntervalInputChanged(String),
+    ClickCountInputChanged(String),
+    MakeClicks,
+    GridRowsInputChanged(String),
+    GridColumnsInputChanged(String),
+    GridSpacingInputChanged(String),
+    GridOrderSelected(GridOrder),
+    MakeGrid,
+    ToggleEditor,
+    EditorPage(usize),
+    EditorSelect(usize),
+    EditorDrag(usize),
+    EditorDrop(usize),
+    EditorInput(usize, EventField, String),
+    EditorSubmit,
+    EditorDelete(usize),
+    EditorDuplicate(usize),
+    EditorNewEventSelected(NewEvent),
+    EditorInsert,
+    EditorPickTemplate(usize),
+    EditorTemplatePicked(usize, Option<std::path::PathBuf>),
+    EditorWindowPicked(usize, WindowMatcher),
+    EditorPickScript(usize),
+    EditorScriptPicked(usize, Option<std::path::PathBuf>),
+    EditorUndo,
+    EditorRedo,
+    TimelineView(Duration, Duration),
+    TimelineFit,
+    TimelineSelect(Duration, Duration),
+    TimelineScrub(Duration),
+    TimelineDelete,
+    TimelineStretchInputChanged(String),
+    TimelineStretch,
+    TimelinePlay,
+    TogglePath,
+    WindowMoved(i32, i32),
+    WindowResized(f32),
     LoopCountChanged(i32),
     LoopCountInputChanged(String),
     FileOpened(Option<std::path::PathBuf>),
     FileSaved(Option<std::path::PathBuf>),
+    DataOpened(Option<std::path::PathBuf>),
+    PlaylistOpened(Option<std::path::PathBuf>),
     Tick,
 }
 
+impl ScriptorApp {
+    // Replace the loaded script with one made by a generator
+    fn use_generated(&mut self, name: &str, script: Result<Script, String>) {
+        if self.record.load(Ordering::Relaxed) || self.run.load(Ordering::Relaxed) {
+            return;
+        }
+        match script {
+            Ok(mut script) => {
+                script.keyboard_layout = self.keyboard_layout_value;
+                self.mouse_anchor = script.mouse_anchor;
+                self.screen_scaling = script.screen_scaling;
+                self.interpolation = script.interpolation;
+                self.recorded_layout = script.keyboard_layout;
+                self.data_file_name = String::new();
+                log(&format!("Made a script of {} events", script.events.len()));
+                *self.script.lock().unwrap() = script;
+                self.reset_editor();
+                self.script_file_name = name.to_string();
+            }
+            Err(e) => log(&format!("Error making script: {}", e)),
+        }
+    }
+
+    // The table only changes the script while it isn't being recorded or played
+    fn can_edit(&self) -> bool {
+        !self.record.load(Ordering::Relaxed) && !self.run.load(Ordering::Relaxed)
+    }
+
+    // Start the table over for a script that was replaced
+    fn reset_editor(&mut self) {
+        self.editor.clear();
+        self.editor_page = 0;
+        self.editor_selected = None;
+        self.editor_dragging = None;
+        self.editor_draft = None;
+        self.timeline_view = None;
+        self.timeline_selection = None;
+        self.timeline_cursor = None;
+    }
+
+    // How far into the loaded script playback is
+    fn playhead(&self, events: &[SerializableEvent]) -> Option<Duration> {
+        let playhead = (*self.playhead.lock().unwrap())?;
+        playhead.duration_since(events.first()?.time).ok()
+    }
+
+    fn timeline(&self) -> Element<'_, Message> {
+        let script = self.script.lock().unwrap();
+        let timeline = Timeline::new(&script.events);
+        let playhead = self.playhead(&script.events);
+        drop(script);
+        let (start, end) = self
+            .timeline_view
+            .unwrap_or((Duration::ZERO, timeline.length));
+        let selection = self.timeline_selection.filter(|(from, to)| from < to);
+
+        let selected = match selection {
+            Some((from, to)) => format!("{:.3}s - {:.3}s", from.as_secs_f64(), to.as_secs_f64()),
+            None => String::from("Drag to select"),
+        };
+        let toolbar = row![
+            text(selected).size(12).width(Length::Fill),
+            button(text("Delete").size(12))
+                .on_press_maybe(selection.map(|_| Message::TimelineDelete))
+                .padding([2, 5]),
+            button(text("Stretch x").size(12))
+                .on_press_maybe(selection.map(|_| Message::TimelineStretch))
+                .padding([2, 5]),
+            text_input("2.0", &self.timeline_stretch.to_string())
+                .on_input(Message::TimelineStretchInputChanged)
+                .width(Length::Fixed(40.0))
+                .size(12)
+                .padding([2, 4]),
+            button(text("Play").size(12))
+                .on_press_maybe(selection.map(|_| Message::TimelinePlay))
+                .padding([2, 5]),
+            button(text("Fit").size(12))
+                .on_press(Message::TimelineFit)
+                .padding([2, 5]),
+            button(text("Path").size(12))
+                .on_press(Message::TogglePath)
+                .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let chart = TimelineChart {
+            timeline,
+            start,
+            end,
+            selection,
+            cursor: playhead.or(self.timeline_cursor),
+        };
+        column![
+            toolbar,
+            canvas(chart)
+                .width(Length::Fill)
+                .height(Length::Fixed(TIMELINE_HEIGHT)),
+        ]
+        .spacing(4)
+        .into()
+    }
+
+    // The script's mouse moves and clicks over the screen it was recorded on
+    fn mouse_path(&self) -> Element<'_, Message> {
+        let script = self.script.lock().unwrap();
+        let path = MousePath::new(&script.events);
+        let playhead = self.playhead(&script.events);
+        let monitors = script
+            .screen
+            .clone()
+            .or_else(ScreenGeometry::current)
+            .map(|screen| screen.monitors)
+            .unwrap_or_default();
+        drop(script);
+
+        let toolbar = row![
+            text(format!(
+                "{} moves, {} clicks",
+                path.moves.len(),
+                path.clicks.len()
+            ))
+            .size(12)
+            .width(Length::Fill),
+            text("Left").size(12).style(chart::LEFT_COLOR),
+            text("Right").size(12).style(chart::RIGHT_COLOR),
+            text("Other").size(12).style(chart::OTHER_COLOR),
+            button(text("Timeline").size(12))
+                .on_press(Message::TogglePath)
+                .padding([2, 5]),
+        ]
+        .spacing(8)
+        .align_items(Alignment::Center);
+
+        let chart = PathChart {
+            path,
+            monitors,
+            playhead,
+        };
+        column![
+            toolbar,
+            canvas(chart)
+                .width(Length::Fill)
+                .height(Length::Fixed(TIMELINE_HEIGHT)),
+        ]
+        .spacing(4)
+        .into()
+    }
+
+    // What the selected step needs picked from outside the table
+    fn step_picker(&self, events: &[SerializableEvent]) -> Element<'_, Message> {
+        let Some(index) = self.editor_selected else {
+            return row![].into();
+        };
+        match events.get(index).map(|event| &event.event_type) {
+            Some(SerializableEventType::WaitForImage { .. }) => button(text("Template…").size(12))
+                .on_press(Message::EditorPickTemplate(index))
+                .padding([2, 5])
+                .into(),
+            Some(SerializableEventType::WaitForWindow { .. }) => pick_list(
+                &self.editor_windows[..],
+                None::<WindowMatcher>,
+                move |matcher| Message::EditorWindowPicked(index, matcher),
+            )
+            .placeholder("Window…")
+            .width(Length::Fixed(160.0))
+            .text_size(12)
+            .padding([2, 5])
+            .into(),
+            Some(SerializableEventType::Call { .. }) => button(text("Script…").size(12))
+                .on_press(Message::EditorPickScript(index))
+                .padding([2, 5])
+                .into(),
+            _ => row![].into(),
+        }
+    }
+
+    // Offer the windows open right now, by title and class
+    fn refresh_windows(&mut self) {
+        self.editor_windows = match SystemWindows::new().windows() {
+            Ok(windows) => windows
+                .into_iter()
+                .filter(|window| !window.title.is_empty())
+                .map(|window| WindowMatcher {
+                    title: Some(window.title),
+                    class: window.class.last().cloned(),
+                })
+                .collect(),
+            Err(e) => {
+                log(&format!("Could not list open windows: {}", e));
+                Vec::new()
+            }
+        };
+    }
+
+    fn event_table(&self) -> Element<'_, Message> {
+        let script = self.script.lock().unwrap();
+        let events = &script.events;
+        let pages = events.len().div_ceil(EVENT_PAGE_ROWS).max(1);
+        let page = self.editor_page.min(pages - 1);
+
+        let toolbar = row![
+            button(text("Undo").size(12))
+                .on_press_maybe(self.editor.can_undo().then_some(Message::EditorUndo))
+                .padding([2, 5]),
+            button(text("Redo").size(12))
+                .on_press_maybe(self.editor.can_redo().then_some(Message::EditorRedo))
+                .padding([2, 5]),
+            pick_list(
+                &NewEvent::ALL[..],
+                Some(self.editor_new_event),
+                Message::EditorNewEventSelected,
+            )
+            .width(Length::Fixed(120.0))
+            .text_size(12)
+            .padding([2, 5]),
+            button(text("Insert").size(12))
+                .on_press(Message::EditorInsert)
+                .padding([2, 5]),
+            self.step_picker(events),
+            text(format!("{} events", events.len()))
+                .size(12)
+                .width(Length::Fill),
+            button(text("◀").size(12))
+                .on_press_maybe((page > 0).then(|| Message::EditorPage(page - 1)))
+                .padding([2, 5]),
+            text(format!("{}/{}", page + 1, pages)).size(12),
+            button(text("▶").size(12))
+                .on_press_maybe((page + 1 < pages).then(|| Message::EditorPage(page + 1)))
+                .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let header = row![
+            text("").width(Length::Fixed(14.0)),
+            text("#").size(12).width(Length::Fixed(40.0)),
+            text("ms").size(12).width(Length::Fixed(60.0)),
+            text("Type").size(12).width(Length::Fixed(90.0)),
+            text("Key / Button / Text").size(12).width(Length::Fill),
+            text("X").size(12).width(Length::Fixed(55.0)),
+            text("Y").size(12).width(Length::Fixed(55.0)),
+            text("").width(Length::Fixed(48.0)),
+        ]
+        .spacing(4);
+
+        let highlight = iced::Color::from_rgb(0.95, 0.75, 0.4);
+        let first = events.first().map(|event| event.time);
+        let mut rows = Column::new().spacing(2);
+        for (index, event) in events
+            .iter()
+            .enumerate()
+            .skip(page * EVENT_PAGE_ROWS)
+            .take(EVENT_PAGE_ROWS)
+        {
+            let event_row = EventRow::new(event, first.unwrap_or(event.time));
+            let cell = |field: EventField, width: Length| -> Element<'_, Message> {
+                match event_row.field(field) {
+                    Some(value) => {
+                        let value = match &self.editor_draft {
+                            Some((i, f, draft)) if *i == index && *f == field => draft.clone(),
+                            _ => value,
+                        };
+                        text_input("", &value)
+                            .on_input(move |value| Message::EditorInput(index, field, value))
+                            .on_submit(Message::EditorSubmit)
+                            .width(width)
+                            .size(12)
+                            .padding([1, 4])
+                            .into()
+                    }
+                    None => text(match field {
+                        EventField::Detail => event_row.detail.clone(),
+                        _ => String::new(),
+                    })
+                    .size(12)
+                    .width(width)
+                    .into(),
+                }
+            };
+
+            let mut number = text(index + 1).size(12).width(Length::Fixed(40.0));
+            if self.editor_selected == Some(index) || self.editor_dragging == Some(index) {
+                number = number.style(highlight);
+            }
+            let line = row![
+                mouse_area(text("≡").size(12).width(Length::Fixed(14.0)))
+                    .on_press(Message::EditorDrag(index)),
+                number,
+                cell(EventField::Offset, Length::Fixed(60.0)),
+                text(event_row.kind).size(12).width(Length::Fixed(90.0)),
+                cell(EventField::Detail, Length::Fill),
+                cell(EventField::X, Length::Fixed(55.0)),
+                cell(EventField::Y, Length::Fixed(55.0)),
+                button(text("⧉").size(12))
+                    .on_press(Message::EditorDuplicate(index))
+                    .padding([1, 4]),
+                button(text("×").size(12))
+                    .on_press(Message::EditorDelete(index))
+                    .padding([1, 4]),
+            ]
+            .spacing(4)
+            .align_items(Alignment::Center);
+
+            // Dragging a row by its handle and letting go over another moves it there
+            rows = rows.push(
+                mouse_area(line)
+                    .on_press(Message::EditorSelect(index))
+                    .on_release(Message::EditorDrop(index)),
+            );
+        }
+
+        drop(script);
+        column![
+            if self.path_open {
+                self.mouse_path()
+            } else {
+                self.timeline()
+            },
+            toolbar,
+            header,
+            scrollable(rows).height(Length::Fill)
+        ]
+        .spacing(4)
+        .padding([6, 8, 6, 8])
+        .width(Length::Fill)
+        .into()
+    }
+}
+
 impl Application for ScriptorApp {
     type Executor = iced::executor::Default;
     type Message = Message;
@@ -196,24 +1037,71 @@ impl Application for ScriptorApp {
     type Flags = AppFlags;
 
     fn new(flags: AppFlags) -> (Self, Command<Message>) {
+        let settings = flags.settings;
+        let mut startup = vec![Command::perform(async {}, |_| Message::Tick)];
+        if let Some(path) = settings.last_script.clone().filter(|path| path.exists()) {
+            startup.push(Command::perform(async {}, move |_| {
+                Message::FileOpened(Some(path))
+            }));
+        }
         (
             ScriptorApp {
-                events: flags.events,
+                script: flags.script,
+                playlist: flags.playlist,
+                playlist_progress: flags.playlist_progress,
+                part: flags.part,
+                playhead: flags.playhead,
                 record: flags.record,
                 run: flags.run,
                 infinite_loop: flags.infinite_loop,
                 loop_count: flags.loop_count,
                 delay: flags.delay,
+                simplify_on_record: flags.simplify_on_record,
                 halt_actions: flags.halt_actions,
+                keyboard_layout: flags.keyboard_layout,
+                scheduler: flags.scheduler,
+                hotkeys: flags.hotkeys,
+                expansions: flags.expansions,
+                expand_text: flags.expand_text,
+                grab_config: flags.grab_config,
+                remap: flags.remap,
                 script_file_name: String::new(),
-                minimize_on_action: false,
-                infinite_loop_checked: true,
-                delay_checked: true,
-                loop_count_value: 1,
+                minimize_on_action: settings.minimize,
+                infinite_loop_checked: settings.infinite_loop,
+                delay_checked: settings.natural_delay,
+                mouse_anchor: MouseAnchor::Absolute,
+                simplify_checked: false,
+                expand_text_checked: false,
+                screen_scaling: ScreenScaling::None,
+                interpolation: Interpolation::default(),
+                recorded_layout: KeyboardLayout::default(),
+                keyboard_layout_value: settings.keyboard_layout,
+                data_file_name: String::new(),
+                playlist_file_name: String::new(),
+                clicker: AutoClicker::default(),
+                grid: GridPattern::default(),
+                editor_open: false,
+                window_height: WINDOW_HEIGHT,
+                editor: EventEditor::new(),
+                editor_page: 0,
+                editor_selected: None,
+                editor_dragging: None,
+                editor_draft: None,
+                editor_new_event: NewEvent::default(),
+                editor_windows: Vec::new(),
+                timeline_view: None,
+                timeline_selection: None,
+                timeline_cursor: None,
+                timeline_stretch: 2.0,
+                path_open: false,
+                loop_count_value: settings.loop_count,
+                settings,
+                settings_changed: false,
+                settings_writable: flags.settings_writable,
                 was_recording: false,
                 was_running: false,
             },
-            Command::perform(async {}, |_| Message::Tick),
+            Command::batch(startup),
         )
     }
 
@@ -228,7 +1116,11 @@ impl Application for ScriptorApp {
                     self.script_file_name = String::new();
                     log("Recording...");
                     self.record.store(true, Ordering::Relaxed);
-                    self.events.lock().unwrap().clear();
+                    self.recorded_layout = self.keyboard_layout_value;
+                    self.script
+                        .lock()
+                        .unwrap()
+                        .reset_for_recording(self.keyboard_layout_value);
 
                     if self.minimize_on_action {
                         return iced::window::minimize(iced::window::Id::MAIN, true);
@@ -240,6 +1132,9 @@ impl Application for ScriptorApp {
                 if self.record.load(Ordering::Relaxed) {
                     log("Stopped recording...");
                     self.record.store(false, Ordering::Relaxed);
+                    if self.simplify_checked {
+                        simplify_script(&self.script);
+                    }
                 }
                 Command::none()
             }
@@ -261,6 +1156,7 @@ impl Application for ScriptorApp {
                 async {
                     rfd::AsyncFileDialog::new()
                         .add_filter("Script Files", &["bin"])
+                        .add_filter("Rhai Scripts", &["rhai"])
                         .pick_file()
                         .await
                         .map(|f| f.path().to_path_buf())
@@ -277,39 +1173,408 @@ impl Application for ScriptorApp {
                 },
                 Message::FileSaved,
             ),
+            Message::OpenData => Command::perform(
+                async {
+                    rfd::AsyncFileDialog::new()
+                        .add_filter("CSV Files", &["csv"])
+                        .pick_file()
+                        .await
+                        .map(|f| f.path().to_path_buf())
+                },
+                Message::DataOpened,
+            ),
+            Message::OpenPlaylist => Command::perform(
+                async {
+                    rfd::AsyncFileDialog::new()
+                        .add_filter("Playlists", &["toml"])
+                        .pick_file()
+                        .await
+                        .map(|f| f.path().to_path_buf())
+                },
+                Message::PlaylistOpened,
+            ),
+            Message::PlaylistOpened(path) => {
+                if let Some(path) = path {
+                    match Playlist::load(&path) {
+                        Ok(playlist) => {
+                            log(&format!(
+                                "Loaded playlist with {} items",
+                                playlist.items.len()
+                            ));
+                            self.playlist_file_name = short_file_name(&path);
+                            *self.playlist.lock().unwrap() = Some(playlist);
+                        }
+                        Err(e) => log(&format!("Error opening playlist: {}", e)),
+                    }
+                }
+                Command::none()
+            }
+            Message::ReloadConfig => {
+                let hotkeys = load_hotkeys(&self.settings.keys);
+                let _ = self
+                    .grab_config
+                    .send(GrabConfig::new(&hotkeys, load_remaps(&self.settings.keys)));
+                *self.hotkeys.lock().unwrap() = hotkeys;
+                *self.expansions.lock().unwrap() =
+                    load_expansions(&self.settings.keys, self.keyboard_layout_value);
+                let mut scheduler = self.scheduler.lock().unwrap();
+                match scheduler.reload() {
+                    Ok(()) => log(&format!(
+                        "Loaded {} schedules",
+                        scheduler.schedules.schedules.len()
+                    )),
+                    Err(e) => log(&format!("Error loading schedules: {}", e)),
+                }
+                Command::none()
+            }
+            Message::ClearPlaylist => {
+                *self.playlist.lock().unwrap() = None;
+                self.playlist_file_name.clear();
+                Command::none()
+            }
+            Message::DataOpened(path) => {
+                if let Some(path) = path {
+                    // Read it once now so a broken file is reported straight away
+                    match DataTable::load(&path) {
+                        Ok(data) => {
+                            log(&format!(
+                                "Loaded {} rows with columns {}",
+                                data.rows.len(),
+                                data.headers.join(", ")
+                            ));
+                            self.data_file_name = short_file_name(&path);
+                            self.script.lock().unwrap().data_source = Some(path);
+                        }
+                        Err(e) => log(&format!("Error: {}", e)),
+                    }
+                }
+                Command::none()
+            }
+            Message::ClearData => {
+                self.data_file_name = String::new();
+                self.script.lock().unwrap().data_source = None;
+                Command::none()
+            }
+            Message::Simplify => {
+                if !self.record.load(Ordering::Relaxed) && !self.run.load(Ordering::Relaxed) {
+                    simplify_script(&self.script);
+                    self.reset_editor();
+                }
+                Command::none()
+            }
+            Message::ToggleEditor => {
+                self.editor_open = !self.editor_open;
+                let width = if self.editor_open {
+                    CONTROLS_WIDTH + EDITOR_WIDTH
+                } else {
+                    CONTROLS_WIDTH
+                };
+                iced::window::resize(
+                    iced::window::Id::MAIN,
+                    iced::Size::new(width, self.window_height),
+                )
+            }
+            Message::EditorPage(page) => {
+                self.editor_page = page;
+                self.editor_draft = None;
+                Command::none()
+            }
+            Message::EditorSelect(index) => {
+                self.editor_selected = Some(index);
+                let script = self.script.lock().unwrap();
+                let event_type = script.events.get(index).map(|event| &event.event_type);
+                let waits_for_window = matches!(
+                    event_type,
+                    Some(SerializableEventType::WaitForWindow { .. })
+                );
+                drop(script);
+                if waits_for_window {
+                    self.refresh_windows();
+                }
+                Command::none()
+            }
+            Message::EditorDrag(index) => {
+                self.editor_selected = Some(index);
+                self.editor_dragging = Some(index);
+                Command::none()
+            }
+            Message::EditorDrop(index) => {
+                if let Some(from) = self.editor_dragging.take() {
+                    if self.can_edit() && from != index {
+                        let mut script = self.script.lock().unwrap();
+                        self.editor.move_event(&mut script.events, from, index);
+                        self.editor_selected = Some(index);
+                    }
+                }
+                Command::none()
+            }
+            Message::EditorInput(index, field, value) => {
+                self.editor_draft = Some((index, field, value));
+                Command::none()
+            }
+            Message::EditorSubmit => {
+                if let Some((index, field, value)) = self.editor_draft.take() {
+                    if self.can_edit() {
+                        let mut script = self.script.lock().unwrap();
+                        let result = self.editor.edit(&mut script.events, index, field, &value);
+                        if let Err(e) = result {
+                            log(&format!("Error editing event: {}", e));
+                        }
+                    }
+                }
+                Command::none()
+            }
+            Message::EditorDelete(index) => {
+                if self.can_edit() {
+                    let mut script = self.script.lock().unwrap();
+                    self.editor.delete(&mut script.events, index);
+                    self.editor_draft = None;
+                }
+                Command::none()
+            }
+            Message::EditorDuplicate(index) => {
+                if self.can_edit() {
+                    let mut script = self.script.lock().unwrap();
+                    self.editor.duplicate(&mut script.events, index);
+                    self.editor_draft = None;
+                }
+                Command::none()
+            }
+            Message::EditorNewEventSelected(value) => {
+                self.editor_new_event = value;
+                Command::none()
+            }
+            Message::EditorInsert => {
+                if !self.can_edit() {
+                    return Command::none();
+                }
+                // After the selected row, or at the end
+                let mut script = self.script.lock().unwrap();
+                let index = self
+                    .editor_selected
+                    .map_or(script.events.len(), |selected| selected + 1)
+                    .min(script.events.len());
+                self.editor
+                    .insert(&mut script.events, index, self.editor_new_event);
+                self.editor_selected = Some(index);
+                self.editor_page = index / EVENT_PAGE_ROWS;
+                self.editor_draft = None;
+                drop(script);
+
+                // Steps that need a file ask for it straight away, and the windows
+                // a new Wait Window step can pick from are listed
+                match self.editor_new_event {
+                    NewEvent::WaitForImage => self.update(Message::EditorPickTemplate(index)),
+                    NewEvent::Call => self.update(Message::EditorPickScript(index)),
+                    NewEvent::WaitForWindow => {
+                        self.refresh_windows();
+                        Command::none()
+                    }
+                    _ => Command::none(),
+                }
+            }
+            Message::EditorPickTemplate(index) => Command::perform(
+                async {
+                    rfd::AsyncFileDialog::new()
+                        .add_filter("Images", &["png", "jpg", "jpeg", "bmp"])
+                        .pick_file()
+                        .await
+                        .map(|f| f.path().to_path_buf())
+                },
+                move |path| Message::EditorTemplatePicked(index, path),
+            ),
+            Message::EditorTemplatePicked(index, path) => {
+                if let Some(path) = path.filter(|_| self.can_edit()) {
+                    let mut script = self.script.lock().unwrap();
+                    let result =
+                        std::fs::read(&path)
+                            .map_err(|e| e.to_string())
+                            .and_then(|template| {
+                                self.editor
+                                    .set_template(&mut script.events, index, template)
+                            });
+                    if let Err(e) = result {
+                        log(&format!("Error loading {}: {}", path.display(), e));
+                    }
+                }
+                Command::none()
+            }
+            Message::EditorPickScript(index) => Command::perform(
+                async {
+                    rfd::AsyncFileDialog::new()
+                        .add_filter("Script Files", &["bin"])
+                        .pick_file()
+                        .await
+                        .map(|f| f.path().to_path_buf())
+                },
+                move |path| Message::EditorScriptPicked(index, path),
+            ),
+            Message::EditorScriptPicked(index, path) => {
+                if let Some(path) = path.filter(|_| self.can_edit()) {
+                    let mut script = self.script.lock().unwrap();
+                    // Kept next to the calling script when it has been saved
+                    let path = match &script.path {
+                        Some(base) => relative_path(base, &path),
+                        None => path,
+                    };
+                    if let Err(e) = self.editor.set_call_script(&mut script.events, index, path) {
+                        log(&format!("Error editing event: {}", e));
+                    }
+                }
+                Command::none()
+            }
+            Message::EditorWindowPicked(index, matcher) => {
+                if self.can_edit() {
+                    let mut script = self.script.lock().unwrap();
+                    if let Err(e) = self.editor.set_window(&mut script.events, index, matcher) {
+                        log(&format!("Error editing event: {}", e));
+                    }
+                }
+                Command::none()
+            }
+            Message::EditorUndo => {
+                if self.can_edit() {
+                    self.editor.undo(&mut self.script.lock().unwrap().events);
+                    self.editor_draft = None;
+                }
+                Command::none()
+            }
+            Message::EditorRedo => {
+                if self.can_edit() {
+                    self.editor.redo(&mut self.script.lock().unwrap().events);
+                    self.editor_draft = None;
+                }
+                Command::none()
+            }
+            Message::TimelineView(start, end) => {
+                self.timeline_view = Some((start, end));
+                Command::none()
+            }
+            Message::TimelineFit => {
+                self.timeline_view = None;
+                Command::none()
+            }
+            Message::TimelineSelect(from, to) => {
+                self.timeline_selection = Some((from, to));
+                Command::none()
+            }
+            Message::TimelineScrub(time) => {
+                // Show the event nearest the cursor in the table
+                self.timeline_cursor = Some(time);
+                let script = self.script.lock().unwrap();
+                let first = script.events.first().map(|event| event.time);
+                let nearest = script
+                    .events
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, event)| {
+                        let offset = first
+                            .and_then(|first| event.time.duration_since(first).ok())
+                            .unwrap_or_default();
+                        offset.abs_diff(time)
+                    })
+                    .map(|(index, _)| index);
+                if let Some(index) = nearest {
+                    self.editor_selected = Some(index);
+                    self.editor_page = index / EVENT_PAGE_ROWS;
+                }
+                Command::none()
+            }
+            Message::TimelineDelete => {
+                if let Some((from, to)) = self.timeline_selection {
+                    if self.can_edit() {
+                        let mut script = self.script.lock().unwrap();
+                        self.editor.delete_range(&mut script.events, from, to);
+                        self.timeline_selection = None;
+                        self.timeline_view = None;
+                        self.editor_draft = None;
+                    }
+                }
+                Command::none()
+            }
+            Message::TimelineStretchInputChanged(input) => {
+                if let Ok(value) = input.parse::<f64>() {
+                    if value.is_finite() && value > 0.0 {
+                        self.timeline_stretch = value;
+                    }
+                }
+                Command::none()
+            }
+            Message::TimelineStretch => {
+                if let Some((from, to)) = self.timeline_selection {
+                    if self.can_edit() {
+                        let mut script = self.script.lock().unwrap();
+                        let factor = self.timeline_stretch;
+                        match self
+                            .editor
+                            .stretch_range(&mut script.events, from, to, factor)
+                        {
+                            Ok(()) => {
+                                self.timeline_selection =
+                                    Some((from, from + (to - from).mul_f64(factor)));
+                                self.timeline_view = None;
+                                self.editor_draft = None;
+                            }
+                            Err(e) => log(&format!("Error stretching events: {}", e)),
+                        }
+                    }
+                }
+                Command::none()
+            }
+            Message::TimelinePlay => {
+                if let Some((from, to)) = self.timeline_selection {
+                    if self.can_edit() {
+                        let mut part = self.script.lock().unwrap().clone();
+                        part.events = timeline::range_events(&part.events, from, to);
+                        if part.events.is_empty() {
+                            log("There aren't any events to run!");
+                            return Command::none();
+                        }
+                        log("Running selection...");
+                        *self.part.lock().unwrap() = Some(part);
+                        self.run.store(true, Ordering::Relaxed);
+                    }
+                }
+                Command::none()
+            }
+            Message::TogglePath => {
+                self.path_open = !self.path_open;
+                Command::none()
+            }
+            Message::WindowMoved(x, y) => {
+                let window = WindowPosition { x, y };
+                // Minimizing moves the window far off screen on some systems
+                if on_screen(window) {
+                    self.settings.window = Some(window);
+                    self.settings_changed = true;
+                }
+                Command::none()
+            }
+            Message::WindowResized(height) => {
+                self.window_height = height;
+                Command::none()
+            }
             Message::FileOpened(path) => {
                 if let Some(path) = path {
                     self.halt_actions.store(true, Ordering::Relaxed);
 
-                    match File::open(&path) {
-                        Ok(mut file) => {
-                            let mut buffer = Vec::<u8>::new();
-                            if file.read_to_end(&mut buffer).is_ok() {
-                                // Deserialize as SerializableEvent and convert to Event
-                                if let Ok(decoded) =
-                                    bincode::deserialize::<Vec<SerializableEvent>>(&buffer)
-                                {
-                                    let events_converted: Vec<Event> =
-                                        decoded.into_iter().map(|e| e.into()).collect();
-
-                                    let mut events = self.events.lock().unwrap();
-                                    events.clear();
-                                    *events = events_converted;
-
-                                    let file_name =
-                                        path.file_name().unwrap().to_str().unwrap().to_string();
-
-                                    if file_name.len() > 12 {
-                                        self.script_file_name = format!("{}...", &file_name[0..12]);
-                                    } else {
-                                        self.script_file_name = file_name;
-                                    }
-                                } else {
-                                    log("Error: Could not deserialize file");
-                                }
-                            } else {
-                                log("Error: Could not read file");
-                            }
+                    match Script::load(&path) {
+                        Ok(script) => {
+                            self.mouse_anchor = script.mouse_anchor;
+                            self.screen_scaling = script.screen_scaling;
+                            self.interpolation = script.interpolation;
+                            self.recorded_layout = script.keyboard_layout;
+                            self.data_file_name = script
+                                .data_source
+                                .as_deref()
+                                .map(short_file_name)
+                                .unwrap_or_default();
+                            *self.script.lock().unwrap() = script;
+                            self.reset_editor();
+                            self.settings.last_script = Some(path.clone());
+                            self.settings_changed = true;
+
+                            self.script_file_name = short_file_name(&path);
                         }
                         Err(e) => {
                             log(&format!("Error opening file: {}", e));
@@ -328,29 +1593,17 @@ impl Application for ScriptorApp {
                         path.set_extension("bin");
                     }
 
-                    match File::create(&path) {
-                        Ok(mut file) => {
-                            let events = self.events.lock().unwrap();
-                            let serializable: Vec<SerializableEvent> =
-                                events.iter().map(|e| e.clone().into()).collect();
-                            let encoded: Vec<u8> = bincode::serialize(&serializable).unwrap();
-
-                            if file.write_all(&encoded).is_ok() {
-                                let file_name =
-                                    path.file_name().unwrap().to_str().unwrap().to_string();
-
-                                if file_name.len() > 12 {
-                                    self.script_file_name = format!("{}...", &file_name[0..12]);
-                                } else {
-                                    self.script_file_name = file_name;
-                                }
-                                log("File saved successfully");
-                            } else {
-                                log("Error: Could not write to file");
-                            }
+                    let result = self.script.lock().unwrap().save(&path);
+
+                    match result {
+                        Ok(()) => {
+                            self.settings.last_script = Some(path.clone());
+                            self.settings_changed = true;
+                            self.script_file_name = short_file_name(&path);
+                            log("File saved successfully");
                         }
                         Err(e) => {
-                            log(&format!("Error creating file: {}", e));
+                            log(&format!("Error saving file: {}", e));
                         }
                     }
 
@@ -361,21 +1614,151 @@ impl Application for ScriptorApp {
             Message::InfiniteLoopToggled(value) => {
                 self.infinite_loop_checked = value;
                 self.infinite_loop.store(value, Ordering::Relaxed);
+                self.settings.infinite_loop = value;
+                self.settings_changed = true;
                 Command::none()
             }
             Message::DelayToggled(value) => {
                 self.delay_checked = value;
                 self.delay.store(value, Ordering::Relaxed);
+                self.settings.natural_delay = value;
+                self.settings_changed = true;
                 Command::none()
             }
             Message::MinimizeToggled(value) => {
                 self.minimize_on_action = value;
+                self.settings.minimize = value;
+                self.settings_changed = true;
+                Command::none()
+            }
+            Message::MouseAnchorSelected(value) => {
+                self.mouse_anchor = value;
+                self.script.lock().unwrap().mouse_anchor = value;
+                Command::none()
+            }
+            Message::ExpandTextToggled(value) => {
+                self.expand_text_checked = value;
+                self.expand_text.store(value, Ordering::Relaxed);
+                Command::none()
+            }
+            Message::RemapToggled(value) => {
+                self.remap.store(value, Ordering::Relaxed);
+                Command::none()
+            }
+            Message::SimplifyToggled(value) => {
+                self.simplify_checked = value;
+                self.simplify_on_record.store(value, Ordering::Relaxed);
+                Command::none()
+            }
+            Message::ScreenScalingSelected(value) => {
+                self.screen_scaling = value;
+                self.script.lock().unwrap().screen_scaling = value;
+                Command::none()
+            }
+            Message::CurveSelected(value) => {
+                self.interpolation.curve = value;
+                self.script.lock().unwrap().interpolation = self.interpolation;
+                Command::none()
+            }
+            Message::SampleRateInputChanged(input) => {
+                if let Ok(value) = input.parse::<u32>() {
+                    if (1..=1000).contains(&value) {
+                        self.interpolation.sample_rate = value;
+                        self.script.lock().unwrap().interpolation = self.interpolation;
+                    }
+                }
+                Command::none()
+            }
+            Message::RecordedLayoutSelected(value) => {
+                self.recorded_layout = value;
+                self.script.lock().unwrap().keyboard_layout = value;
+                Command::none()
+            }
+            Message::KeyboardLayoutSelected(value) => {
+                self.keyboard_layout_value = value;
+                *self.keyboard_layout.lock().unwrap() = value;
+                self.settings.keyboard_layout = value;
+                self.settings_changed = true;
+                // Which characters the record and run keys type changed with it
+                *self.expansions.lock().unwrap() = load_expansions(&self.settings.keys, value);
                 Command::none()
             }
             Message::LoopCountChanged(value) => {
                 self.loop_count_value = value;
                 let mut loop_count = self.loop_count.lock().unwrap();
                 *loop_count = value;
+                self.settings.loop_count = value;
+                self.settings_changed = true;
+                Command::none()
+            }
+            Message::ClickButtonSelected(value) => {
+                self.clicker.button = value;
+                Command::none()
+            }
+            Message::ClickKindSelected(value) => {
+                self.clicker.kind = value;
+                Command::none()
+            }
+            Message::ClickIntervalInputChanged(input) => {
+                if let Ok(value) = input.parse::<u64>() {
+                    if value >= 1 {
+                        self.clicker.interval = Duration::from_millis(value);
+                    }
+                }
+                Command::none()
+            }
+            Message::ClickCountInputChanged(input) => {
+                // 0 clicks until the run is stopped
+                if let Ok(value) = input.parse::<u32>() {
+                    self.clicker.stop = match value {
+                        0 => StopAfter::Stopped,
+                        clicks => StopAfter::Clicks(clicks),
+                    };
+                }
+                Command::none()
+            }
+            Message::MakeClicks => {
+                let script = self.clicker.script();
+                self.use_generated("Clicker", script);
+                Command::none()
+            }
+            Message::GridRowsInputChanged(input) => {
+                if let Ok(value) = input.parse::<u32>() {
+                    if value >= 1 {
+                        self.grid.rows = value;
+                    }
+                }
+                Command::none()
+            }
+            Message::GridColumnsInputChanged(input) => {
+                if let Ok(value) = input.parse::<u32>() {
+                    if value >= 1 {
+                        self.grid.columns = value;
+                    }
+                }
+                Command::none()
+            }
+            Message::GridSpacingInputChanged(input) => {
+                if let Ok(value) = input.parse::<f64>() {
+                    if value.is_finite() {
+                        self.grid.spacing_x = value;
+                        self.grid.spacing_y = value;
+                    }
+                }
+                Command::none()
+            }
+            Message::GridOrderSelected(value) => {
+                self.grid.order = value;
+                Command::none()
+            }
+            Message::MakeGrid => {
+                // Each cell is clicked the way the clicker above is set up
+                self.grid.action = CellAction::Click(self.clicker.kind);
+                self.grid.button = self.clicker.button;
+                self.grid.hold = self.clicker.hold;
+                self.grid.interval = self.clicker.interval;
+                let script = self.grid.script();
+                self.use_generated("Grid", script);
                 Command::none()
             }
             Message::LoopCountInputChanged(input) => {
@@ -384,14 +1767,24 @@ impl Application for ScriptorApp {
                         self.loop_count_value = value;
                         let mut loop_count = self.loop_count.lock().unwrap();
                         *loop_count = value;
+                        self.settings.loop_count = value;
+                        self.settings_changed = true;
                     }
                 }
                 Command::none()
             }
             Message::Tick => {
+                if std::mem::take(&mut self.settings_changed) && self.settings_writable {
+                    save_settings(&self.settings);
+                }
+
                 let is_recording = self.record.load(Ordering::Relaxed);
                 let is_running = self.run.load(Ordering::Relaxed);
 
+                if is_recording && !self.was_recording {
+                    self.reset_editor();
+                }
+
                 if is_recording && !self.was_recording && self.minimize_on_action {
                     self.script_file_name = String::new();
                     self.was_recording = is_recording;
@@ -469,6 +1862,28 @@ impl Application for ScriptorApp {
         .width(Length::Fixed(184.0))
         .padding(6);
 
+        let simplify_button = button(
+            text("Simplify Moves")
+                .size(12)
+                .horizontal_alignment(iced::alignment::Horizontal::Center),
+        )
+        .on_press(Message::Simplify)
+        .width(Length::Fixed(184.0))
+        .padding(6);
+
+        let editor_button = button(
+            text(if self.editor_open {
+                "Hide Events ◂"
+            } else {
+                "Edit Events ▸"
+            })
+            .size(12)
+            .horizontal_alignment(iced::alignment::Horizontal::Center),
+        )
+        .on_press(Message::ToggleEditor)
+        .width(Length::Fixed(184.0))
+        .padding(6);
+
         let minimize_checkbox = checkbox("Minimize", self.minimize_on_action)
             .on_toggle(Message::MinimizeToggled)
             .size(14)
@@ -484,9 +1899,235 @@ impl Application for ScriptorApp {
             .size(14)
             .text_size(12);
 
-        let checkboxes = column![minimize_checkbox, delay_checkbox, infinite_checkbox,]
-            .spacing(2)
-            .align_items(Alignment::Start);
+        let simplify_checkbox = checkbox("Simplify On Record", self.simplify_checked)
+            .on_toggle(Message::SimplifyToggled)
+            .size(14)
+            .text_size(12);
+
+        let expand_text_checkbox = checkbox("Text Expansion", self.expand_text_checked)
+            .on_toggle(Message::ExpandTextToggled)
+            .size(14)
+            .text_size(12);
+
+        // The toggle chord flips this from the listener, so it's read every frame
+        let remap_checkbox = checkbox("Remap Keys", self.remap.load(Ordering::Relaxed))
+            .on_toggle(Message::RemapToggled)
+            .size(14)
+            .text_size(12);
+
+        let checkboxes = column![
+            minimize_checkbox,
+            delay_checkbox,
+            infinite_checkbox,
+            simplify_checkbox,
+            expand_text_checkbox,
+            remap_checkbox,
+        ]
+        .spacing(2)
+        .align_items(Alignment::Start);
+
+        let mouse_anchor_row = row![
+            text("Mouse:").size(12),
+            pick_list(
+                &MouseAnchor::ALL[..],
+                Some(self.mouse_anchor),
+                Message::MouseAnchorSelected,
+            )
+            .width(Length::Fill)
+            .text_size(12)
+            .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let screen_scaling_row = row![
+            text("Screen:").size(12),
+            pick_list(
+                &ScreenScaling::ALL[..],
+                Some(self.screen_scaling),
+                Message::ScreenScalingSelected,
+            )
+            .width(Length::Fill)
+            .text_size(12)
+            .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let interpolation_row = row![
+            text("Smooth:").size(12),
+            pick_list(
+                &Curve::ALL[..],
+                Some(self.interpolation.curve),
+                Message::CurveSelected,
+            )
+            .width(Length::Fill)
+            .text_size(12)
+            .padding([2, 5]),
+            text_input("60", &self.interpolation.sample_rate.to_string())
+                .on_input(Message::SampleRateInputChanged)
+                .width(40)
+                .size(12)
+                .padding([2, 5]),
+            text("Hz").size(12),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let keyboard_layout_row = row![
+            text("Keys:").size(12),
+            pick_list(
+                &KeyboardLayout::ALL[..],
+                Some(self.recorded_layout),
+                Message::RecordedLayoutSelected,
+            )
+            .width(Length::Fill)
+            .text_size(12)
+            .padding([2, 5]),
+            text("→").size(12),
+            pick_list(
+                &KeyboardLayout::ALL[..],
+                Some(self.keyboard_layout_value),
+                Message::KeyboardLayoutSelected,
+            )
+            .width(Length::Fill)
+            .text_size(12)
+            .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let data_row = row![
+            text("Data:").size(12),
+            text(&self.data_file_name).size(12).width(Length::Fill),
+            button(text("CSV").size(12))
+                .on_press(Message::OpenData)
+                .padding([2, 5]),
+            button(text("×").size(12))
+                .on_press(Message::ClearData)
+                .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let playlist_row = row![
+            text("List:").size(12),
+            text(&self.playlist_file_name).size(12).width(Length::Fill),
+            button(text("Open").size(12))
+                .on_press(Message::OpenPlaylist)
+                .padding([2, 5]),
+            button(text("×").size(12))
+                .on_press(Message::ClearPlaylist)
+                .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let playlist_status = match (
+            *self.playlist_progress.lock().unwrap(),
+            &*self.playlist.lock().unwrap(),
+        ) {
+            (Some(progress), _) => progress.to_string(),
+            (None, Some(playlist)) => format!("{} items, run to play", playlist.items.len()),
+            (None, None) => String::new(),
+        };
+
+        let schedule_row = row![
+            text("Timer:").size(12),
+            text(self.scheduler.lock().unwrap().summary(Local::now()))
+                .size(12)
+                .width(Length::Fill),
+            button(text("↻").size(12))
+                .on_press(Message::ReloadConfig)
+                .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let clicker_row = row![
+            text("Click:").size(12),
+            pick_list(
+                &CLICK_BUTTONS[..],
+                Some(self.clicker.button),
+                Message::ClickButtonSelected,
+            )
+            .width(Length::Fill)
+            .text_size(12)
+            .padding([2, 5]),
+            pick_list(
+                &ClickKind::ALL[..],
+                Some(self.clicker.kind),
+                Message::ClickKindSelected,
+            )
+            .width(Length::Fill)
+            .text_size(12)
+            .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let click_count = match self.clicker.stop {
+            StopAfter::Clicks(clicks) => clicks,
+            _ => 0,
+        };
+        let clicker_timing_row = row![
+            text_input("100", &self.clicker.interval.as_millis().to_string())
+                .on_input(Message::ClickIntervalInputChanged)
+                .width(45)
+                .size(12)
+                .padding([2, 5]),
+            text("ms ×").size(12),
+            text_input("0", &click_count.to_string())
+                .on_input(Message::ClickCountInputChanged)
+                .width(Length::Fill)
+                .size(12)
+                .padding([2, 5]),
+            button(text("Make").size(12))
+                .on_press(Message::MakeClicks)
+                .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let grid_row = row![
+            text("Grid:").size(12),
+            text_input("3", &self.grid.rows.to_string())
+                .on_input(Message::GridRowsInputChanged)
+                .width(Length::Fill)
+                .size(12)
+                .padding([2, 5]),
+            text("×").size(12),
+            text_input("3", &self.grid.columns.to_string())
+                .on_input(Message::GridColumnsInputChanged)
+                .width(Length::Fill)
+                .size(12)
+                .padding([2, 5]),
+            text("by").size(12),
+            text_input("50", &self.grid.spacing_x.to_string())
+                .on_input(Message::GridSpacingInputChanged)
+                .width(Length::Fill)
+                .size(12)
+                .padding([2, 5]),
+            text("px").size(12),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
+
+        let grid_order_row = row![
+            pick_list(
+                &GridOrder::ALL[..],
+                Some(self.grid.order),
+                Message::GridOrderSelected,
+            )
+            .width(Length::Fill)
+            .text_size(12)
+            .padding([2, 5]),
+            button(text("Make").size(12))
+                .on_press(Message::MakeGrid)
+                .padding([2, 5]),
+        ]
+        .spacing(5)
+        .align_items(Alignment::Center);
 
         let loop_count_label = text("Loop Count:").size(12);
 
@@ -537,27 +2178,36 @@ impl Application for ScriptorApp {
             .align_items(Alignment::Center);
 
         let record_button = button(
-            text("Record [ , ]")
-                .size(12)
-                .horizontal_alignment(iced::alignment::Horizontal::Center),
+            text(format!(
+                "Record [ {} ]",
+                key_label(self.settings.keys.record, self.keyboard_layout_value)
+            ))
+            .size(12)
+            .horizontal_alignment(iced::alignment::Horizontal::Center),
         )
         .on_press(Message::Record)
         .width(Length::Fixed(184.0))
         .padding(6);
 
         let stop_button = button(
-            text("Stop Recording [ . ]")
-                .size(12)
-                .horizontal_alignment(iced::alignment::Horizontal::Center),
+            text(format!(
+                "Stop Recording [ {} ]",
+                key_label(self.settings.keys.stop, self.keyboard_layout_value)
+            ))
+            .size(12)
+            .horizontal_alignment(iced::alignment::Horizontal::Center),
         )
         .on_press(Message::StopRecording)
         .width(Length::Fixed(184.0))
         .padding(6);
 
         let run_button = button(
-            text("Run [ / ]")
-                .size(12)
-                .horizontal_alignment(iced::alignment::Horizontal::Center),
+            text(format!(
+                "Run [ {} ]",
+                key_label(self.settings.keys.run, self.keyboard_layout_value)
+            ))
+            .size(12)
+            .horizontal_alignment(iced::alignment::Horizontal::Center),
         )
         .on_press(Message::Run)
         .width(Length::Fixed(184.0))
@@ -567,7 +2217,21 @@ impl Application for ScriptorApp {
             file_section,
             open_button,
             save_button,
+            simplify_button,
+            editor_button,
             container(checkboxes).width(Length::Fill).center_x(),
+            mouse_anchor_row,
+            screen_scaling_row,
+            interpolation_row,
+            keyboard_layout_row,
+            data_row,
+            playlist_row,
+            text(playlist_status).size(11),
+            schedule_row,
+            clicker_row,
+            clicker_timing_row,
+            grid_row,
+            grid_order_row,
             loop_count_row,
             record_button,
             stop_button,
@@ -576,10 +2240,26 @@ impl Application for ScriptorApp {
         .spacing(4)
         .padding([6, 8, 6, 8]);
 
-        container(content)
-            .width(Length::Fill)
-            .height(Length::Fill)
-            .into()
+        let controls = container(content)
+            .width(Length::Fixed(CONTROLS_WIDTH))
+            .height(Length::Fill);
+        if self.editor_open {
+            row![controls, self.event_table()].into()
+        } else {
+            controls.into()
+        }
+    }
+
+    fn subscription(&self) -> iced::Subscription<Message> {
+        iced::event::listen_with(|event, _| match event {
+            iced::Event::Window(_, iced::window::Event::Moved { x, y }) => {
+                Some(Message::WindowMoved(x, y))
+            }
+            iced::Event::Window(_, iced::window::Event::Resized { height, .. }) => {
+                Some(Message::WindowResized(height as f32))
+            }
+            _ => None,
+        })
     }
 
     fn theme(&self) -> Theme {
@@ -608,82 +2288,415 @@ impl Application for ScriptorApp {
     }
 }
 
+// A config file that couldn't be loaded is logged and left at its defaults
+fn or_default<T: Default>(what: &str, loaded: Result<T, String>) -> T {
+    loaded.unwrap_or_else(|e| {
+        log(&format!("Error loading {}: {}", what, e));
+        T::default()
+    })
+}
+
+// Hotkeys from the config directory, none if they can't be loaded
+fn load_hotkeys(keys: &ControlKeys) -> Hotkeys {
+    let hotkeys = or_default(
+        "hotkeys",
+        Hotkeys::path().and_then(|path| Hotkeys::load(&path, keys)),
+    );
+    if !hotkeys.hotkeys.is_empty() {
+        log(&format!("Loaded {} hotkeys", hotkeys.hotkeys.len()));
+    }
+    hotkeys
+}
+
+// Text expansions from the config directory, none if they can't be loaded
+fn load_expansions(keys: &ControlKeys, layout: KeyboardLayout) -> Expansions {
+    let expansions = or_default(
+        "text expansions",
+        Expansions::path().and_then(|path| Expansions::load(&path, keys, layout)),
+    );
+    if !expansions.expansions.is_empty() {
+        log(&format!(
+            "Loaded {} text expansions",
+            expansions.expansions.len()
+        ));
+    }
+    expansions
+}
+
+// Settings from the config directory, the defaults if they can't be loaded
+fn load_settings() -> Settings {
+    or_default(
+        "settings",
+        Settings::path().and_then(|path| Settings::load(&path)),
+    )
+}
+
+fn save_settings(settings: &Settings) {
+    if let Err(e) = Settings::path().and_then(|path| settings.save(&path)) {
+        log(&format!("Error saving settings: {}", e));
+    }
+}
+
+// Whether the window's corner is on one of the screens attached now
+fn on_screen(window: WindowPosition) -> bool {
+    let (x, y) = (window.x as f64, window.y as f64);
+    ScreenGeometry::current().is_none_or(|screen| {
+        screen.monitors.iter().any(|monitor| {
+            (monitor.x..monitor.x + monitor.width).contains(&x)
+                && (monitor.y..monitor.y + monitor.height).contains(&y)
+        })
+    })
+}
+
+// What a key types on this keyboard, or its name for keys that don't type
+fn key_label(key: SerializableKey, layout: KeyboardLayout) -> String {
+    match layout.character(Keystroke::plain(key)) {
+        Some(c) if !c.is_whitespace() => c.to_string(),
+        _ => format!("{:?}", key),
+    }
+}
+
+// Key remaps from the config directory, none if they can't be loaded
+fn load_remaps(keys: &ControlKeys) -> Remaps {
+    let remaps = or_default(
+        "key remaps",
+        Remaps::path().and_then(|path| Remaps::load(&path, keys)),
+    );
+    if !remaps.remaps.is_empty() {
+        log(&format!("Loaded {} key remaps", remaps.remaps.len()));
+    }
+    remaps
+}
+
+fn send_remapped(events: Vec<SerializableEventType>) {
+    for event_type in events {
+        if let Ok(event_type) = EventType::try_from(event_type) {
+            send_event(&event_type);
+        }
+    }
+}
+
+// Play the backspaces and snippet of an expansion, watching typing again once
+// the keys have come back through the listener
+fn expand(events: Vec<SerializableEvent>, keyboard_layout: KeyboardLayout, expanding: &AtomicBool) {
+    let mut script = Script::new(events);
+    script.keyboard_layout = keyboard_layout;
+    let run = Arc::new(AtomicBool::new(true));
+    let delay = Arc::new(AtomicBool::new(true));
+    let mut player = Player::new(&script, run, delay);
+    if let Some(first) = script.events.first() {
+        player.reset_timing(first.time);
+    }
+    let result = flow::parse(&script.events)
+        .map_err(WaitError::Failed)
+        .and_then(|block| player.run_block(&block));
+    if let Err(e) = result {
+        log(&format!("Error: Expansion failed: {}", e));
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    expanding.store(false, Ordering::Relaxed);
+}
+
+// File name cut down to fit next to a label
+fn short_file_name(path: &std::path::Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if file_name.chars().count() > 12 {
+        format!("{}...", file_name.chars().take(12).collect::<String>())
+    } else {
+        file_name
+    }
+}
+
+// Drop redundant mouse movement from the loaded script
+fn simplify_script(script: &Mutex<Script>) {
+    let removed = script
+        .lock()
+        .unwrap()
+        .simplify_mouse_moves(&SimplifyOptions::default());
+    log(&format!(
+        "Simplified script, removed {} mouse moves",
+        removed
+    ));
+}
+
+#[allow(clippy::too_many_arguments)]
 fn event_loop(
-    events: Arc<Mutex<Vec<Event>>>,
+    script: Arc<Mutex<Script>>,
+    playlist: Arc<Mutex<Option<Playlist>>>,
+    playlist_progress: Arc<Mutex<Option<PlaylistProgress>>>,
+    part: Arc<Mutex<Option<Script>>>,
+    playhead: Arc<Mutex<Option<SystemTime>>>,
+    hotkey_queue: Arc<Mutex<HotkeyQueue>>,
     run: Arc<AtomicBool>,
     infinite_loop: Arc<AtomicBool>,
     loop_count: Arc<Mutex<i32>>,
     delay: Arc<AtomicBool>,
+    keyboard_layout: Arc<Mutex<KeyboardLayout>>,
+    stop_key: SerializableKey,
 ) {
     loop {
         if run.load(Ordering::Relaxed) {
-            let events_ref = Arc::clone(&events);
-            let run_ref = Arc::clone(&run);
-            let infinite_loop_ref = Arc::clone(&infinite_loop);
-            let loop_count_ref = Arc::clone(&loop_count);
-            let delay_ref = Arc::clone(&delay);
-            send_events(
-                events_ref,
-                run_ref,
-                infinite_loop_ref,
-                loop_count_ref,
-                delay_ref,
-            );
+            // A part of the script picked on the timeline plays once on its own.
+            // A macro started by a hotkey is loaded now, otherwise a loaded
+            // playlist is played instead of the script.
+            let part = part.lock().unwrap().take();
+            let hotkey = hotkey_queue.lock().unwrap().start_next();
+            let playlist = playlist.lock().unwrap().clone();
+            let keyboard_layout = *keyboard_layout.lock().unwrap();
+            match (part, hotkey, playlist) {
+                (Some(part), _, _) => {
+                    let playback = Playback {
+                        loop_count: 1,
+                        infinite_loop: &AtomicBool::new(false),
+                        speed: 1.0,
+                        playhead: Some(&playhead),
+                        stop_key,
+                    };
+                    send_events(part, &run, &delay, keyboard_layout, playback, |_, _| {});
+                }
+                (None, Some(path), _) => send_file(
+                    &path,
+                    &run,
+                    &delay,
+                    keyboard_layout,
+                    stop_key,
+                    &playlist_progress,
+                ),
+                (None, None, Some(playlist)) => send_playlist(
+                    &playlist,
+                    &playlist_progress,
+                    &run,
+                    &delay,
+                    keyboard_layout,
+                    stop_key,
+                ),
+                (None, None, None) => {
+                    let script = script.lock().unwrap().clone();
+                    let playback = Playback {
+                        loop_count: *loop_count.lock().unwrap(),
+                        infinite_loop: &infinite_loop,
+                        speed: 1.0,
+                        playhead: Some(&playhead),
+                        stop_key,
+                    };
+                    send_events(script, &run, &delay, keyboard_layout, playback, |_, _| {});
+                }
+            }
+
+            run.store(false, Ordering::Relaxed);
+            *playhead.lock().unwrap() = None;
+            log("Done");
+
+            // A macro a hotkey queued or replaced this one with starts straight away
+            let mut hotkey_queue = hotkey_queue.lock().unwrap();
+            hotkey_queue.finished();
+            if hotkey_queue.has_next() {
+                run.store(true, Ordering::Relaxed);
+            }
         }
         thread::sleep(Duration::from_millis(10));
     }
 }
 
-fn send_events(
-    events: Arc<Mutex<Vec<Event>>>,
-    run: Arc<AtomicBool>,
-    infinite_loop: Arc<AtomicBool>,
-    loop_count: Arc<Mutex<i32>>,
+// Start scheduled runs as they come due. `busy` gives the number of runs going
+// that the scheduler didn't start, or None when nothing should start right now.
+fn schedule_loop(
+    scheduler: Arc<Mutex<Scheduler>>,
+    busy: impl Fn() -> Option<usize>,
     delay: Arc<AtomicBool>,
+    keyboard_layout: Arc<Mutex<KeyboardLayout>>,
+    stop_key: SerializableKey,
+    progress: Arc<Mutex<Option<PlaylistProgress>>>,
 ) {
-    let events = events.lock().unwrap().to_vec();
-    if events.is_empty() {
-        log("There aren't any events to run!");
-        run.store(false, Ordering::Relaxed);
-        return;
+    loop {
+        {
+            let mut scheduler = scheduler.lock().unwrap();
+            if let Err(e) = scheduler.check(Local::now()) {
+                log(&format!("Error: {}", e));
+            }
+
+            if let Some(others) = busy() {
+                while let Some(job) = scheduler.start(others) {
+                    let delay = Arc::clone(&delay);
+                    let keyboard_layout = *keyboard_layout.lock().unwrap();
+                    let progress = Arc::clone(&progress);
+                    thread::spawn(move || {
+                        run_scheduled(job, &delay, keyboard_layout, stop_key, &progress)
+                    });
+                }
+            }
+        }
+        thread::sleep(Duration::from_secs(1));
     }
+}
 
-    let loop_count = loop_count.lock().unwrap();
-    let mut i = 0;
-    while i < *loop_count {
-        let start_time = std::time::Instant::now();
-        let recording_start = events[0].time;
+// Play the script or playlist of a schedule that fired
+fn run_scheduled(
+    job: ScheduledRun,
+    delay: &Arc<AtomicBool>,
+    keyboard_layout: KeyboardLayout,
+    stop_key: SerializableKey,
+    progress: &Mutex<Option<PlaylistProgress>>,
+) {
+    log(&format!(
+        "Schedule \"{}\" running {}",
+        job.schedule.name,
+        job.path.display()
+    ));
+    send_file(
+        &job.path,
+        &job.run,
+        delay,
+        keyboard_layout,
+        stop_key,
+        progress,
+    );
+
+    // Frees the run's slot
+    job.run.store(false, Ordering::Relaxed);
+    log(&format!("Schedule \"{}\" finished", job.schedule.name));
+}
 
-        let mut halted = false;
-        for event in &events {
-            if !run.load(Ordering::Relaxed) {
-                log("Running halted!");
-                halted = true;
-                break;
+// Load a script or playlist from disk and play it once
+fn send_file(
+    path: &std::path::Path,
+    run: &Arc<AtomicBool>,
+    delay: &Arc<AtomicBool>,
+    keyboard_layout: KeyboardLayout,
+    stop_key: SerializableKey,
+    progress: &Mutex<Option<PlaylistProgress>>,
+) {
+    if is_playlist(path) {
+        match Playlist::load(path) {
+            Ok(playlist) => {
+                send_playlist(&playlist, progress, run, delay, keyboard_layout, stop_key)
             }
+            Err(e) => log(&format!("Error: {}: {}", path.display(), e)),
+        }
+        return;
+    }
 
-            if delay.load(Ordering::Relaxed) {
-                let target_offset = event.time.duration_since(recording_start).unwrap();
-                let current_offset = start_time.elapsed();
+    match Script::load(path) {
+        Ok(script) => {
+            let playback = Playback {
+                loop_count: 1,
+                infinite_loop: &AtomicBool::new(false),
+                speed: 1.0,
+                playhead: None,
+                stop_key,
+            };
+            send_events(script, run, delay, keyboard_layout, playback, |_, _| {});
+        }
+        Err(e) => log(&format!("Error: {}: {}", path.display(), e)),
+    }
+}
 
-                if target_offset > current_offset {
-                    let sleep_duration = target_offset - current_offset;
-                    spin_sleep::sleep(sleep_duration);
-                }
-            } else {
-                spin_sleep::sleep(Duration::from_micros(50));
-            }
+// How many times to play a script and how fast, and where to note how far
+// into it playback is. The stop key is released after each loop so it's never
+// left held down.
+struct Playback<'a> {
+    loop_count: i32,
+    infinite_loop: &'a AtomicBool,
+    speed: f64,
+    playhead: Option<&'a Arc<Mutex<Option<SystemTime>>>>,
+    stop_key: SerializableKey,
+}
 
-            send_event(&event.event_type);
+// Play a script `playback.loop_count` times, or once per row of its data source.
+// `on_loop` is given the loop number and count as each loop starts. Returns
+// whether it played to the end rather than being stopped or failing.
+fn send_events(
+    mut script: Script,
+    run: &Arc<AtomicBool>,
+    delay: &Arc<AtomicBool>,
+    keyboard_layout: KeyboardLayout,
+    playback: Playback,
+    mut on_loop: impl FnMut(i32, i32),
+) -> bool {
+    // Scripts recorded on another layout are converted to type the same text here
+    if script.keyboard_layout != keyboard_layout {
+        let from = script.keyboard_layout;
+        let report = script.translate_layout(keyboard_layout);
+        log(&format!(
+            "Keyboard layout {} -> {}: {}",
+            from, keyboard_layout, report
+        ));
+    }
+
+    let events = interpolate_mouse_moves(&script.events, &script.interpolation);
+    if events.is_empty() {
+        log("There aren't any events to run!");
+        return false;
+    }
+
+    let block = match flow::parse(&events) {
+        Ok(block) => block,
+        Err(e) => {
+            log(&format!("Error: {}", e));
+            return false;
+        }
+    };
+
+    // A data source replaces the loop count, playing the script once per row
+    let data = match script.data_path() {
+        Some(path) => match DataTable::load(&path) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                log(&format!("Error: {}", e));
+                return false;
+            }
+        },
+        None => None,
+    };
+    let loop_count = match &data {
+        Some(data) => data.rows.len() as i32,
+        None => playback.loop_count,
+    };
+
+    let mut player = Player::new(&script, Arc::clone(run), Arc::clone(delay));
+    player.speed = playback.speed;
+    player.playhead = playback.playhead.cloned();
+    let mut i = 0;
+    while i < loop_count {
+        on_loop(i + 1, loop_count);
+        player.variables = script.variables.clone();
+        if let Some(data) = &data {
+            log(&format!("Row {} of {}", i + 1, data.rows.len()));
+            player.variables.extend(data.row_variables(i as usize));
         }
 
-        if halted {
-            break;
+        player.reset_timing(events[0].time);
+        match player.run_block(&block) {
+            Ok(Flow::Next) => {}
+            Ok(Flow::Exit(code)) => {
+                log(&format!("Exited with code {}", code));
+                break;
+            }
+            Ok(Flow::Jump(label)) => {
+                log(&format!("Error: No label \"{}\" to jump to", label));
+                return false;
+            }
+            Err(WaitError::Stopped) => {
+                log("Running halted!");
+                return false;
+            }
+            Err(e) if data.is_some() => {
+                log(&format!("Error: Row {} failed: {}", i + 1, e));
+                return false;
+            }
+            Err(e) => {
+                log(&format!("Error: Step failed: {}", e));
+                return false;
+            }
         }
 
         #[cfg(not(target_os = "macos"))]
         {
-            match simulate(&EventType::KeyRelease(Key::Dot)) {
+            match simulate(&EventType::KeyRelease(playback.stop_key.into())) {
                 Ok(()) => (),
                 Err(SimulateError) => {
                     eprintln!("Could not send final release key.");
@@ -691,11 +2704,74 @@ fn send_events(
             };
         }
 
-        if !infinite_loop.load(Ordering::Relaxed) {
+        if data.is_some() || !playback.infinite_loop.load(Ordering::Relaxed) {
             i += 1;
         }
     }
 
-    run.store(false, Ordering::Relaxed);
-    log("Done");
+    true
+}
+
+// Play every item of a playlist in order, stopping at the first one that fails
+fn send_playlist(
+    playlist: &Playlist,
+    progress: &Mutex<Option<PlaylistProgress>>,
+    run: &Arc<AtomicBool>,
+    delay: &Arc<AtomicBool>,
+    keyboard_layout: KeyboardLayout,
+    stop_key: SerializableKey,
+) {
+    let infinite_loop = AtomicBool::new(false);
+    let mut round = 1;
+    'rounds: while playlist.repeat == 0 || round <= playlist.repeat {
+        for (index, item) in playlist.items.iter().enumerate() {
+            let path = playlist.script_path(item);
+            let script = match Script::load(&path) {
+                Ok(script) => script,
+                Err(e) => {
+                    log(&format!("Error: {}: {}", path.display(), e));
+                    break 'rounds;
+                }
+            };
+            log(&format!(
+                "Playlist item {} of {}: {}",
+                index + 1,
+                playlist.items.len(),
+                path.display()
+            ));
+
+            let playback = Playback {
+                loop_count: item.loops as i32,
+                infinite_loop: &infinite_loop,
+                speed: item.speed,
+                playhead: None,
+                stop_key,
+            };
+            let on_loop = |loop_number, loops| {
+                *progress.lock().unwrap() = Some(PlaylistProgress {
+                    item: index + 1,
+                    items: playlist.items.len(),
+                    loop_number: loop_number as u32,
+                    loops: loops as u32,
+                    round,
+                    rounds: playlist.repeat,
+                });
+            };
+            if !send_events(script, run, delay, keyboard_layout, playback, on_loop) {
+                break 'rounds;
+            }
+
+            // Wait out the pause in short steps so stopping isn't held up by it
+            let pause = Instant::now();
+            while pause.elapsed() < Duration::from_millis(item.delay_after_ms) {
+                if !run.load(Ordering::Relaxed) {
+                    break 'rounds;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        round += 1;
+    }
+
+    *progress.lock().unwrap() = None;
 }
@@ -0,0 +1,72 @@
+use crate::screen::{Monitor, ScreenGeometry};
+use crate::serializable_event::{SerializableButton, SerializableEvent, SerializableEventType};
+use std::time::Duration;
+
+// A mouse button pressed at the last position the mouse moved to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Click {
+    pub time: Duration,
+    pub x: f64,
+    pub y: f64,
+    pub button: SerializableButton,
+}
+
+// Where a script moves the mouse and clicks, with times from the first event.
+// Coordinates are as recorded, before any scaling or anchoring on playback.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MousePath {
+    pub moves: Vec<(Duration, f64, f64)>,
+    pub clicks: Vec<Click>,
+}
+
+impl MousePath {
+    pub fn new(events: &[SerializableEvent]) -> Self {
+        let Some(first) = events.first().map(|event| event.time) else {
+            return MousePath::default();
+        };
+        let mut path = MousePath::default();
+        let mut position = None;
+        for event in events {
+            let time = event.time.duration_since(first).unwrap_or_default();
+            match event.event_type {
+                SerializableEventType::MouseMove { x, y } => {
+                    path.moves.push((time, x, y));
+                    position = Some((x, y));
+                }
+                // Clicks before the mouse has moved are somewhere unknown
+                SerializableEventType::ButtonPress(button) => {
+                    if let Some((x, y)) = position {
+                        path.clicks.push(Click { time, x, y, button });
+                    }
+                }
+                _ => {}
+            }
+        }
+        path
+    }
+
+    // Where the mouse is at `time`, the last move made by then
+    pub fn position_at(&self, time: Duration) -> Option<(f64, f64)> {
+        let after = self.moves.partition_point(|(at, _, _)| *at <= time);
+        let (_, x, y) = self.moves.get(after.checked_sub(1)?)?;
+        Some((*x, *y))
+    }
+
+    // Smallest rectangle covering every move
+    pub fn bounds(&self) -> Option<Monitor> {
+        if self.moves.is_empty() {
+            return None;
+        }
+        let monitors = self
+            .moves
+            .iter()
+            .map(|(_, x, y)| Monitor {
+                x: *x,
+                y: *y,
+                width: 0.0,
+                height: 0.0,
+            })
+            .collect();
+        Some(ScreenGeometry { monitors }.bounds())
+    }
+}
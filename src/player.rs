@@ -0,0 +1,526 @@
+use crate::calls::{resolve_call, MAX_CALL_DEPTH};
+use crate::capture::{Region, ScreenCapture, SystemScreen};
+use crate::flow::{self, Condition, Node, NodeKind};
+use crate::interpolate::interpolate_mouse_moves;
+use crate::layout::KeyboardLayout;
+use crate::screen::{CoordinateMapper, ScreenGeometry};
+use crate::script::{MouseAnchor, Script};
+use crate::scripting::run_script;
+use crate::serializable_event::{MatchAction, SerializableEventType};
+use crate::template::wait_for_image;
+use crate::variables::{substitute, Variables};
+use crate::wait::{color_matches, poll, wait_for_pixel, WaitError};
+use crate::window::{
+    find_recorded_window, wait_for_window, SystemWindows, TimeoutPolicy, WindowCondition,
+    WindowSystem,
+};
+use crate::{cursor_position, log, send_event};
+use rdev::EventType;
+use std::{
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+// How a block finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Flow {
+    // Ran to the end
+    Next,
+    // Hit a jump to a label outside of it
+    Jump(String),
+    // Hit an Exit step
+    Exit(i32),
+}
+
+// What a called script takes over from the one calling it
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub run: Arc<AtomicBool>,
+    pub delay: Arc<AtomicBool>,
+    pub keyboard_layout: KeyboardLayout,
+    pub speed: f64,
+    // File of the calling script, relative calls are resolved from it
+    pub path: Option<PathBuf>,
+    // How many calls deep the calling script is
+    pub depth: usize,
+}
+
+// Play a saved script to the end for a Call step or a Rhai call(). The called
+// script sees the caller's `variables`, its own defaults over those and then
+// `parameters` over both. The variables it finishes with are returned.
+pub fn play_file(
+    caller: &Caller,
+    path: &Path,
+    variables: Variables,
+    parameters: Variables,
+) -> Result<Variables, WaitError> {
+    if caller.depth >= MAX_CALL_DEPTH {
+        return Err(WaitError::Failed(format!(
+            "Calls are nested more than {} deep, does {} call itself?",
+            MAX_CALL_DEPTH,
+            path.display()
+        )));
+    }
+
+    let path = resolve_call(caller.path.as_deref(), path);
+    let mut script =
+        Script::load(&path).map_err(|e| WaitError::Failed(format!("{}: {}", path.display(), e)))?;
+    if script.keyboard_layout != caller.keyboard_layout {
+        script.translate_layout(caller.keyboard_layout);
+    }
+    let events = interpolate_mouse_moves(&script.events, &script.interpolation);
+    let block = flow::parse(&events)
+        .map_err(|e| WaitError::Failed(format!("{}: {}", path.display(), e)))?;
+
+    let mut player = Player::new(&script, Arc::clone(&caller.run), Arc::clone(&caller.delay));
+    player.depth = caller.depth + 1;
+    player.speed = caller.speed;
+    let defaults = std::mem::replace(&mut player.variables, variables);
+    player.variables.extend(defaults);
+    player.variables.extend(parameters);
+    if let Some(first) = events.first() {
+        player.reset_timing(first.time);
+    }
+    match player.run_block(&block)? {
+        Flow::Next | Flow::Exit(_) => Ok(player.variables),
+        Flow::Jump(label) => Err(WaitError::Failed(format!(
+            "No label \"{}\" to jump to in {}",
+            label,
+            path.display()
+        ))),
+    }
+}
+
+// Plays a parsed script. Everything that lasts for a whole run (coordinate
+// mapping, screen and window connections, variables) lives here.
+pub struct Player {
+    run: Arc<AtomicBool>,
+    // Natural delay, checked before every event so it can change while playing
+    delay: Arc<AtomicBool>,
+    mapper: CoordinateMapper,
+    screen: SystemScreen,
+    windows: SystemWindows,
+    mouse_anchor: MouseAnchor,
+    keyboard_layout: KeyboardLayout,
+    pub variables: Variables,
+    // Multiplies the recorded timing, 2.0 plays twice as fast
+    pub speed: f64,
+    // Set to the recorded time of each event as it plays, to show where
+    // playback has got to
+    pub playhead: Option<Arc<Mutex<Option<SystemTime>>>>,
+    path: Option<PathBuf>,
+    // How many Call steps led to this script, 0 for the one started by the user
+    depth: usize,
+    // Recorded time that lines up with `start_time`
+    start_time: Instant,
+    recording_start: SystemTime,
+}
+
+impl Player {
+    // Coordinates are fitted to the current screen and anchored once, to where the
+    // cursor is right now
+    pub fn new(script: &Script, run: Arc<AtomicBool>, delay: Arc<AtomicBool>) -> Self {
+        let cursor = cursor_position();
+        if script.mouse_anchor == MouseAnchor::Cursor && cursor.is_none() {
+            log("Cursor position unknown until the mouse moves, playing at the recorded positions");
+        }
+        let mapper = CoordinateMapper::new(
+            script.screen_scaling,
+            script.screen.clone(),
+            ScreenGeometry::current(),
+            script.mouse_anchor,
+            script.first_mouse_position(),
+            cursor,
+        );
+
+        Player {
+            run,
+            delay,
+            mapper,
+            screen: SystemScreen::new(),
+            windows: SystemWindows::new(),
+            mouse_anchor: script.mouse_anchor,
+            keyboard_layout: script.keyboard_layout,
+            variables: script.variables.clone(),
+            speed: 1.0,
+            playhead: None,
+            path: script.path.clone(),
+            depth: 0,
+            start_time: Instant::now(),
+            recording_start: script
+                .events
+                .first()
+                .map_or(SystemTime::UNIX_EPOCH, |event| event.time),
+        }
+    }
+
+    fn caller(&self) -> Caller {
+        Caller {
+            run: Arc::clone(&self.run),
+            delay: Arc::clone(&self.delay),
+            keyboard_layout: self.keyboard_layout,
+            speed: self.speed,
+            path: self.path.clone(),
+            depth: self.depth,
+        }
+    }
+
+    // Treat `time` in the recording as now
+    pub fn reset_timing(&mut self, time: SystemTime) {
+        self.start_time = Instant::now();
+        self.recording_start = time;
+    }
+
+    pub fn run_block(&mut self, block: &[Node]) -> Result<Flow, WaitError> {
+        let mut index = 0;
+        while let Some(node) = block.get(index) {
+            if !self.run.load(Ordering::Relaxed) {
+                return Err(WaitError::Stopped);
+            }
+
+            match self.run_node(node)? {
+                Flow::Next => index += 1,
+                Flow::Jump(label) => {
+                    let target = block.iter().position(
+                        |node| matches!(&node.kind, NodeKind::Label(name) if *name == label),
+                    );
+                    match target {
+                        Some(target) => {
+                            index = target;
+                            self.reset_timing(block[target].time);
+                        }
+                        None => return Ok(Flow::Jump(label)),
+                    }
+                }
+                Flow::Exit(code) => return Ok(Flow::Exit(code)),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn run_node(&mut self, node: &Node) -> Result<Flow, WaitError> {
+        self.wait_for(node.time);
+        if let Some(playhead) = &self.playhead {
+            *playhead.lock().unwrap() = Some(node.time);
+        }
+
+        match &node.kind {
+            NodeKind::Event(event_type) => {
+                self.run_event(event_type, node.time)?;
+                Ok(Flow::Next)
+            }
+            NodeKind::Repeat { count, body } => {
+                let mut i = 0;
+                while count.is_none_or(|count| i < count) {
+                    if !self.run.load(Ordering::Relaxed) {
+                        return Err(WaitError::Stopped);
+                    }
+                    self.reset_timing(node.time);
+                    match self.run_block(body)? {
+                        Flow::Next => {}
+                        flow => return Ok(flow),
+                    }
+                    i += 1;
+                }
+                Ok(Flow::Next)
+            }
+            NodeKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let holds = self.check(condition)?;
+                self.reset_timing(node.time);
+                self.run_block(if holds { then } else { otherwise })
+            }
+            NodeKind::Label(_) => Ok(Flow::Next),
+            NodeKind::Jump(label) => Ok(Flow::Jump(label.clone())),
+            NodeKind::Exit(code) => Ok(Flow::Exit(*code)),
+        }
+    }
+
+    // Sleep until the recorded time of an event comes up
+    fn wait_for(&self, time: SystemTime) {
+        if self.delay.load(Ordering::Relaxed) {
+            let target_offset = time
+                .duration_since(self.recording_start)
+                .unwrap_or_default()
+                .div_f64(self.speed);
+            let current_offset = self.start_time.elapsed();
+
+            if target_offset > current_offset {
+                spin_sleep::sleep(target_offset - current_offset);
+            }
+        } else {
+            spin_sleep::sleep(Duration::from_micros(50));
+        }
+    }
+
+    fn run_event(
+        &mut self,
+        event_type: &SerializableEventType,
+        time: SystemTime,
+    ) -> Result<(), WaitError> {
+        match event_type {
+            SerializableEventType::MouseMove { x, y } => {
+                let (x, y) = self.mapper.map(*x, *y);
+                send_event(&EventType::MouseMove { x, y });
+            }
+            SerializableEventType::ActiveWindow {
+                title,
+                class,
+                geometry,
+            } => {
+                if self.mouse_anchor == MouseAnchor::Window {
+                    match self.follow_window(title, class, *geometry) {
+                        Err(WaitError::Stopped) => return Err(WaitError::Stopped),
+                        Err(e) => log(&format!("Error: {}", e)),
+                        Ok(()) => {}
+                    }
+                    self.reset_timing(time);
+                }
+            }
+            event_type if event_type.is_step() => {
+                self.run_step(event_type)?;
+
+                // Keep the recorded timing from the moment the step ended
+                self.reset_timing(time);
+            }
+            event_type => {
+                if let Ok(event_type) = EventType::try_from(event_type.clone()) {
+                    send_event(&event_type);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Whether a condition holds right now
+    pub fn check(&mut self, condition: &Condition) -> Result<bool, WaitError> {
+        match condition {
+            Condition::Variable { name, value } => {
+                Ok(self.variables.get(name).is_some_and(|v| v == value))
+            }
+            Condition::Pixel {
+                x,
+                y,
+                color,
+                tolerance,
+            } => {
+                let (x, y) = self.mapper.map(*x as f64, *y as f64);
+                let actual = self
+                    .screen
+                    .pixel(x.round() as i32, y.round() as i32)
+                    .map_err(WaitError::Failed)?;
+                Ok(color_matches(actual, *color, *tolerance))
+            }
+            Condition::Window { matcher, condition } => {
+                let found = match condition {
+                    WindowCondition::Exists => self
+                        .windows
+                        .windows()
+                        .map_err(WaitError::Failed)?
+                        .iter()
+                        .any(|window| matcher.matches(window)),
+                    WindowCondition::Focused => self
+                        .windows
+                        .active_window()
+                        .map_err(WaitError::Failed)?
+                        .is_some_and(|window| matcher.matches(&window)),
+                };
+                Ok(found)
+            }
+            Condition::ExitCode {
+                program,
+                args,
+                code,
+            } => {
+                let status = process::Command::new(program)
+                    .args(args)
+                    .status()
+                    .map_err(|e| WaitError::Failed(format!("Could not run {}: {}", program, e)))?;
+                Ok(status.code() == Some(*code))
+            }
+            Condition::Not(condition) => Ok(!self.check(condition)?),
+        }
+    }
+
+    // Focus the window a recorded ActiveWindow step refers to and map the following
+    // mouse positions into it. Without a matching window the recorded positions are
+    // used as they are.
+    fn follow_window(
+        &mut self,
+        title: &str,
+        class: &[String],
+        recorded: Region,
+    ) -> Result<(), WaitError> {
+        let windows = &mut self.windows;
+        let Some(window) =
+            find_recorded_window(windows, title, class).map_err(WaitError::Failed)?
+        else {
+            self.mapper.unfollow_window();
+            return Err(WaitError::Failed(format!(
+                "Could not find window \"{}\", using recorded positions",
+                title
+            )));
+        };
+
+        let active = windows.active_window().map_err(WaitError::Failed)?;
+        if active.map(|active| active.id) != Some(window.id) {
+            windows.activate(window.id).map_err(WaitError::Failed)?;
+            let focused = poll(
+                Duration::from_secs(1),
+                &self.run,
+                || {
+                    Ok(windows
+                        .active_window()?
+                        .filter(|active| active.id == window.id))
+                },
+                || format!("window \"{}\" did not take focus", window.title),
+            );
+            match focused {
+                Ok(_) => {}
+                Err(WaitError::TimedOut(message)) => log(&format!("Warning: {}", message)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.mapper.follow_window(recorded, window.geometry);
+        Ok(())
+    }
+
+    // Carry out a script step that isn't plain input, blocking until it's done
+    fn run_step(&mut self, step: &SerializableEventType) -> Result<(), WaitError> {
+        let run = &*self.run;
+        match step {
+            SerializableEventType::WaitForPixel {
+                x,
+                y,
+                color,
+                tolerance,
+                timeout,
+            } => {
+                let (x, y) = self.mapper.map(*x as f64, *y as f64);
+                let position = (x.round() as i32, y.round() as i32);
+                wait_for_pixel(
+                    &mut self.screen,
+                    position,
+                    *color,
+                    *tolerance,
+                    *timeout,
+                    run,
+                )
+            }
+            SerializableEventType::WaitForImage {
+                template,
+                region,
+                confidence,
+                timeout,
+                action,
+            } => {
+                let template = image::load_from_memory(template)
+                    .map_err(|e| WaitError::Failed(format!("Could not decode template: {}", e)))?
+                    .to_rgb8();
+                let region = region.map(|region| self.mapper.map_region(region));
+                let found = wait_for_image(
+                    &mut self.screen,
+                    &template,
+                    region,
+                    *confidence,
+                    *timeout,
+                    run,
+                )?;
+                log(&format!(
+                    "Found image at ({}, {}) with confidence {:.2}",
+                    found.x, found.y, found.confidence
+                ));
+
+                let (x, y) = found.center();
+                match action {
+                    MatchAction::Wait => {}
+                    MatchAction::Move => send_event(&EventType::MouseMove { x, y }),
+                    MatchAction::Click(button) => {
+                        send_event(&EventType::MouseMove { x, y });
+                        send_event(&EventType::ButtonPress((*button).into()));
+                        thread::sleep(Duration::from_millis(20));
+                        send_event(&EventType::ButtonRelease((*button).into()));
+                    }
+                }
+                Ok(())
+            }
+            SerializableEventType::WaitForWindow {
+                matcher,
+                condition,
+                timeout,
+                on_timeout,
+            } => match wait_for_window(&mut self.windows, matcher, *condition, *timeout, run) {
+                Ok(window) => {
+                    log(&format!("Found window \"{}\"", window.title));
+                    Ok(())
+                }
+                Err(WaitError::TimedOut(message)) if *on_timeout == TimeoutPolicy::Continue => {
+                    log(&format!("Continuing after timeout: {}", message));
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+            SerializableEventType::TypeText {
+                text,
+                per_char_delay,
+            } => {
+                let layout = self.keyboard_layout;
+                let text = substitute(text, &self.variables).map_err(WaitError::Failed)?;
+                let (strokes, missing) = layout.keystrokes(&text);
+                if !missing.is_empty() {
+                    log(&format!(
+                        "Warning: The {} layout cannot type {}, skipping them",
+                        layout,
+                        missing
+                            .iter()
+                            .map(|c| format!("{:?}", c))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+
+                for (i, stroke) in strokes.iter().enumerate() {
+                    if !run.load(Ordering::Relaxed) {
+                        return Err(WaitError::Stopped);
+                    }
+                    if i > 0 {
+                        spin_sleep::sleep(*per_char_delay);
+                    }
+
+                    for event_type in stroke.event_types() {
+                        if let Ok(event_type) = EventType::try_from(event_type) {
+                            send_event(&event_type);
+                        }
+                    }
+                }
+                Ok(())
+            }
+            SerializableEventType::Rhai { source } => {
+                self.variables = run_script(source, self.caller(), self.variables.clone())?;
+                Ok(())
+            }
+            SerializableEventType::Call { script, parameters } => {
+                // Changes the called script makes to variables stay in it
+                let mut values = Variables::new();
+                for (name, value) in parameters {
+                    let value = substitute(value, &self.variables).map_err(WaitError::Failed)?;
+                    values.insert(name.clone(), value);
+                }
+                log(&format!("Calling {}", script.display()));
+                play_file(&self.caller(), script, self.variables.clone(), values)?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
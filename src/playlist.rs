@@ -0,0 +1,262 @@
+use crate::calls::resolve_call;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Playlists are TOML files, for example:
+//
+//   repeat = 2
+//
+//   [[items]]
+//   script = "login.bin"
+//
+//   [[items]]
+//   script = "export.bin"
+//   loops = 10
+//   speed = 2.0
+//   delay_after_ms = 5000
+
+// Playlists are told apart from scripts by their extension
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "toml")
+}
+
+// One script in a playlist and how to play it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistItem {
+    // Relative paths are from the playlist's directory
+    pub script: PathBuf,
+    #[serde(default = "one")]
+    pub loops: u32,
+    // Multiplies the recorded timing, 2.0 plays twice as fast
+    #[serde(default = "normal_speed")]
+    pub speed: f64,
+    // Pause once the item has finished, before the next one starts
+    #[serde(default)]
+    pub delay_after_ms: u64,
+}
+
+// Scripts played one after another, for batches that would otherwise be started
+// by hand one at a time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
+    // How many times to play the whole list, 0 to play it until stopped
+    #[serde(default = "one")]
+    pub repeat: u32,
+    pub items: Vec<PlaylistItem>,
+    // File the playlist was loaded from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn normal_speed() -> f64 {
+    1.0
+}
+
+impl Playlist {
+    pub fn load(path: &Path) -> Result<Playlist, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read file: {}", e))?;
+        let mut playlist: Playlist =
+            toml::from_str(&text).map_err(|e| format!("Could not parse playlist: {}", e))?;
+        playlist.path = Some(path.to_path_buf());
+        playlist.validate()?;
+        Ok(playlist)
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<(), String> {
+        // Relative items are checked from where the playlist is going
+        let previous = self.path.replace(path.to_path_buf());
+        let written = self.validate().and_then(|()| {
+            let text = toml::to_string_pretty(self)
+                .map_err(|e| format!("Could not encode playlist: {}", e))?;
+            fs::write(path, text).map_err(|e| format!("Could not write to file: {}", e))
+        });
+        if written.is_err() {
+            self.path = previous;
+        }
+        written
+    }
+
+    // Missing scripts are caught here rather than halfway through playing the list
+    pub fn validate(&self) -> Result<(), String> {
+        if self.items.is_empty() {
+            return Err("Playlist has no items".to_string());
+        }
+        for (i, item) in self.items.iter().enumerate() {
+            if item.loops == 0 {
+                return Err(format!("Item {} must loop at least once", i + 1));
+            }
+            if !(item.speed.is_finite() && item.speed > 0.0) {
+                return Err(format!(
+                    "Item {} has speed {}, it must be above 0",
+                    i + 1,
+                    item.speed
+                ));
+            }
+            let path = self.script_path(item);
+            if !path.is_file() {
+                return Err(format!(
+                    "Item {} plays {}, which does not exist",
+                    i + 1,
+                    path.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Where an item's script file is
+    pub fn script_path(&self, item: &PlaylistItem) -> PathBuf {
+        resolve_call(self.path.as_deref(), &item.script)
+    }
+}
+
+// Where a running playlist has got to, all counted from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaylistProgress {
+    pub item: usize,
+    pub items: usize,
+    pub loop_number: u32,
+    pub loops: u32,
+    pub round: u32,
+    // 0 when the playlist repeats until stopped
+    pub rounds: u32,
+}
+
+impl std::fmt::Display for PlaylistProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Item {}/{}, loop {}/{}, round {}",
+            self.item, self.items, self.loop_number, self.loops, self.round
+        )?;
+        if self.rounds > 0 {
+            write!(f, "/{}", self.rounds)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "simplyscriptor2-playlist-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("login.bin"), b"").unwrap();
+        directory
+    }
+
+    fn item(script: &str) -> PlaylistItem {
+        PlaylistItem {
+            script: PathBuf::from(script),
+            loops: 1,
+            speed: 1.0,
+            delay_after_ms: 0,
+        }
+    }
+
+    #[test]
+    fn loads_items_relative_to_the_playlist() {
+        let directory = directory("load");
+        let path = directory.join("batch.toml");
+        fs::write(
+            &path,
+            "repeat = 0\n\n[[items]]\nscript = \"login.bin\"\nloops = 3\n",
+        )
+        .unwrap();
+
+        let playlist = Playlist::load(&path).unwrap();
+        assert_eq!(playlist.repeat, 0);
+        assert_eq!(playlist.items[0].loops, 3);
+        assert_eq!(playlist.items[0].speed, 1.0);
+        assert_eq!(
+            playlist.script_path(&playlist.items[0]),
+            directory.join("login.bin")
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn refuses_empty_playlists() {
+        let directory = directory("empty");
+        let path = directory.join("batch.toml");
+        fs::write(&path, "items = []\n").unwrap();
+
+        assert_eq!(Playlist::load(&path).unwrap_err(), "Playlist has no items");
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn refuses_missing_scripts() {
+        let directory = directory("missing");
+        let mut playlist = Playlist {
+            repeat: 1,
+            items: vec![item("login.bin"), item("export.bin")],
+            path: Some(directory.join("batch.toml")),
+        };
+
+        let error = playlist.validate().unwrap_err();
+        assert!(error.starts_with("Item 2 plays"), "{}", error);
+        assert!(error.contains("export.bin"), "{}", error);
+
+        // Nothing is written and the old location is kept
+        let elsewhere = directory.join("elsewhere").join("batch.toml");
+        assert!(playlist.save(&elsewhere).is_err());
+        assert_eq!(playlist.path, Some(directory.join("batch.toml")));
+
+        playlist.items.pop();
+        playlist.validate().unwrap();
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn refuses_bad_loop_counts_and_speeds() {
+        let directory = directory("counts");
+        let playlist = |item: PlaylistItem| Playlist {
+            repeat: 1,
+            items: vec![item],
+            path: Some(directory.join("batch.toml")),
+        };
+
+        let no_loops = playlist(PlaylistItem {
+            loops: 0,
+            ..item("login.bin")
+        });
+        assert_eq!(
+            no_loops.validate().unwrap_err(),
+            "Item 1 must loop at least once"
+        );
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let bad_speed = playlist(PlaylistItem {
+                speed,
+                ..item("login.bin")
+            });
+            assert!(bad_speed.validate().is_err(), "{}", speed);
+        }
+
+        // A whole-list repeat of 0 means until stopped, not a mistake
+        let forever = Playlist {
+            repeat: 0,
+            ..playlist(item("login.bin"))
+        };
+        forever.validate().unwrap();
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// xorshift64, plenty for jittering clicks and random() in Rhai scripts, but not
+// for anything that has to be unpredictable
+#[derive(Debug, Clone)]
+pub struct Random(u64);
+
+impl Random {
+    // Zero would get stuck at zero, so the lowest bit is always set
+    pub fn new(seed: u64) -> Self {
+        Random(seed | 1)
+    }
+
+    // Seeded from the clock, different every run
+    pub fn from_time() -> Self {
+        Random::new(time_seed())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Anywhere from -max to max
+    pub fn spread(&mut self, max: f64) -> f64 {
+        if max <= 0.0 {
+            return 0.0;
+        }
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        (unit * 2.0 - 1.0) * max
+    }
+
+    // Anywhere from min to max, both included. Worked out in i128 so the full
+    // i64 range doesn't overflow. None if min is above max.
+    pub fn range(&mut self, min: i64, max: i64) -> Option<i64> {
+        if min > max {
+            return None;
+        }
+        let span = (max as i128 - min as i128 + 1) as u128;
+        let offset = (self.next_u64() as u128 % span) as i128;
+        Some((min as i128 + offset) as i64)
+    }
+}
+
+pub fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let (mut a, mut b) = (Random::new(7), Random::new(7));
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Random::new(0).next_u64(), 0);
+    }
+
+    #[test]
+    fn range_stays_inside_its_bounds() {
+        let mut random = Random::new(1);
+        for _ in 0..1000 {
+            let value = random.range(-3, 3).unwrap();
+            assert!((-3..=3).contains(&value));
+        }
+        assert_eq!(random.range(5, 5), Some(5));
+        assert!(random.range(i64::MIN, i64::MAX).is_some());
+        assert!(random.range(i64::MAX - 1, i64::MAX).unwrap() >= i64::MAX - 1);
+        assert_eq!(random.range(2, 1), None);
+    }
+
+    #[test]
+    fn spread_stays_inside_its_bounds() {
+        let mut random = Random::new(3);
+        for _ in 0..1000 {
+            assert!(random.spread(2.5).abs() <= 2.5);
+        }
+        assert_eq!(random.spread(0.0), 0.0);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::config::{config_dir, load_config};
+use crate::hotkey::{Chord, ChordKey};
+use crate::serializable_event::{SerializableEventType, SerializableKey};
+use crate::settings::ControlKeys;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Key remaps are kept in remaps.toml in the config directory, for example:
+//
+//   toggle = "Ctrl+Alt+R"
+//
+//   [[remaps]]
+//   from = "Pause"
+//   to = "Escape"
+//
+//   [[remaps]]
+//   from = "Mouse8"
+//   to = "Ctrl+C"
+//
+//   [[remaps]]
+//   from = "CapsLock"
+//   to = "ControlLeft"
+const REMAPS_FILE: &str = "remaps.toml";
+
+// Pressing `from` also presses `to`, and letting go of it lets go of `to`. Only
+// `to` can be a modifier on its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Remap {
+    pub from: Chord,
+    pub to: Chord,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Remaps {
+    // Chord that switches remapping on and off
+    #[serde(default)]
+    pub toggle: Option<Chord>,
+    #[serde(default)]
+    pub remaps: Vec<Remap>,
+}
+
+impl Remaps {
+    pub fn path() -> Result<PathBuf, String> {
+        Ok(config_dir()?.join(REMAPS_FILE))
+    }
+
+    // No remaps file yet is the same as an empty one
+    pub fn load(path: &Path, keys: &ControlKeys) -> Result<Remaps, String> {
+        let remaps: Remaps = load_config(path)?;
+        remaps.validate(keys)?;
+        Ok(remaps)
+    }
+
+    // A chord can only be remapped once, and not to a key that is remapped
+    // itself, since the listener hears the remapped keys too and would go round
+    // in a loop
+    pub fn validate(&self, keys: &ControlKeys) -> Result<(), String> {
+        for chord in self
+            .remaps
+            .iter()
+            .map(|remap| &remap.from)
+            .chain(&self.toggle)
+        {
+            if chord.is_modifier() {
+                return Err(format!("{} is only a modifier, it needs a key", chord));
+            }
+            if let ChordKey::Key(key) = chord.key {
+                if keys.contains(key) {
+                    return Err(format!(
+                        "{} uses {:?}, which is taken by the record, stop and run keys",
+                        chord, key
+                    ));
+                }
+            }
+        }
+
+        for (i, remap) in self.remaps.iter().enumerate() {
+            if Some(remap.from) == self.toggle {
+                return Err(format!(
+                    "{} is both the toggle and remapped to {}",
+                    remap.from, remap.to
+                ));
+            }
+            if let Some(other) = self.remaps[..i]
+                .iter()
+                .find(|other| other.from == remap.from)
+            {
+                return Err(format!(
+                    "{} is remapped to both {} and {}",
+                    remap.from, other.to, remap.to
+                ));
+            }
+            if let Some(other) = self
+                .remaps
+                .iter()
+                .find(|other| other.from.key == remap.to.key)
+            {
+                return Err(format!(
+                    "{} is remapped to {}, which is remapped again to {}",
+                    remap.from, remap.to, other.to
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn find(&self, chord: &Chord) -> Option<&Remap> {
+        self.remaps.iter().find(|remap| remap.from == *chord)
+    }
+}
+
+// Turns the global input events into the remapped ones to send. The listener
+// grabs input, so a remapped key is swallowed and only what it's remapped to
+// reaches other programs.
+#[derive(Debug, Clone, Default)]
+pub struct Remapper {
+    // Source keys that are down, with the chords they came from and pressed
+    held: Vec<(ChordKey, Chord, Chord)>,
+}
+
+impl Remapper {
+    pub fn new() -> Self {
+        Remapper::default()
+    }
+
+    // Feed every input event through here, along with the chord ChordTracker
+    // made of it and the keys it says are held. Returns the events to send in
+    // place of a remapped key, which should be swallowed, or None to let the
+    // event through.
+    pub fn update(
+        &mut self,
+        event_type: &SerializableEventType,
+        chord: Option<Chord>,
+        held: &[SerializableKey],
+        remaps: &Remaps,
+    ) -> Option<Vec<SerializableEventType>> {
+        let (key, pressed) = ChordKey::of(event_type)?;
+        if let Some(i) = self.held.iter().position(|(source, ..)| *source == key) {
+            // Key repeats of a held source key are swallowed too
+            if pressed {
+                return Some(Vec::new());
+            }
+            let (_, from, to) = self.held.remove(i);
+            return Some(lifting_modifiers(&from, held, to.release()));
+        }
+        if !pressed {
+            return None;
+        }
+
+        let remap = remaps.find(&chord?)?;
+        self.held.push((remap.from.key, remap.from, remap.to));
+        Some(lifting_modifiers(&remap.from, held, remap.to.press()))
+    }
+
+    // Let go of everything still pressed, for when remapping is switched off
+    // with a source key down
+    pub fn release_all(&mut self) -> Vec<SerializableEventType> {
+        self.held
+            .drain(..)
+            .rev()
+            .flat_map(|(_, _, to)| to.release())
+            .collect()
+    }
+}
+
+// `events` with the modifiers of `from` that are still held let go of before
+// and pressed again after, so Ctrl+J remapped to Escape sends a plain Escape
+fn lifting_modifiers(
+    from: &Chord,
+    held: &[SerializableKey],
+    events: Vec<SerializableEventType>,
+) -> Vec<SerializableEventType> {
+    let lifted: Vec<SerializableKey> = held
+        .iter()
+        .copied()
+        .filter(|key| from.uses_modifier(*key))
+        .collect();
+    lifted
+        .iter()
+        .map(|key| SerializableEventType::KeyRelease(*key))
+        .chain(events)
+        .chain(
+            lifted
+                .iter()
+                .map(|key| SerializableEventType::KeyPress(*key)),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotkey::ChordTracker;
+    use SerializableEventType::{KeyPress, KeyRelease};
+    use SerializableKey::{CapsLock, ControlLeft, ControlRight, Escape, KeyC, KeyJ, Pause};
+
+    fn remaps(pairs: &[(&str, &str)]) -> Remaps {
+        Remaps {
+            toggle: None,
+            remaps: pairs
+                .iter()
+                .map(|(from, to)| Remap {
+                    from: from.parse().unwrap(),
+                    to: to.parse().unwrap(),
+                })
+                .collect(),
+        }
+    }
+
+    // Runs events through a tracker and remapper like the grab callback does,
+    // returning what each one turned into, or None when it went through
+    fn feed(
+        remapper: &mut Remapper,
+        remaps: &Remaps,
+        events: &[SerializableEventType],
+    ) -> Vec<Option<String>> {
+        let mut tracker = ChordTracker::new();
+        events
+            .iter()
+            .map(|event_type| {
+                let chord = tracker.update(event_type);
+                remapper
+                    .update(event_type, chord, tracker.held(), remaps)
+                    .map(|events| format!("{:?}", events))
+            })
+            .collect()
+    }
+
+    fn sent(events: &[SerializableEventType]) -> Option<String> {
+        Some(format!("{:?}", events))
+    }
+
+    #[test]
+    fn holds_the_target_as_long_as_the_source() {
+        let remaps = remaps(&[("Pause", "Ctrl+C")]);
+        let mut remapper = Remapper::new();
+        let results = feed(
+            &mut remapper,
+            &remaps,
+            &[
+                KeyPress(Pause),
+                // Auto-repeat while held is swallowed without sending anything
+                KeyPress(Pause),
+                KeyPress(Pause),
+                KeyRelease(Pause),
+                KeyPress(Escape),
+                KeyRelease(Escape),
+            ],
+        );
+        assert_eq!(
+            results,
+            [
+                sent(&[KeyPress(ControlLeft), KeyPress(KeyC)]),
+                sent(&[]),
+                sent(&[]),
+                sent(&[KeyRelease(KeyC), KeyRelease(ControlLeft)]),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn lifts_the_source_modifiers_around_the_target() {
+        let remaps = remaps(&[("Ctrl+KeyJ", "Escape")]);
+        let mut remapper = Remapper::new();
+        let results = feed(
+            &mut remapper,
+            &remaps,
+            &[
+                KeyPress(ControlRight),
+                KeyPress(KeyJ),
+                KeyRelease(KeyJ),
+                KeyRelease(ControlRight),
+            ],
+        );
+        assert_eq!(
+            results,
+            [
+                None,
+                sent(&[
+                    KeyRelease(ControlRight),
+                    KeyPress(Escape),
+                    KeyPress(ControlRight)
+                ]),
+                sent(&[
+                    KeyRelease(ControlRight),
+                    KeyRelease(Escape),
+                    KeyPress(ControlRight)
+                ]),
+                None,
+            ]
+        );
+
+        // Once the modifier is up, letting go of the source sends just the release
+        let mut remapper = Remapper::new();
+        let results = feed(
+            &mut remapper,
+            &remaps,
+            &[
+                KeyPress(ControlLeft),
+                KeyPress(KeyJ),
+                KeyRelease(ControlLeft),
+                KeyRelease(KeyJ),
+            ],
+        );
+        assert_eq!(results[3], sent(&[KeyRelease(Escape)]));
+    }
+
+    #[test]
+    fn remaps_to_a_modifier_on_its_own() {
+        let remaps = remaps(&[("CapsLock", "ControlLeft")]);
+        remaps.validate(&ControlKeys::default()).unwrap();
+
+        let mut remapper = Remapper::new();
+        let results = feed(
+            &mut remapper,
+            &remaps,
+            &[KeyPress(CapsLock), KeyPress(KeyC), KeyRelease(CapsLock)],
+        );
+        assert_eq!(
+            results,
+            [
+                sent(&[KeyPress(ControlLeft)]),
+                None,
+                sent(&[KeyRelease(ControlLeft)]),
+            ]
+        );
+
+        // A modifier on its own would never be seen as a source
+        let backwards = self::remaps(&[("ControlLeft", "CapsLock")]);
+        assert!(backwards.validate(&ControlKeys::default()).is_err());
+    }
+
+    #[test]
+    fn release_all_lets_go_of_held_targets() {
+        let remaps = remaps(&[("Pause", "Ctrl+C"), ("CapsLock", "Escape")]);
+        let mut remapper = Remapper::new();
+        feed(
+            &mut remapper,
+            &remaps,
+            &[KeyPress(Pause), KeyPress(CapsLock)],
+        );
+
+        // Latest first, in the same order a release would use
+        assert_eq!(
+            format!("{:?}", remapper.release_all()),
+            format!(
+                "{:?}",
+                [
+                    KeyRelease(Escape),
+                    KeyRelease(KeyC),
+                    KeyRelease(ControlLeft)
+                ]
+            )
+        );
+        assert!(remapper.release_all().is_empty());
+
+        // The source's release afterwards goes through as it is
+        assert_eq!(feed(&mut remapper, &remaps, &[KeyRelease(Pause)]), [None]);
+    }
+
+    #[test]
+    fn validate_rejects_chains_and_duplicates() {
+        let keys = ControlKeys::default();
+        assert!(remaps(&[("Pause", "Escape"), ("Escape", "KeyC")])
+            .validate(&keys)
+            .is_err());
+        assert!(remaps(&[("Pause", "Escape"), ("Pause", "KeyC")])
+            .validate(&keys)
+            .is_err());
+        assert!(remaps(&[("Comma", "KeyC")]).validate(&keys).is_err());
+        remaps(&[("Pause", "Escape"), ("CapsLock", "Escape")])
+            .validate(&keys)
+            .unwrap();
+    }
+}
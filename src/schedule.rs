@@ -0,0 +1,811 @@
+use crate::calls::resolve_call;
+use crate::config::{config_dir, load_config};
+use crate::log;
+use chrono::{
+    DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDateTime, TimeZone, Timelike,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// Schedules are kept in schedules.toml in the config directory, for example:
+//
+//   max_concurrent = 1
+//
+//   [[schedules]]
+//   name = "nightly export"
+//   run = "export.toml"
+//   cron = "0 2 * * 1-5"
+//   catch_up = "once"
+//
+//   [[schedules]]
+//   name = "keep awake"
+//   run = "/home/me/scripts/jiggle.bin"
+//   every_seconds = 240
+//
+// When each one last ran is kept separately in schedule-state.toml, so the
+// schedules file is only ever written by hand.
+const SCHEDULES_FILE: &str = "schedules.toml";
+const STATE_FILE: &str = "schedule-state.toml";
+
+// A run this late is still on time rather than missed
+const GRACE_SECONDS: i64 = 60;
+// Most missed runs caught up at once with CatchUp::All
+const MAX_CATCH_UP: usize = 100;
+// How far ahead to look for the next time a cron expression matches
+const CRON_SEARCH_MINUTES: usize = 4 * 366 * 24 * 60;
+
+// What to do about runs that were missed because the app wasn't running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUp {
+    #[default]
+    Skip,
+    // Run once for any number of missed runs
+    Once,
+    // Run once for every missed run
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub name: String,
+    // Script or playlist (.toml) to play, relative to the config directory
+    pub run: PathBuf,
+    // Standard five field cron expression, in local time
+    #[serde(default)]
+    pub cron: Option<String>,
+    #[serde(default)]
+    pub every_seconds: Option<u64>,
+    #[serde(default)]
+    pub catch_up: CatchUp,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedules {
+    // Most scheduled runs playing at the same time, a run started from the GUI
+    // counts towards it
+    #[serde(default = "one")]
+    pub max_concurrent: usize,
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+}
+
+impl Default for Schedules {
+    fn default() -> Self {
+        Schedules {
+            max_concurrent: 1,
+            schedules: Vec::new(),
+        }
+    }
+}
+
+impl Schedules {
+    pub fn path() -> Result<PathBuf, String> {
+        Ok(config_dir()?.join(SCHEDULES_FILE))
+    }
+
+    // No schedules file yet is the same as an empty one
+    pub fn load(path: &Path) -> Result<Schedules, String> {
+        let schedules: Schedules = load_config(path)?;
+        schedules.validate()?;
+        Ok(schedules)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_concurrent == 0 {
+            return Err("max_concurrent must be at least 1".to_string());
+        }
+        let mut names = Vec::new();
+        for schedule in &self.schedules {
+            if names.contains(&&schedule.name) {
+                return Err(format!(
+                    "Schedule \"{}\" is used more than once",
+                    schedule.name
+                ));
+            }
+            names.push(&schedule.name);
+            if let Trigger::Cron(cron) = schedule.trigger()? {
+                if cron.next_after(Local::now()).is_none() {
+                    return Err(format!("Schedule \"{}\" never runs", schedule.name));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// When a schedule fires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    Cron(Cron),
+    Every(u64),
+}
+
+impl Schedule {
+    pub fn trigger(&self) -> Result<Trigger, String> {
+        let trigger = match (&self.cron, self.every_seconds) {
+            (Some(cron), None) => Trigger::Cron(cron.parse()?),
+            (None, Some(0)) => return Err("every_seconds must be at least 1".to_string()),
+            (None, Some(seconds)) => Trigger::Every(seconds),
+            _ => {
+                return Err(format!(
+                    "Schedule \"{}\" needs either cron or every_seconds",
+                    self.name
+                ))
+            }
+        };
+        Ok(trigger)
+    }
+
+    // Times after `after` and up to `until` the schedule fires at, at most `limit`
+    pub fn fire_times(
+        &self,
+        after: DateTime<Local>,
+        until: DateTime<Local>,
+        limit: usize,
+    ) -> Vec<DateTime<Local>> {
+        let Ok(trigger) = self.trigger() else {
+            return Vec::new();
+        };
+
+        let mut times = Vec::new();
+        let mut time = after;
+        while times.len() < limit {
+            let next = match &trigger {
+                Trigger::Cron(cron) => cron.next_after(time),
+                Trigger::Every(seconds) => Some(time + ChronoDuration::seconds(*seconds as i64)),
+            };
+            match next {
+                Some(next) if next <= until => {
+                    times.push(next);
+                    time = next;
+                }
+                _ => break,
+            }
+        }
+        times
+    }
+
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self.trigger().ok()? {
+            Trigger::Cron(cron) => cron.next_after(time),
+            Trigger::Every(seconds) => Some(time + ChronoDuration::seconds(seconds as i64)),
+        }
+    }
+
+    // Last time after `after` and up to `until` the schedule fires at, found
+    // without stepping through every time in between
+    pub fn latest_due(
+        &self,
+        after: DateTime<Local>,
+        until: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        let latest = match self.trigger().ok()? {
+            Trigger::Cron(cron) => cron.last_at_or_before(until)?,
+            Trigger::Every(seconds) => {
+                let periods = (until - after).num_seconds() / seconds as i64;
+                after + ChronoDuration::seconds(periods * seconds as i64)
+            }
+        };
+        Some(latest).filter(|latest| *latest > after)
+    }
+}
+
+// Minute, hour, day of month, month and day of week, each a set of allowed values
+// as bits. Fields take *, numbers, ranges (1-5), steps (*/15, 0-30/10) and lists
+// of those (1,15). Day of week is 0-6 from Sunday, 7 is Sunday as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Whether day of month and day of week were restricted, when both are a
+    // day matching either is enough
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "\"{}\" should have 5 fields: minute hour day month weekday",
+                text
+            ));
+        };
+
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+        // 7 is also Sunday
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let number = |text: &str| -> Result<u32, String> {
+        text.parse::<u32>()
+            .ok()
+            .filter(|value| (min..=max).contains(value))
+            .ok_or_else(|| format!("\"{}\" is not a number from {} to {}", text, min, max))
+    };
+
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, number(step).ok().filter(|step| *step > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(|| format!("\"{}\" has an invalid step", part))?;
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // A single value with a step runs to the end, like 5/15
+                None if part.contains('/') => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("\"{}\" is a backwards range", part));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl Cron {
+    fn day_matches(&self, time: &NaiveDateTime) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    // First whole minute after `time` that the expression matches
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut naive =
+            time.naive_local().with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+
+        // Skip whole days and hours that can't match instead of every minute
+        let mut searched = 0;
+        while searched < CRON_SEARCH_MINUTES {
+            if !has(self.months, naive.month()) || !self.day_matches(&naive) {
+                let next_day = naive.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                searched += (next_day - naive).num_minutes() as usize;
+                naive = next_day;
+            } else if !has(self.hours, naive.hour()) {
+                let next_hour = naive.with_minute(0)? + ChronoDuration::hours(1);
+                searched += (next_hour - naive).num_minutes() as usize;
+                naive = next_hour;
+            } else if !has(self.minutes, naive.minute()) {
+                naive += ChronoDuration::minutes(1);
+                searched += 1;
+            } else if let Some(local) = Local.from_local_datetime(&naive).earliest() {
+                return Some(local);
+            } else {
+                // Skipped over by a daylight saving change
+                naive += ChronoDuration::minutes(1);
+                searched += 1;
+            }
+        }
+        None
+    }
+
+    // Last whole minute at or before `time` that the expression matches
+    pub fn last_at_or_before(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut naive = time.naive_local().with_second(0)?.with_nanosecond(0)?;
+
+        // Skip back over whole days and hours that can't match
+        let mut searched = 0;
+        while searched < CRON_SEARCH_MINUTES {
+            if !has(self.months, naive.month()) || !self.day_matches(&naive) {
+                let previous_day = naive.date().pred_opt()?.and_hms_opt(23, 59, 0)?;
+                searched += (naive - previous_day).num_minutes() as usize;
+                naive = previous_day;
+            } else if !has(self.hours, naive.hour()) {
+                let previous_hour = naive.with_minute(0)? - ChronoDuration::minutes(1);
+                searched += (naive - previous_hour).num_minutes() as usize;
+                naive = previous_hour;
+            } else if !has(self.minutes, naive.minute()) {
+                naive -= ChronoDuration::minutes(1);
+                searched += 1;
+            } else if let Some(local) = Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .filter(|local| *local <= time)
+            {
+                return Some(local);
+            } else {
+                naive -= ChronoDuration::minutes(1);
+                searched += 1;
+            }
+        }
+        None
+    }
+}
+
+// A run the scheduler has started
+#[derive(Debug)]
+pub struct ScheduledRun {
+    pub schedule: Schedule,
+    // Resolved path of the script or playlist
+    pub path: PathBuf,
+    // Cleared to stop the run, and by the run once it's finished
+    pub run: Arc<AtomicBool>,
+    // Counted as playing until the run is dropped
+    _playing: Playing,
+}
+
+// Keeps a run in the scheduler's count of playing runs while it's alive
+#[derive(Debug)]
+struct Playing(Arc<AtomicUsize>);
+
+impl Playing {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Playing(Arc::clone(count))
+    }
+}
+
+impl Drop for Playing {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Decides which schedules are due and hands out runs within the concurrency
+// limit. Whoever owns it calls `check` regularly and plays what `start` returns.
+pub struct Scheduler {
+    pub schedules: Schedules,
+    // Where relative paths in schedules are resolved from
+    schedules_path: Option<PathBuf>,
+    // Unix time each schedule last fired at, by name
+    last_fired: BTreeMap<String, i64>,
+    state_path: Option<PathBuf>,
+    // Schedules that never fired count from here, so adding one doesn't catch up
+    started: DateTime<Local>,
+    // Indices of schedules waiting for a free slot
+    pending: VecDeque<usize>,
+    running: Vec<Arc<AtomicBool>>,
+    // Runs that haven't finished yet, stopped ones included
+    playing: Arc<AtomicUsize>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new(Schedules::default())
+    }
+}
+
+impl Scheduler {
+    pub fn new(schedules: Schedules) -> Self {
+        Scheduler {
+            schedules,
+            schedules_path: None,
+            last_fired: BTreeMap::new(),
+            state_path: None,
+            started: Local::now(),
+            pending: VecDeque::new(),
+            running: Vec::new(),
+            playing: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Load the schedules and their state from the config directory
+    pub fn load() -> Result<Self, String> {
+        let path = Schedules::path()?;
+        let mut scheduler = Scheduler::new(Schedules::load(&path)?);
+        scheduler.schedules_path = Some(path);
+
+        let state_path = config_dir()?.join(STATE_FILE);
+        scheduler.last_fired = load_config(&state_path)?;
+        scheduler.state_path = Some(state_path);
+        Ok(scheduler)
+    }
+
+    // Pick up edits to the schedules file, keeping runs that are playing
+    pub fn reload(&mut self) -> Result<(), String> {
+        let path = match &self.schedules_path {
+            Some(path) => path.clone(),
+            None => Schedules::path()?,
+        };
+        self.schedules = Schedules::load(&path)?;
+        self.schedules_path = Some(path);
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn save_state(&self) -> Result<(), String> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+        let text = toml::to_string(&self.last_fired)
+            .map_err(|e| format!("Could not encode schedule state: {}", e))?;
+        fs::write(path, text).map_err(|e| format!("Could not write to file: {}", e))
+    }
+
+    // Queue every schedule that has come due since it last fired, following its
+    // catch-up rule for the ones that were missed
+    pub fn check(&mut self, now: DateTime<Local>) -> Result<(), String> {
+        let mut fired = false;
+        for (index, schedule) in self.schedules.schedules.iter().enumerate() {
+            if !schedule.enabled {
+                continue;
+            }
+
+            let last = self.last_fired_at(schedule);
+            let Some(latest) = schedule.latest_due(last, now) else {
+                continue;
+            };
+            // Only counted up to the cap, however long the app was closed
+            let due = schedule.fire_times(last, now, MAX_CATCH_UP).len().max(1);
+
+            let on_time = now - latest <= ChronoDuration::seconds(GRACE_SECONDS);
+            let runs = match schedule.catch_up {
+                CatchUp::Skip => on_time as usize,
+                CatchUp::Once => 1,
+                CatchUp::All => due,
+            };
+            if due > 1 || !on_time {
+                let missed = due - on_time as usize;
+                log(&format!(
+                    "Schedule \"{}\" missed {}{} runs, catching up {}",
+                    schedule.name,
+                    missed,
+                    if due == MAX_CATCH_UP { " or more" } else { "" },
+                    runs - on_time as usize
+                ));
+            }
+            self.pending.extend(std::iter::repeat_n(index, runs));
+            self.last_fired
+                .insert(schedule.name.clone(), latest.timestamp());
+            fired = true;
+        }
+
+        if fired {
+            self.save_state()?;
+        }
+        Ok(())
+    }
+
+    // Number of scheduled runs still playing
+    pub fn running(&mut self) -> usize {
+        self.running.retain(|run| run.load(Ordering::Relaxed));
+        self.running.len()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Number of scheduled runs that haven't finished, readable without locking
+    // the scheduler, for input handling that mustn't wait on it
+    pub fn playing(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.playing)
+    }
+
+    // Next queued run, if starting it keeps within max_concurrent along with
+    // `others` runs that weren't started by the scheduler
+    pub fn start(&mut self, others: usize) -> Option<ScheduledRun> {
+        if self.running() + others >= self.schedules.max_concurrent {
+            return None;
+        }
+        let schedule = self
+            .schedules
+            .schedules
+            .get(self.pending.pop_front()?)?
+            .clone();
+        let run = Arc::new(AtomicBool::new(true));
+        self.running.push(Arc::clone(&run));
+        Some(ScheduledRun {
+            path: resolve_call(self.schedules_path.as_deref(), &schedule.run),
+            schedule,
+            run,
+            _playing: Playing::new(&self.playing),
+        })
+    }
+
+    // Stop every scheduled run and forget the queued ones. Anything that came due
+    // since the last check counts as fired too, so the next check doesn't start
+    // it again straight after the stop.
+    pub fn stop_all(&mut self, now: DateTime<Local>) -> Result<(), String> {
+        for run in &self.running {
+            run.store(false, Ordering::Relaxed);
+        }
+        self.pending.clear();
+
+        let mut fired = false;
+        for schedule in self.schedules.schedules.iter().filter(|s| s.enabled) {
+            if let Some(latest) = schedule.latest_due(self.last_fired_at(schedule), now) {
+                self.last_fired
+                    .insert(schedule.name.clone(), latest.timestamp());
+                fired = true;
+            }
+        }
+        if fired {
+            self.save_state()?;
+        }
+        Ok(())
+    }
+
+    // When a schedule last fired, or when the scheduler started if it never has
+    fn last_fired_at(&self, schedule: &Schedule) -> DateTime<Local> {
+        self.last_fired
+            .get(&schedule.name)
+            .and_then(|time| Local.timestamp_opt(*time, 0).single())
+            .unwrap_or(self.started)
+    }
+
+    // The enabled schedule that fires soonest
+    pub fn next(&self) -> Option<(&Schedule, DateTime<Local>)> {
+        self.schedules
+            .schedules
+            .iter()
+            .filter(|schedule| schedule.enabled)
+            .filter_map(|schedule| {
+                Some((schedule, schedule.next_after(self.last_fired_at(schedule))?))
+            })
+            .min_by_key(|(_, time)| *time)
+    }
+
+    // One line for the GUI
+    pub fn summary(&mut self, now: DateTime<Local>) -> String {
+        let running = self.running();
+        if running > 0 {
+            return format!("{} running, {} queued", running, self.pending.len());
+        }
+        match self.next() {
+            Some((schedule, time)) if time.date_naive() == now.date_naive() => {
+                format!("{} at {}", schedule.name, time.format("%H:%M"))
+            }
+            Some((schedule, time)) => format!("{} {}", schedule.name, time.format("%a %H:%M")),
+            None if self.schedules.schedules.is_empty() => "None".to_string(),
+            None => "Nothing due".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(cron: Option<&str>, every_seconds: Option<u64>, catch_up: CatchUp) -> Schedule {
+        Schedule {
+            name: "test".to_string(),
+            run: PathBuf::from("test.bin"),
+            cron: cron.map(str::to_string),
+            every_seconds,
+            catch_up,
+            enabled: true,
+        }
+    }
+
+    fn at(text: &str) -> DateTime<Local> {
+        let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        Local.from_local_datetime(&naive).earliest().unwrap()
+    }
+
+    #[test]
+    fn parses_cron_fields() {
+        let cron: Cron = "*/15 9-17 * * 1-5".parse().unwrap();
+        assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.hours, (9..=17).map(|hour| 1 << hour).sum::<u64>());
+        assert!(!cron.days_restricted && cron.weekdays_restricted);
+
+        let cron: Cron = "0,30 5/6 1 1 7".parse().unwrap();
+        assert_eq!(cron.minutes, 1 | 1 << 30);
+        assert_eq!(cron.hours, 1 << 5 | 1 << 11 | 1 << 17 | 1 << 23);
+        // 7 is Sunday as well as 0
+        assert!(has(cron.weekdays, 0));
+    }
+
+    #[test]
+    fn rejects_bad_cron_expressions() {
+        for text in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "a * * * *",
+        ] {
+            assert!(text.parse::<Cron>().is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn cron_finds_the_next_match() {
+        let cron: Cron = "30 2 * * *".parse().unwrap();
+        assert_eq!(
+            cron.next_after(at("2024-03-04 01:00")),
+            Some(at("2024-03-04 02:30"))
+        );
+        assert_eq!(
+            cron.next_after(at("2024-03-04 02:30")),
+            Some(at("2024-03-05 02:30"))
+        );
+
+        // With both days restricted either one matching is enough: the 13th or
+        // a Friday, and 2024-03-08 is a Friday
+        let cron: Cron = "0 0 13 * 5".parse().unwrap();
+        assert_eq!(
+            cron.next_after(at("2024-03-04 00:00")),
+            Some(at("2024-03-08 00:00"))
+        );
+        assert_eq!(
+            cron.next_after(at("2024-03-08 00:00")),
+            Some(at("2024-03-13 00:00"))
+        );
+
+        // 30 February never comes
+        let cron: Cron = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(cron.next_after(at("2024-03-04 00:00")), None);
+    }
+
+    #[test]
+    fn lists_fire_times_up_to_the_limit() {
+        let every = schedule(None, Some(60), CatchUp::Skip);
+        let after = at("2024-03-04 10:00");
+        let until = after + ChronoDuration::seconds(200);
+        assert_eq!(
+            every.fire_times(after, until, 100),
+            vec![
+                after + ChronoDuration::seconds(60),
+                after + ChronoDuration::seconds(120),
+                after + ChronoDuration::seconds(180),
+            ]
+        );
+        assert_eq!(every.fire_times(after, until, 2).len(), 2);
+
+        let hourly = schedule(Some("0 * * * *"), None, CatchUp::Skip);
+        assert_eq!(
+            hourly.fire_times(at("2024-03-04 10:00"), at("2024-03-04 12:30"), 100),
+            vec![at("2024-03-04 11:00"), at("2024-03-04 12:00")]
+        );
+    }
+
+    #[test]
+    fn finds_latest_due_time_directly() {
+        let every = schedule(None, Some(60), CatchUp::Skip);
+        let after = at("2024-03-04 10:00");
+        assert_eq!(
+            every.latest_due(after, after + ChronoDuration::seconds(150)),
+            Some(after + ChronoDuration::seconds(120))
+        );
+        assert_eq!(
+            every.latest_due(after, after + ChronoDuration::seconds(59)),
+            None
+        );
+
+        let nightly = schedule(Some("0 2 * * *"), None, CatchUp::Skip);
+        assert_eq!(
+            nightly.latest_due(at("2024-01-01 00:00"), at("2024-03-04 10:00")),
+            Some(at("2024-03-04 02:00"))
+        );
+        assert_eq!(
+            nightly.latest_due(at("2024-03-04 02:00"), at("2024-03-04 10:00")),
+            None
+        );
+    }
+
+    #[test]
+    fn catching_up_all_is_capped_but_moves_to_the_latest_run() {
+        let now = Local::now();
+        let mut scheduler = Scheduler::new(Schedules {
+            max_concurrent: 1,
+            schedules: vec![schedule(None, Some(60), CatchUp::All)],
+        });
+        let last = now - ChronoDuration::minutes(1000);
+        scheduler
+            .last_fired
+            .insert("test".to_string(), last.timestamp());
+
+        scheduler.check(now).unwrap();
+        assert_eq!(scheduler.pending(), MAX_CATCH_UP);
+        let fired = scheduler.last_fired["test"];
+        assert!(now.timestamp() - fired < 60);
+
+        // Nothing is left over to catch up on the next check
+        scheduler.check(now).unwrap();
+        assert_eq!(scheduler.pending(), MAX_CATCH_UP);
+    }
+
+    #[test]
+    fn catching_up_once_runs_once() {
+        let now = Local::now();
+        let mut scheduler = Scheduler::new(Schedules {
+            max_concurrent: 1,
+            schedules: vec![schedule(None, Some(60), CatchUp::Once)],
+        });
+        scheduler.last_fired.insert(
+            "test".to_string(),
+            (now - ChronoDuration::minutes(1000)).timestamp(),
+        );
+
+        scheduler.check(now).unwrap();
+        assert_eq!(scheduler.pending(), 1);
+    }
+
+    #[test]
+    fn stopping_forgets_queued_and_newly_due_runs() {
+        let now = Local::now();
+        let mut scheduler = Scheduler::new(Schedules {
+            max_concurrent: 1,
+            schedules: vec![schedule(None, Some(60), CatchUp::All)],
+        });
+        scheduler.last_fired.insert(
+            "test".to_string(),
+            (now - ChronoDuration::minutes(5)).timestamp(),
+        );
+
+        scheduler.check(now).unwrap();
+        let playing = scheduler.playing();
+        let run = scheduler.start(0).unwrap();
+        assert!(scheduler.pending() > 0);
+        assert_eq!(playing.load(Ordering::Relaxed), 1);
+
+        // A run comes due between the last check and the stop
+        let later = now + ChronoDuration::seconds(61);
+        scheduler.stop_all(later).unwrap();
+        assert!(!run.run.load(Ordering::Relaxed));
+        assert_eq!(scheduler.pending(), 0);
+        // Still playing until the run has actually ended
+        assert_eq!(playing.load(Ordering::Relaxed), 1);
+        drop(run);
+        assert_eq!(playing.load(Ordering::Relaxed), 0);
+
+        scheduler.check(later).unwrap();
+        assert_eq!(scheduler.pending(), 0);
+
+        // Later runs still fire as usual
+        scheduler
+            .check(later + ChronoDuration::seconds(60))
+            .unwrap();
+        assert_eq!(scheduler.pending(), 1);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::capture::Region;
+use crate::script::MouseAnchor;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// A single display, in the same global coordinate space as recorded mouse events
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Monitor {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Monitor {
+    fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    fn is_empty(&self) -> bool {
+        self.width <= 0.0 || self.height <= 0.0
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (self.x + self.width / 2.0, self.y + self.height / 2.0)
+    }
+
+    // Scale each axis independently so this monitor fills the other one
+    fn stretch_to(&self, to: &Monitor, x: f64, y: f64) -> (f64, f64) {
+        if self.is_empty() {
+            return (x, y);
+        }
+
+        (
+            to.x + (x - self.x) * to.width / self.width,
+            to.y + (y - self.y) * to.height / self.height,
+        )
+    }
+
+    // Scale both axes by the same amount and center the result, keeping the aspect ratio
+    fn letterbox_to(&self, to: &Monitor, x: f64, y: f64) -> (f64, f64) {
+        if self.is_empty() {
+            return (x, y);
+        }
+
+        let scale = (to.width / self.width).min(to.height / self.height);
+        let padding_x = (to.width - self.width * scale) / 2.0;
+        let padding_y = (to.height - self.height * scale) / 2.0;
+        (
+            to.x + padding_x + (x - self.x) * scale,
+            to.y + padding_y + (y - self.y) * scale,
+        )
+    }
+}
+
+// Layout of every display, primary monitor first
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ScreenGeometry {
+    pub monitors: Vec<Monitor>,
+}
+
+impl ScreenGeometry {
+    // Geometry of the displays attached right now
+    pub fn current() -> Option<ScreenGeometry> {
+        let monitors = current_monitors();
+        if monitors.is_empty() {
+            None
+        } else {
+            Some(ScreenGeometry { monitors })
+        }
+    }
+
+    // Smallest rectangle covering every monitor
+    pub fn bounds(&self) -> Monitor {
+        let left = self.monitors.iter().map(|m| m.x).fold(f64::MAX, f64::min);
+        let top = self.monitors.iter().map(|m| m.y).fold(f64::MAX, f64::min);
+        let right = self
+            .monitors
+            .iter()
+            .map(|m| m.x + m.width)
+            .fold(f64::MIN, f64::max);
+        let bottom = self
+            .monitors
+            .iter()
+            .map(|m| m.y + m.height)
+            .fold(f64::MIN, f64::max);
+
+        Monitor {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    fn monitor_at(&self, x: f64, y: f64) -> usize {
+        self.monitors
+            .iter()
+            .position(|m| m.contains(x, y))
+            .unwrap_or(0)
+    }
+
+    // Monitor of this layout that stands in for `monitor` of the recorded one:
+    // the same monitor if it's still attached, otherwise the one in the same
+    // place on the desktop. Monitors are listed in whatever order the system
+    // reports them, so their index can't be relied on after replugging.
+    fn matching(&self, recorded: &ScreenGeometry, monitor: &Monitor) -> &Monitor {
+        if let Some(same) = self.monitors.iter().find(|m| *m == monitor) {
+            return same;
+        }
+
+        let (x, y) = monitor.center();
+        let (x, y) = recorded.bounds().stretch_to(&self.bounds(), x, y);
+        self.monitors
+            .iter()
+            .min_by(|a, b| {
+                let distance = |m: &Monitor| {
+                    let (center_x, center_y) = m.center();
+                    (center_x - x).hypot(center_y - y)
+                };
+                distance(a).total_cmp(&distance(b))
+            })
+            .unwrap_or(&self.monitors[0])
+    }
+}
+
+// How recorded coordinates are fitted to a display that differs from the recording one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ScreenScaling {
+    // Replay coordinates unchanged
+    #[default]
+    None,
+    // Scale the whole recorded desktop to the current one, axes independently
+    Stretch,
+    // Scale the whole recorded desktop keeping its aspect ratio, centered
+    Letterbox,
+    // Map each recorded monitor onto the same monitor, or the one in the same
+    // position on the current desktop
+    PerMonitor,
+}
+
+impl ScreenScaling {
+    pub const ALL: [ScreenScaling; 4] = [
+        ScreenScaling::None,
+        ScreenScaling::Stretch,
+        ScreenScaling::Letterbox,
+        ScreenScaling::PerMonitor,
+    ];
+}
+
+impl fmt::Display for ScreenScaling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScreenScaling::None => "No Scaling",
+            ScreenScaling::Stretch => "Stretch",
+            ScreenScaling::Letterbox => "Letterbox",
+            ScreenScaling::PerMonitor => "Per Monitor",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Turns recorded mouse coordinates into the coordinates sent during playback
+#[derive(Debug, Clone)]
+pub struct CoordinateMapper {
+    scaling: ScreenScaling,
+    recorded: Option<ScreenGeometry>,
+    current: Option<ScreenGeometry>,
+    offset: (f64, f64),
+    // Set while following a recorded window, replaces scaling and the anchor
+    window_offset: Option<(f64, f64)>,
+}
+
+impl CoordinateMapper {
+    pub fn new(
+        scaling: ScreenScaling,
+        recorded: Option<ScreenGeometry>,
+        current: Option<ScreenGeometry>,
+        anchor: MouseAnchor,
+        first_recorded: Option<(f64, f64)>,
+        cursor: Option<(f64, f64)>,
+    ) -> Self {
+        let mut mapper = CoordinateMapper {
+            scaling,
+            recorded,
+            current,
+            offset: (0.0, 0.0),
+            window_offset: None,
+        };
+
+        // The anchor works on already scaled positions so both can be combined
+        let first_scaled = first_recorded.map(|(x, y)| mapper.scale(x, y));
+        mapper.offset = anchor.offset(first_scaled, cursor);
+        mapper
+    }
+
+    // Move recorded positions inside `recorded` to the same spot inside `current`
+    pub fn follow_window(&mut self, recorded: Region, current: Region) {
+        self.window_offset = Some((
+            (current.x - recorded.x) as f64,
+            (current.y - recorded.y) as f64,
+        ));
+    }
+
+    pub fn unfollow_window(&mut self) {
+        self.window_offset = None;
+    }
+
+    pub fn map(&self, x: f64, y: f64) -> (f64, f64) {
+        if let Some((dx, dy)) = self.window_offset {
+            return (x + dx, y + dy);
+        }
+
+        let (x, y) = self.scale(x, y);
+        (x + self.offset.0, y + self.offset.1)
+    }
+
+    // Map both corners of a recorded region
+    pub fn map_region(&self, region: Region) -> Region {
+        let (left, top) = self.map(region.x as f64, region.y as f64);
+        let (right, bottom) = self.map(
+            region.x as f64 + region.width as f64,
+            region.y as f64 + region.height as f64,
+        );
+        Region {
+            x: left.round() as i32,
+            y: top.round() as i32,
+            width: (right - left).round().max(1.0) as u32,
+            height: (bottom - top).round().max(1.0) as u32,
+        }
+    }
+
+    fn scale(&self, x: f64, y: f64) -> (f64, f64) {
+        let (Some(recorded), Some(current)) = (&self.recorded, &self.current) else {
+            return (x, y);
+        };
+        if recorded.monitors.is_empty() || current.monitors.is_empty() {
+            return (x, y);
+        }
+
+        match self.scaling {
+            ScreenScaling::None => (x, y),
+            ScreenScaling::Stretch => recorded.bounds().stretch_to(&current.bounds(), x, y),
+            ScreenScaling::Letterbox => recorded.bounds().letterbox_to(&current.bounds(), x, y),
+            ScreenScaling::PerMonitor => {
+                let monitor = &recorded.monitors[recorded.monitor_at(x, y)];
+                monitor.stretch_to(current.matching(recorded, monitor), x, y)
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn current_monitors() -> Vec<Monitor> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::randr::ConnectionExt as _;
+
+    let monitors = x11rb::connect(None).ok().and_then(|(conn, screen_num)| {
+        let root = conn.setup().roots[screen_num].root;
+        let reply = conn.randr_get_monitors(root, true).ok()?.reply().ok()?;
+
+        let mut monitors: Vec<(bool, Monitor)> = reply
+            .monitors
+            .iter()
+            .map(|m| {
+                (
+                    m.primary,
+                    Monitor {
+                        x: m.x as f64,
+                        y: m.y as f64,
+                        width: m.width as f64,
+                        height: m.height as f64,
+                    },
+                )
+            })
+            .collect();
+        monitors.sort_by_key(|(primary, _)| !primary);
+        Some(monitors.into_iter().map(|(_, m)| m).collect::<Vec<_>>())
+    });
+
+    match monitors {
+        Some(monitors) if !monitors.is_empty() => monitors,
+        _ => display_size_monitor(),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn current_monitors() -> Vec<Monitor> {
+    use core_graphics::display::CGDisplay;
+
+    let main_id = CGDisplay::main().id;
+    let mut ids = CGDisplay::active_displays().unwrap_or_default();
+    ids.sort_by_key(|id| *id != main_id);
+
+    ids.into_iter()
+        .map(|id| {
+            let bounds = CGDisplay::new(id).bounds();
+            Monitor {
+                x: bounds.origin.x,
+                y: bounds.origin.y,
+                width: bounds.size.width,
+                height: bounds.size.height,
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn current_monitors() -> Vec<Monitor> {
+    display_size_monitor()
+}
+
+// Fallback when the platform can't list monitors: just the primary display size
+#[cfg(not(target_os = "macos"))]
+fn display_size_monitor() -> Vec<Monitor> {
+    match rdev::display_size() {
+        Ok((width, height)) => vec![Monitor {
+            x: 0.0,
+            y: 0.0,
+            width: width as f64,
+            height: height as f64,
+        }],
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(x: f64, y: f64, width: f64, height: f64) -> Monitor {
+        Monitor {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn screen(monitors: &[Monitor]) -> Option<ScreenGeometry> {
+        Some(ScreenGeometry {
+            monitors: monitors.to_vec(),
+        })
+    }
+
+    fn mapper(
+        scaling: ScreenScaling,
+        recorded: &[Monitor],
+        current: &[Monitor],
+    ) -> CoordinateMapper {
+        CoordinateMapper::new(
+            scaling,
+            screen(recorded),
+            screen(current),
+            MouseAnchor::Absolute,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn stretches_and_letterboxes_the_desktop() {
+        let recorded = [monitor(0.0, 0.0, 2560.0, 1440.0)];
+        let wide = [monitor(0.0, 0.0, 1920.0, 1080.0)];
+        let stretch = mapper(ScreenScaling::Stretch, &recorded, &wide);
+        assert_eq!(stretch.map(1280.0, 720.0), (960.0, 540.0));
+        assert_eq!(stretch.map(2560.0, 0.0), (1920.0, 0.0));
+
+        // A 4:3 screen keeps the aspect ratio with bars above and below
+        let square = [monitor(0.0, 0.0, 1280.0, 1024.0)];
+        let letterbox = mapper(ScreenScaling::Letterbox, &recorded, &square);
+        assert_eq!(letterbox.map(0.0, 0.0), (0.0, 152.0));
+        assert_eq!(letterbox.map(2560.0, 1440.0), (1280.0, 872.0));
+
+        let none = mapper(ScreenScaling::None, &recorded, &wide);
+        assert_eq!(none.map(2000.0, 1000.0), (2000.0, 1000.0));
+    }
+
+    #[test]
+    fn leaves_positions_alone_without_geometry() {
+        let mapper = CoordinateMapper::new(
+            ScreenScaling::Stretch,
+            None,
+            screen(&[monitor(0.0, 0.0, 800.0, 600.0)]),
+            MouseAnchor::Absolute,
+            None,
+            None,
+        );
+        assert_eq!(mapper.map(1000.0, 900.0), (1000.0, 900.0));
+    }
+
+    #[test]
+    fn anchors_after_scaling() {
+        let mapper = CoordinateMapper::new(
+            ScreenScaling::Stretch,
+            screen(&[monitor(0.0, 0.0, 2000.0, 1000.0)]),
+            screen(&[monitor(0.0, 0.0, 1000.0, 500.0)]),
+            MouseAnchor::Cursor,
+            Some((200.0, 100.0)),
+            Some((300.0, 300.0)),
+        );
+        // The first recorded position lands on the cursor
+        assert_eq!(mapper.map(200.0, 100.0), (300.0, 300.0));
+        assert_eq!(mapper.map(400.0, 100.0), (400.0, 300.0));
+    }
+
+    #[test]
+    fn maps_monitors_by_geometry_not_order() {
+        let left = monitor(0.0, 0.0, 1920.0, 1080.0);
+        let right = monitor(1920.0, 0.0, 2560.0, 1440.0);
+        // Same monitors, listed the other way round after replugging
+        let mapper = mapper(ScreenScaling::PerMonitor, &[left, right], &[right, left]);
+        assert_eq!(mapper.map(100.0, 100.0), (100.0, 100.0));
+        assert_eq!(mapper.map(2000.0, 100.0), (2000.0, 100.0));
+    }
+
+    #[test]
+    fn maps_replaced_monitors_by_position() {
+        let recorded = [
+            monitor(0.0, 0.0, 1920.0, 1080.0),
+            monitor(1920.0, 0.0, 1920.0, 1080.0),
+        ];
+        // The right monitor is now a smaller one, listed first
+        let current = [
+            monitor(1920.0, 0.0, 1280.0, 720.0),
+            monitor(0.0, 0.0, 1920.0, 1080.0),
+        ];
+        let mapper = mapper(ScreenScaling::PerMonitor, &recorded, &current);
+        assert_eq!(mapper.map(100.0, 100.0), (100.0, 100.0));
+        assert_eq!(mapper.map(1920.0 + 960.0, 540.0), (1920.0 + 640.0, 360.0));
+    }
+
+    #[test]
+    fn maps_regions_through_both_corners() {
+        let mapper = mapper(
+            ScreenScaling::Stretch,
+            &[monitor(0.0, 0.0, 200.0, 200.0)],
+            &[monitor(0.0, 0.0, 100.0, 100.0)],
+        );
+        let region = mapper.map_region(Region {
+            x: 20,
+            y: 40,
+            width: 60,
+            height: 1,
+        });
+        assert_eq!(
+            region,
+            Region {
+                x: 10,
+                y: 20,
+                width: 30,
+                height: 1
+            }
+        );
+    }
+}
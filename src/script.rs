@@ -0,0 +1,386 @@
+use crate::calls::{find_cycle, relative_path, resolve_call};
+use crate::interpolate::Interpolation;
+use crate::layout::{translate_layout, KeyboardLayout, TranslationReport};
+use crate::screen::{ScreenGeometry, ScreenScaling};
+use crate::serializable_event::{SerializableEvent, SerializableEventType};
+use crate::simplify::{simplify_mouse_moves, SimplifyOptions};
+use crate::variables::Variables;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// Script files start with this marker so they can be told apart from the older
+// format, which was a bare bincode encoded Vec<SerializableEvent>
+const SCRIPT_MAGIC: &[u8; 4] = b"SS2S";
+// bincode isn't self describing, so bump this whenever a field is added to
+// Script and keep a layout below that older files can still be read with
+const SCRIPT_VERSION: u32 = 5;
+
+// Layouts of earlier format versions, missing fields are filled in with defaults
+#[derive(Deserialize)]
+struct ScriptV1 {
+    mouse_anchor: MouseAnchor,
+    events: Vec<SerializableEvent>,
+}
+
+// Version 2 recorded the screen
+#[derive(Deserialize)]
+struct ScriptV2 {
+    mouse_anchor: MouseAnchor,
+    screen_scaling: ScreenScaling,
+    screen: Option<ScreenGeometry>,
+    events: Vec<SerializableEvent>,
+}
+
+// Version 3 added mouse path interpolation
+#[derive(Deserialize)]
+struct ScriptV3 {
+    mouse_anchor: MouseAnchor,
+    screen_scaling: ScreenScaling,
+    screen: Option<ScreenGeometry>,
+    interpolation: Interpolation,
+    events: Vec<SerializableEvent>,
+}
+
+// Version 4 added the keyboard layout, version 5 variables and the data source
+#[derive(Deserialize)]
+struct ScriptV4 {
+    mouse_anchor: MouseAnchor,
+    screen_scaling: ScreenScaling,
+    screen: Option<ScreenGeometry>,
+    interpolation: Interpolation,
+    keyboard_layout: KeyboardLayout,
+    events: Vec<SerializableEvent>,
+}
+
+// Where recorded mouse coordinates are placed on screen during playback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MouseAnchor {
+    // Replay coordinates exactly where they were recorded
+    #[default]
+    Absolute,
+    // Replay coordinates as offsets from the first recorded position, starting
+    // from wherever the cursor is when playback begins
+    Cursor,
+    // Replay coordinates relative to the window they were recorded in, focusing
+    // that window again first
+    Window,
+}
+
+impl MouseAnchor {
+    pub const ALL: [MouseAnchor; 3] = [
+        MouseAnchor::Absolute,
+        MouseAnchor::Cursor,
+        MouseAnchor::Window,
+    ];
+
+    // Offset to add to every recorded MouseMove for this run
+    pub fn offset(
+        &self,
+        first_recorded: Option<(f64, f64)>,
+        cursor: Option<(f64, f64)>,
+    ) -> (f64, f64) {
+        match (self, first_recorded, cursor) {
+            (MouseAnchor::Cursor, Some((first_x, first_y)), Some((cursor_x, cursor_y))) => {
+                (cursor_x - first_x, cursor_y - first_y)
+            }
+            _ => (0.0, 0.0),
+        }
+    }
+}
+
+impl std::fmt::Display for MouseAnchor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MouseAnchor::Absolute => "Screen",
+            MouseAnchor::Cursor => "Cursor",
+            MouseAnchor::Window => "Window",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A recorded script along with the settings it should be played back with
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Script {
+    pub mouse_anchor: MouseAnchor,
+    pub screen_scaling: ScreenScaling,
+    // Displays the script was recorded on, if they could be detected
+    pub screen: Option<ScreenGeometry>,
+    pub interpolation: Interpolation,
+    // Layout of the keyboard the script was recorded on
+    pub keyboard_layout: KeyboardLayout,
+    // Default variable values, data source columns take precedence
+    pub variables: Variables,
+    // CSV file to play the script once per row of, instead of the loop count.
+    // Relative to the script's file once it's saved.
+    pub data_source: Option<PathBuf>,
+    pub events: Vec<SerializableEvent>,
+    // File the script was loaded from or last saved to, Call steps with relative
+    // paths are resolved from its directory
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+impl Script {
+    pub fn new(events: Vec<SerializableEvent>) -> Self {
+        Script {
+            events,
+            ..Default::default()
+        }
+    }
+
+    // Clear the events for a new recording, remembering the screen and keyboard
+    // layout it's made with
+    pub fn reset_for_recording(&mut self, keyboard_layout: KeyboardLayout) {
+        self.events.clear();
+        self.screen = ScreenGeometry::current();
+        self.keyboard_layout = keyboard_layout;
+    }
+
+    // Position of the first recorded mouse movement
+    pub fn first_mouse_position(&self) -> Option<(f64, f64)> {
+        self.events.iter().find_map(|event| match event.event_type {
+            SerializableEventType::MouseMove { x, y } => Some((x, y)),
+            _ => None,
+        })
+    }
+
+    // Drop redundant mouse movement, returning how many events were removed
+    pub fn simplify_mouse_moves(&mut self, options: &SimplifyOptions) -> usize {
+        let before = self.events.len();
+        self.events = simplify_mouse_moves(&self.events, options);
+        before - self.events.len()
+    }
+
+    // Convert recorded keys so they type the same text on another layout
+    pub fn translate_layout(&mut self, to: KeyboardLayout) -> TranslationReport {
+        let (events, report) = translate_layout(&self.events, self.keyboard_layout, to);
+        self.events = events;
+        self.keyboard_layout = to;
+        report
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::from(&SCRIPT_MAGIC[..]);
+        bytes.extend_from_slice(&SCRIPT_VERSION.to_le_bytes());
+        let encoded =
+            bincode::serialize(self).map_err(|e| format!("Could not encode script: {}", e))?;
+        bytes.extend(encoded);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Script, String> {
+        if !bytes.starts_with(SCRIPT_MAGIC) {
+            // Scripts saved before the header existed are just the events
+            return bincode::deserialize::<Vec<SerializableEvent>>(bytes)
+                .map(Script::new)
+                .map_err(|e| format!("Could not deserialize file: {}", e));
+        }
+
+        let version_bytes = bytes
+            .get(SCRIPT_MAGIC.len()..SCRIPT_MAGIC.len() + 4)
+            .ok_or("Script header is truncated")?;
+        let version = u32::from_le_bytes(version_bytes.try_into().unwrap());
+        if version > SCRIPT_VERSION {
+            return Err(format!(
+                "Script was saved by a newer version (format {}, supported {})",
+                version, SCRIPT_VERSION
+            ));
+        }
+
+        let body = &bytes[SCRIPT_MAGIC.len() + 4..];
+        match version {
+            1 => decode::<ScriptV1>(body).map(|old| Script {
+                mouse_anchor: old.mouse_anchor,
+                events: old.events,
+                ..Default::default()
+            }),
+            2 => decode::<ScriptV2>(body).map(|old| Script {
+                mouse_anchor: old.mouse_anchor,
+                screen_scaling: old.screen_scaling,
+                screen: old.screen,
+                events: old.events,
+                ..Default::default()
+            }),
+            3 => decode::<ScriptV3>(body).map(|old| Script {
+                mouse_anchor: old.mouse_anchor,
+                screen_scaling: old.screen_scaling,
+                screen: old.screen,
+                interpolation: old.interpolation,
+                events: old.events,
+                ..Default::default()
+            }),
+            4 => decode::<ScriptV4>(body).map(|old| Script {
+                mouse_anchor: old.mouse_anchor,
+                screen_scaling: old.screen_scaling,
+                screen: old.screen,
+                interpolation: old.interpolation,
+                keyboard_layout: old.keyboard_layout,
+                events: old.events,
+                ..Default::default()
+            }),
+            _ => decode(body),
+        }
+    }
+
+    // Where the data source is, relative paths are taken from the script's directory
+    pub fn data_path(&self) -> Option<PathBuf> {
+        self.data_source
+            .as_deref()
+            .map(|data| resolve_call(self.path.as_deref(), data))
+    }
+
+    // Rhai files are loaded as a script with a single Rhai step
+    pub fn load(path: &Path) -> Result<Script, String> {
+        let mut script = if path
+            .extension()
+            .is_some_and(|extension| extension == "rhai")
+        {
+            let source =
+                fs::read_to_string(path).map_err(|e| format!("Could not read file: {}", e))?;
+            Script::new(vec![SerializableEvent {
+                time: SystemTime::now(),
+                event_type: SerializableEventType::Rhai { source },
+            }])
+        } else {
+            let bytes = fs::read(path).map_err(|e| format!("Could not read file: {}", e))?;
+            Script::from_bytes(&bytes)?
+        };
+        script.path = Some(path.to_path_buf());
+        Ok(script)
+    }
+
+    // Refuses to save a script that would end up calling itself
+    pub fn save(&mut self, path: &Path) -> Result<(), String> {
+        if let Some(cycle) = find_cycle(self, path) {
+            let cycle: Vec<_> = cycle
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(format!(
+                "Calls would go round in a loop: {}",
+                cycle.join(" -> ")
+            ));
+        }
+
+        // The data source is kept relative to wherever the script is saved now
+        let data_source = self.data_source.clone();
+        self.data_source = self.data_path().map(|data| relative_path(path, &data));
+        let written = self.to_bytes().and_then(|bytes| {
+            fs::write(path, bytes).map_err(|e| format!("Could not write to file: {}", e))
+        });
+        if let Err(e) = written {
+            self.data_source = data_source;
+            return Err(e);
+        }
+        self.path = Some(path.to_path_buf());
+        Ok(())
+    }
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    bincode::deserialize(bytes).map_err(|e| format!("Could not deserialize file: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn events() -> Vec<SerializableEvent> {
+        vec![SerializableEvent {
+            time: UNIX_EPOCH,
+            event_type: SerializableEventType::MouseMove { x: 3.0, y: 4.0 },
+        }]
+    }
+
+    fn with_header(version: u32, body: Vec<u8>) -> Vec<u8> {
+        let mut bytes = Vec::from(&SCRIPT_MAGIC[..]);
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn round_trips_current_version() {
+        let mut script = Script::new(events());
+        script.mouse_anchor = MouseAnchor::Cursor;
+        script.keyboard_layout = KeyboardLayout::De;
+        script.data_source = Some(PathBuf::from("rows.csv"));
+
+        let decoded = Script::from_bytes(&script.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.mouse_anchor, MouseAnchor::Cursor);
+        assert_eq!(decoded.keyboard_layout, KeyboardLayout::De);
+        assert_eq!(decoded.data_source, Some(PathBuf::from("rows.csv")));
+        assert_eq!(decoded.events.len(), 1);
+    }
+
+    #[test]
+    fn reads_older_versions_with_defaults() {
+        // bincode lays a struct out like a tuple of its fields
+        let v1 = bincode::serialize(&(MouseAnchor::Window, events())).unwrap();
+        let script = Script::from_bytes(&with_header(1, v1)).unwrap();
+        assert_eq!(script.mouse_anchor, MouseAnchor::Window);
+        assert_eq!(script.keyboard_layout, KeyboardLayout::default());
+        assert!(script.screen.is_none());
+        assert_eq!(script.events.len(), 1);
+
+        let v4 = bincode::serialize(&(
+            MouseAnchor::Cursor,
+            ScreenScaling::default(),
+            None::<ScreenGeometry>,
+            Interpolation::default(),
+            KeyboardLayout::Uk,
+            events(),
+        ))
+        .unwrap();
+        let script = Script::from_bytes(&with_header(4, v4)).unwrap();
+        assert_eq!(script.keyboard_layout, KeyboardLayout::Uk);
+        assert!(script.data_source.is_none());
+        assert_eq!(script.events.len(), 1);
+    }
+
+    #[test]
+    fn keeps_the_data_source_relative_to_the_script() {
+        let directory = std::env::temp_dir().join(format!(
+            "simplyscriptor2-script-data-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("scripts")).unwrap();
+        fs::create_dir_all(directory.join("data")).unwrap();
+        let rows = directory.join("data").join("rows.csv");
+        fs::write(&rows, "name\nAda\n").unwrap();
+
+        let mut script = Script::new(events());
+        script.data_source = Some(rows.clone());
+        let path = directory.join("scripts").join("form.bin");
+        script.save(&path).unwrap();
+        assert_eq!(
+            script.data_source,
+            Some(Path::new("..").join("data").join("rows.csv"))
+        );
+
+        // Moving both folders together keeps the script working
+        let moved = std::env::temp_dir().join(format!(
+            "simplyscriptor2-script-data-moved-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&moved);
+        fs::rename(&directory, &moved).unwrap();
+        let loaded = Script::load(&moved.join("scripts").join("form.bin")).unwrap();
+        let data = loaded.data_path().unwrap();
+        assert_eq!(fs::read_to_string(data).unwrap(), "name\nAda\n");
+        fs::remove_dir_all(&moved).unwrap();
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let bytes = with_header(SCRIPT_VERSION + 1, Vec::new());
+        assert!(Script::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,525 @@
+use crate::calls::resolve_call;
+use crate::capture::SystemScreen;
+use crate::player::{play_file, Caller};
+use crate::random::Random;
+use crate::serializable_event::{SerializableButton, SerializableEventType, SerializableKey};
+use crate::template::wait_for_image;
+use crate::variables::Variables;
+use crate::wait::{wait_for_pixel, WaitError};
+use crate::window::{wait_for_window, SystemWindows, WindowCondition, WindowMatcher};
+use crate::{cursor_position, log, send_event};
+use rdev::EventType;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Position};
+use serde::de::{value, Deserialize, IntoDeserializer};
+use std::{
+    cell::RefCell,
+    path::Path,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+// Longest single sleep while waiting, so a stop is noticed quickly
+const SLEEP_SLICE: Duration = Duration::from_millis(20);
+// Gap between key presses in type_text when none is given
+const TYPE_DELAY: Duration = Duration::from_millis(20);
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// State the script functions share while a Rhai script runs
+struct Host {
+    caller: Caller,
+    variables: Variables,
+    screen: SystemScreen,
+    windows: SystemWindows,
+    random: Random,
+}
+
+// Run a Rhai script on the current thread, returning the variables as the script
+// left them. Stopping playback ends the script at the next statement or sleep.
+//
+// Available functions:
+//   send_event(#{ type: "key_press", key: "KeyA" })  also key_release, button_press,
+//                                                     button_release, mouse_move, wheel
+//   key_down(key), key_up(key), key_tap(key)          key names as in SerializableKey
+//   type_text(text), type_text(text, delay_ms)
+//   mouse_move(x, y), mouse_down(button), mouse_up(button), click(), click(button)
+//   scroll(delta_x, delta_y), cursor() -> [x, y]
+//   sleep(ms), random(min, max)                      both ends included, errors if min > max
+//   wait_pixel(x, y, "#RRGGBB", tolerance, timeout_ms) -> bool
+//   wait_image("template.png", confidence, timeout_ms) -> #{ x, y, confidence } or (),
+//                                                     relative to the script's file
+//   wait_window(title, timeout_ms) -> bool
+//   call("recorded.bin"), call(path, #{ name: value })  plays a saved script, relative
+//                                                     to the calling script's file
+//   get_var(name) -> string or (), set_var(name, value)
+pub fn run_script(
+    source: &str,
+    caller: Caller,
+    variables: Variables,
+) -> Result<Variables, WaitError> {
+    let run = Arc::clone(&caller.run);
+    let host = Rc::new(RefCell::new(Host {
+        caller,
+        variables,
+        screen: SystemScreen::new(),
+        windows: SystemWindows::new(),
+        random: Random::from_time(),
+    }));
+
+    let mut engine = Engine::new();
+    let progress_run = Arc::clone(&run);
+    engine.on_progress(move |_| (!progress_run.load(Ordering::Relaxed)).then_some(Dynamic::UNIT));
+    engine.on_print(log);
+    engine.on_debug(|text, _, position| log(&format!("{:?}: {}", position, text)));
+    register_functions(&mut engine, &host);
+
+    let result = engine.run(source);
+    let variables = host.borrow().variables.clone();
+    match result {
+        Ok(()) => Ok(variables),
+        Err(e) => match *e {
+            EvalAltResult::ErrorTerminated(..) => Err(WaitError::Stopped),
+            e => Err(WaitError::Failed(format!("Script error: {}", e))),
+        },
+    }
+}
+
+fn register_functions(engine: &mut Engine, host: &Rc<RefCell<Host>>) {
+    let h = Rc::clone(host);
+    engine.register_fn("sleep", move |ms: i64| -> ScriptResult<()> {
+        let run = Arc::clone(&h.borrow().caller.run);
+        sleep(&run, Duration::from_millis(ms.max(0) as u64))
+    });
+
+    engine.register_fn("send_event", |event: Map| -> ScriptResult<()> {
+        send(event_from_map(&event)?);
+        Ok(())
+    });
+    engine.register_fn("key_down", |key: &str| -> ScriptResult<()> {
+        send(SerializableEventType::KeyPress(parse_name(key)?));
+        Ok(())
+    });
+    engine.register_fn("key_up", |key: &str| -> ScriptResult<()> {
+        send(SerializableEventType::KeyRelease(parse_name(key)?));
+        Ok(())
+    });
+    engine.register_fn("key_tap", |key: &str| -> ScriptResult<()> {
+        let key: SerializableKey = parse_name(key)?;
+        send(SerializableEventType::KeyPress(key));
+        send(SerializableEventType::KeyRelease(key));
+        Ok(())
+    });
+
+    let h = Rc::clone(host);
+    engine.register_fn("type_text", move |text: &str| -> ScriptResult<()> {
+        type_text(&h, text, TYPE_DELAY)
+    });
+    let h = Rc::clone(host);
+    engine.register_fn(
+        "type_text",
+        move |text: &str, delay_ms: i64| -> ScriptResult<()> {
+            type_text(&h, text, Duration::from_millis(delay_ms.max(0) as u64))
+        },
+    );
+
+    engine.register_fn("mouse_move", |x: f64, y: f64| {
+        send(SerializableEventType::MouseMove { x, y });
+    });
+    engine.register_fn("mouse_move", |x: i64, y: i64| {
+        send(SerializableEventType::MouseMove {
+            x: x as f64,
+            y: y as f64,
+        });
+    });
+    engine.register_fn("mouse_down", |button: &str| -> ScriptResult<()> {
+        send(SerializableEventType::ButtonPress(parse_name(button)?));
+        Ok(())
+    });
+    engine.register_fn("mouse_up", |button: &str| -> ScriptResult<()> {
+        send(SerializableEventType::ButtonRelease(parse_name(button)?));
+        Ok(())
+    });
+    engine.register_fn("click", || click(SerializableButton::Left));
+    engine.register_fn("click", |button: &str| -> ScriptResult<()> {
+        click(parse_name(button)?);
+        Ok(())
+    });
+    engine.register_fn("scroll", |delta_x: i64, delta_y: i64| {
+        send(SerializableEventType::Wheel { delta_x, delta_y });
+    });
+    engine.register_fn("cursor", || -> Array {
+        let (x, y) = cursor_position().unwrap_or_default();
+        vec![Dynamic::from_float(x), Dynamic::from_float(y)]
+    });
+
+    let h = Rc::clone(host);
+    engine.register_fn("random", move |min: i64, max: i64| -> ScriptResult<i64> {
+        h.borrow_mut()
+            .random
+            .range(min, max)
+            .ok_or_else(|| error(format!("random({}, {}): min is above max", min, max)))
+    });
+
+    let h = Rc::clone(host);
+    engine.register_fn(
+        "wait_pixel",
+        move |x: i64, y: i64, color: &str, tolerance: i64, timeout_ms: i64| -> ScriptResult<bool> {
+            let color = parse_color(color)?;
+            let mut host = h.borrow_mut();
+            let run = Arc::clone(&host.caller.run);
+            let result = wait_for_pixel(
+                &mut host.screen,
+                (x as i32, y as i32),
+                color,
+                tolerance.clamp(0, 255) as u8,
+                Duration::from_millis(timeout_ms.max(0) as u64),
+                &run,
+            );
+            timed_out_as_false(result.map(|_| true))
+        },
+    );
+
+    let h = Rc::clone(host);
+    engine.register_fn(
+        "wait_image",
+        move |path: &str, confidence: f64, timeout_ms: i64| -> ScriptResult<Dynamic> {
+            let mut host = h.borrow_mut();
+            let path = resolve_call(host.caller.path.as_deref(), Path::new(path));
+            let template = image::open(&path)
+                .map_err(|e| error(format!("Could not open {}: {}", path.display(), e)))?
+                .to_rgb8();
+            let run = Arc::clone(&host.caller.run);
+            let result = wait_for_image(
+                &mut host.screen,
+                &template,
+                None,
+                confidence as f32,
+                Duration::from_millis(timeout_ms.max(0) as u64),
+                &run,
+            );
+            match result {
+                Ok(found) => {
+                    let (x, y) = found.center();
+                    let mut map = Map::new();
+                    map.insert("x".into(), Dynamic::from_float(x));
+                    map.insert("y".into(), Dynamic::from_float(y));
+                    map.insert(
+                        "confidence".into(),
+                        Dynamic::from_float(found.confidence as f64),
+                    );
+                    Ok(map.into())
+                }
+                Err(WaitError::TimedOut(_)) => Ok(Dynamic::UNIT),
+                Err(e) => Err(wait_error(e)),
+            }
+        },
+    );
+
+    let h = Rc::clone(host);
+    engine.register_fn(
+        "wait_window",
+        move |title: &str, timeout_ms: i64| -> ScriptResult<bool> {
+            let matcher = WindowMatcher {
+                title: Some(title.to_string()),
+                class: None,
+            };
+            let mut host = h.borrow_mut();
+            let run = Arc::clone(&host.caller.run);
+            let result = wait_for_window(
+                &mut host.windows,
+                &matcher,
+                WindowCondition::Exists,
+                Duration::from_millis(timeout_ms.max(0) as u64),
+                &run,
+            );
+            timed_out_as_false(result.map(|_| true))
+        },
+    );
+
+    let h = Rc::clone(host);
+    engine.register_fn("call", move |path: &str| -> ScriptResult<()> {
+        call(&h, path, Map::new())
+    });
+    let h = Rc::clone(host);
+    engine.register_fn(
+        "call",
+        move |path: &str, parameters: Map| -> ScriptResult<()> { call(&h, path, parameters) },
+    );
+
+    let h = Rc::clone(host);
+    engine.register_fn("get_var", move |name: &str| -> Dynamic {
+        h.borrow()
+            .variables
+            .get(name)
+            .map_or(Dynamic::UNIT, |value| value.clone().into())
+    });
+    let h = Rc::clone(host);
+    engine.register_fn("set_var", move |name: &str, value: Dynamic| {
+        h.borrow_mut()
+            .variables
+            .insert(name.to_string(), value.to_string());
+    });
+}
+
+// Play a saved script with the script's variables and `parameters` on top, keeping
+// the variables it leaves behind
+fn call(host: &Rc<RefCell<Host>>, path: &str, parameters: Map) -> ScriptResult<()> {
+    let (caller, variables) = {
+        let host = host.borrow();
+        (host.caller.clone(), host.variables.clone())
+    };
+    let parameters = parameters
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let variables =
+        play_file(&caller, Path::new(path), variables, parameters).map_err(wait_error)?;
+    host.borrow_mut().variables = variables;
+    Ok(())
+}
+
+fn type_text(host: &Rc<RefCell<Host>>, text: &str, delay: Duration) -> ScriptResult<()> {
+    let (run, layout) = {
+        let host = host.borrow();
+        (Arc::clone(&host.caller.run), host.caller.keyboard_layout)
+    };
+    let (strokes, missing) = layout.keystrokes(text);
+    if !missing.is_empty() {
+        return Err(error(format!(
+            "The {} layout cannot type {:?}",
+            layout, missing
+        )));
+    }
+
+    for (i, stroke) in strokes.iter().enumerate() {
+        if i > 0 {
+            sleep(&run, delay)?;
+        }
+        for event_type in stroke.event_types() {
+            send(event_type);
+        }
+    }
+    Ok(())
+}
+
+fn sleep(run: &AtomicBool, duration: Duration) -> ScriptResult<()> {
+    let mut left = duration;
+    while !left.is_zero() {
+        if !run.load(Ordering::Relaxed) {
+            return Err(wait_error(WaitError::Stopped));
+        }
+        let slice = left.min(SLEEP_SLICE);
+        thread::sleep(slice);
+        left -= slice;
+    }
+    Ok(())
+}
+
+fn send(event_type: SerializableEventType) {
+    if let Ok(event_type) = EventType::try_from(event_type) {
+        send_event(&event_type);
+    }
+}
+
+fn click(button: SerializableButton) {
+    send(SerializableEventType::ButtonPress(button));
+    thread::sleep(Duration::from_millis(20));
+    send(SerializableEventType::ButtonRelease(button));
+}
+
+fn event_from_map(event: &Map) -> ScriptResult<SerializableEventType> {
+    let field = |name: &str| {
+        event
+            .get(name)
+            .cloned()
+            .ok_or_else(|| error(format!("send_event needs \"{}\"", name)))
+    };
+    let number = |name: &str| -> ScriptResult<f64> {
+        let value = field(name)?;
+        value
+            .as_float()
+            .or_else(|_| value.as_int().map(|v| v as f64))
+            .map_err(|_| error(format!("\"{}\" must be a number", name)))
+    };
+    let name = |name: &str| -> ScriptResult<String> { Ok(field(name)?.to_string()) };
+
+    match name("type")?.as_str() {
+        "key_press" => Ok(SerializableEventType::KeyPress(parse_name(&name("key")?)?)),
+        "key_release" => Ok(SerializableEventType::KeyRelease(parse_name(&name(
+            "key",
+        )?)?)),
+        "button_press" => Ok(SerializableEventType::ButtonPress(parse_name(&name(
+            "button",
+        )?)?)),
+        "button_release" => Ok(SerializableEventType::ButtonRelease(parse_name(&name(
+            "button",
+        )?)?)),
+        "mouse_move" => Ok(SerializableEventType::MouseMove {
+            x: number("x")?,
+            y: number("y")?,
+        }),
+        "wheel" => Ok(SerializableEventType::Wheel {
+            delta_x: number("delta_x")? as i64,
+            delta_y: number("delta_y")? as i64,
+        }),
+        other => Err(error(format!("Unknown event type \"{}\"", other))),
+    }
+}
+
+// Key and button names are the variant names, like "KeyA" or "Left"
+fn parse_name<'de, T: Deserialize<'de>>(name: &'de str) -> ScriptResult<T> {
+    let deserializer: value::StrDeserializer<value::Error> = name.into_deserializer();
+    T::deserialize(deserializer).map_err(|_| error(format!("Unknown key or button \"{}\"", name)))
+}
+
+fn parse_color(color: &str) -> ScriptResult<[u8; 3]> {
+    crate::wait::parse_color(color).map_err(error)
+}
+
+fn timed_out_as_false(result: Result<bool, WaitError>) -> ScriptResult<bool> {
+    match result {
+        Ok(found) => Ok(found),
+        Err(WaitError::TimedOut(_)) => Ok(false),
+        Err(e) => Err(wait_error(e)),
+    }
+}
+
+fn wait_error(e: WaitError) -> Box<EvalAltResult> {
+    match e {
+        WaitError::Stopped => Box::new(EvalAltResult::ErrorTerminated(
+            Dynamic::UNIT,
+            Position::NONE,
+        )),
+        e => error(e.to_string()),
+    }
+}
+
+fn error(message: String) -> Box<EvalAltResult> {
+    message.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::KeyboardLayout;
+    use std::time::Instant;
+
+    fn caller(run: bool) -> Caller {
+        Caller {
+            run: Arc::new(AtomicBool::new(run)),
+            delay: Arc::new(AtomicBool::new(false)),
+            keyboard_layout: KeyboardLayout::default(),
+            speed: 1.0,
+            path: None,
+            depth: 0,
+        }
+    }
+
+    fn map(fields: &[(&str, Dynamic)]) -> Map {
+        fields
+            .iter()
+            .map(|(name, value)| ((*name).into(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn builds_events_from_maps() {
+        let key = event_from_map(&map(&[
+            ("type", "key_press".into()),
+            ("key", "KeyA".into()),
+        ]))
+        .unwrap();
+        assert_eq!(format!("{:?}", key), "KeyPress(KeyA)");
+
+        let button = event_from_map(&map(&[
+            ("type", "button_release".into()),
+            ("button", "Right".into()),
+        ]))
+        .unwrap();
+        assert_eq!(format!("{:?}", button), "ButtonRelease(Right)");
+
+        // Integers and floats are both accepted for coordinates
+        let moved = event_from_map(&map(&[
+            ("type", "mouse_move".into()),
+            ("x", Dynamic::from_int(10)),
+            ("y", Dynamic::from_float(20.5)),
+        ]))
+        .unwrap();
+        assert_eq!(format!("{:?}", moved), "MouseMove { x: 10.0, y: 20.5 }");
+
+        let wheel = event_from_map(&map(&[
+            ("type", "wheel".into()),
+            ("delta_x", Dynamic::from_int(0)),
+            ("delta_y", Dynamic::from_int(-1)),
+        ]))
+        .unwrap();
+        assert_eq!(format!("{:?}", wheel), "Wheel { delta_x: 0, delta_y: -1 }");
+    }
+
+    #[test]
+    fn refuses_incomplete_or_unknown_event_maps() {
+        let unknown =
+            |fields: &[(&str, Dynamic)]| event_from_map(&map(fields)).unwrap_err().to_string();
+
+        assert!(unknown(&[("key", "KeyA".into())]).contains("needs \"type\""));
+        assert!(unknown(&[("type", "key_press".into())]).contains("needs \"key\""));
+        assert!(unknown(&[("type", "teleport".into())]).contains("Unknown event type"));
+        assert!(
+            unknown(&[("type", "key_press".into()), ("key", "NoSuchKey".into())])
+                .contains("Unknown key or button")
+        );
+        assert!(unknown(&[
+            ("type", "mouse_move".into()),
+            ("x", "left".into()),
+            ("y", Dynamic::from_int(0)),
+        ])
+        .contains("must be a number"));
+    }
+
+    #[test]
+    fn parses_colors_with_and_without_hash() {
+        assert_eq!(parse_color("#FF8000").unwrap(), [255, 128, 0]);
+        assert_eq!(parse_color("00ff7f").unwrap(), [0, 255, 127]);
+        assert!(parse_color("#FFF").is_err());
+        assert!(parse_color("#FF80001").is_err());
+        assert!(parse_color("#GG0000").is_err());
+    }
+
+    #[test]
+    fn keeps_variables_the_script_sets() {
+        let mut variables = Variables::new();
+        variables.insert("name".to_string(), "ada".to_string());
+        let variables = run_script(
+            r#"set_var("greeting", "hello " + get_var("name"));"#,
+            caller(true),
+            variables,
+        )
+        .unwrap();
+        assert_eq!(variables["greeting"], "hello ada");
+    }
+
+    #[test]
+    fn stops_when_the_run_flag_is_cleared() {
+        let result = run_script("loop {}", caller(false), Variables::new());
+        assert_eq!(result.unwrap_err(), WaitError::Stopped);
+
+        for source in ["loop {}", "sleep(60000);"] {
+            let caller = caller(true);
+            let run = Arc::clone(&caller.run);
+            let stopper = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                run.store(false, Ordering::Relaxed);
+            });
+            let start = Instant::now();
+            let result = run_script(source, caller, Variables::new());
+            stopper.join().unwrap();
+            assert_eq!(result.unwrap_err(), WaitError::Stopped, "{}", source);
+            assert!(start.elapsed() < Duration::from_secs(5), "{}", source);
+        }
+    }
+}
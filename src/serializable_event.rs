@@ -1,6 +1,13 @@
+use crate::capture::Region;
+use crate::flow::Condition;
+use crate::variables::Variables;
+use crate::window::{TimeoutPolicy, WindowCondition, WindowMatcher};
 use rdev::{Button, Event, EventType, Key};
 use serde::{Deserialize, Serialize};
-use std::time::SystemTime;
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
 // Serializable version of rdev::Event
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,8 +22,102 @@ pub enum SerializableEventType {
     KeyRelease(SerializableKey),
     ButtonPress(SerializableButton),
     ButtonRelease(SerializableButton),
-    MouseMove { x: f64, y: f64 },
-    Wheel { delta_x: i64, delta_y: i64 },
+    MouseMove {
+        x: f64,
+        y: f64,
+    },
+    Wheel {
+        delta_x: i64,
+        delta_y: i64,
+    },
+    // Script steps. These have no rdev equivalent, new variants must go at the
+    // end so older script files still decode.
+    WaitForPixel {
+        x: i32,
+        y: i32,
+        color: [u8; 3],
+        tolerance: u8,
+        timeout: Duration,
+    },
+    // Template is a PNG kept inside the script so it works on any machine
+    WaitForImage {
+        template: Vec<u8>,
+        region: Option<Region>,
+        confidence: f32,
+        timeout: Duration,
+        action: MatchAction,
+    },
+    WaitForWindow {
+        matcher: WindowMatcher,
+        condition: WindowCondition,
+        timeout: Duration,
+        on_timeout: TimeoutPolicy,
+    },
+    // Recorded whenever focus moves to another window (or it moves), the events
+    // after it happened in this window
+    ActiveWindow {
+        title: String,
+        class: Vec<String>,
+        geometry: Region,
+    },
+    // Typed through the keyboard layout of the machine it's played on, with
+    // `{{name}}` replaced by the value of the variable
+    TypeText {
+        text: String,
+        per_char_delay: Duration,
+    },
+    // Control flow markers, see flow.rs for how they nest. A Repeat without a
+    // count goes on until playback is stopped.
+    Repeat {
+        count: Option<u32>,
+    },
+    If {
+        condition: Condition,
+    },
+    Else,
+    End,
+    Label {
+        name: String,
+    },
+    Jump {
+        label: String,
+    },
+    Exit {
+        code: i32,
+    },
+    // Rhai source run by the player, see scripting.rs for what it can call
+    Rhai {
+        source: String,
+    },
+    // Play another saved script in place, relative paths are from the calling
+    // script's directory. Parameter values may use {{ variables }} of the caller.
+    Call {
+        script: PathBuf,
+        parameters: Variables,
+    },
+}
+
+// What to do once a WaitForImage template is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchAction {
+    Wait,
+    Move,
+    Click(SerializableButton),
+}
+
+impl SerializableEventType {
+    // Whether this is a script step rather than recorded keyboard or mouse input
+    pub fn is_step(&self) -> bool {
+        !matches!(
+            self,
+            SerializableEventType::KeyPress(_)
+                | SerializableEventType::KeyRelease(_)
+                | SerializableEventType::ButtonPress(_)
+                | SerializableEventType::ButtonRelease(_)
+                | SerializableEventType::MouseMove { .. }
+                | SerializableEventType::Wheel { .. }
+        )
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -136,6 +237,17 @@ pub enum SerializableButton {
     Unknown(u8),
 }
 
+impl std::fmt::Display for SerializableButton {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SerializableButton::Left => write!(f, "Left"),
+            SerializableButton::Right => write!(f, "Right"),
+            SerializableButton::Middle => write!(f, "Middle"),
+            SerializableButton::Unknown(number) => write!(f, "Button {}", number),
+        }
+    }
+}
+
 // Convert from rdev types to serializable types
 impl From<Event> for SerializableEvent {
     fn from(event: Event) -> Self {
@@ -285,39 +397,44 @@ impl From<Button> for SerializableButton {
     }
 }
 
-// Convert from serializable types back to rdev types
-impl From<SerializableEvent> for Event {
-    fn from(event: SerializableEvent) -> Self {
+// Convert from serializable types back to rdev types, script steps have no rdev
+// equivalent and are handed back unchanged
+impl TryFrom<SerializableEvent> for Event {
+    type Error = SerializableEventType;
+
+    fn try_from(event: SerializableEvent) -> Result<Self, Self::Error> {
         #[cfg(any(target_os = "macos", target_os = "windows"))]
         {
-            Event {
+            Ok(Event {
                 time: event.time,
-                event_type: event.event_type.into(),
+                event_type: event.event_type.try_into()?,
                 unicode: None,
                 platform_code: 0,
                 position_code: 0,
                 extra_data: 0,
                 usb_hid: 0,
-            }
+            })
         }
 
         #[cfg(target_os = "linux")]
         {
-            Event {
+            Ok(Event {
                 time: event.time,
-                event_type: event.event_type.into(),
+                event_type: event.event_type.try_into()?,
                 unicode: None,
                 platform_code: 0,
                 position_code: 0,
                 usb_hid: 0,
-            }
+            })
         }
     }
 }
 
-impl From<SerializableEventType> for EventType {
-    fn from(event_type: SerializableEventType) -> Self {
-        match event_type {
+impl TryFrom<SerializableEventType> for EventType {
+    type Error = SerializableEventType;
+
+    fn try_from(event_type: SerializableEventType) -> Result<Self, Self::Error> {
+        Ok(match event_type {
             SerializableEventType::KeyPress(key) => EventType::KeyPress(key.into()),
             SerializableEventType::KeyRelease(key) => EventType::KeyRelease(key.into()),
             SerializableEventType::ButtonPress(button) => EventType::ButtonPress(button.into()),
@@ -326,7 +443,8 @@ impl From<SerializableEventType> for EventType {
             SerializableEventType::Wheel { delta_x, delta_y } => {
                 EventType::Wheel { delta_x, delta_y }
             }
-        }
+            step => return Err(step),
+        })
     }
 }
 
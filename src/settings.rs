@@ -0,0 +1,255 @@
+use crate::config::{config_dir, load_config};
+use crate::layout::KeyboardLayout;
+use crate::serializable_event::SerializableKey;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Options set in the window are kept in settings.toml in the config directory,
+// for example:
+//
+//   minimize = false
+//   natural_delay = true
+//   infinite_loop = false
+//   loop_count = 3
+//   keyboard_layout = "De"
+//   last_script = "/home/sam/macros/login.bin"
+//
+//   [keys]
+//   record = "Comma"
+//   stop = "Dot"
+//   run = "Slash"
+//
+//   [window]
+//   x = 100
+//   y = 80
+const SETTINGS_FILE: &str = "settings.toml";
+
+// Keys that start and stop recording and running while the window is open
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlKeys {
+    pub record: SerializableKey,
+    pub stop: SerializableKey,
+    pub run: SerializableKey,
+}
+
+impl ControlKeys {
+    // Whether one of the shortcuts uses this key
+    pub fn contains(&self, key: SerializableKey) -> bool {
+        [self.record, self.stop, self.run].contains(&key)
+    }
+}
+
+impl Default for ControlKeys {
+    fn default() -> Self {
+        ControlKeys {
+            record: SerializableKey::Comma,
+            stop: SerializableKey::Dot,
+            run: SerializableKey::Slash,
+        }
+    }
+}
+
+// Top left corner of the window on the desktop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub minimize: bool,
+    pub natural_delay: bool,
+    pub infinite_loop: bool,
+    pub loop_count: i32,
+    pub keys: ControlKeys,
+    // Layout of this machine's keyboard
+    pub keyboard_layout: KeyboardLayout,
+    // Opened again on the next start
+    pub last_script: Option<PathBuf>,
+    pub window: Option<WindowPosition>,
+    // The file as it was loaded, so keys this version doesn't know about are
+    // written back as they were
+    #[serde(skip)]
+    file: toml::Table,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            minimize: false,
+            natural_delay: true,
+            infinite_loop: true,
+            loop_count: 1,
+            keys: ControlKeys::default(),
+            keyboard_layout: KeyboardLayout::default(),
+            last_script: None,
+            window: None,
+            file: toml::Table::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn path() -> Result<PathBuf, String> {
+        Ok(config_dir()?.join(SETTINGS_FILE))
+    }
+
+    // No settings file yet is the same as the defaults
+    pub fn load(path: &Path) -> Result<Settings, String> {
+        let file: toml::Table = load_config(path)?;
+        let mut settings: Settings = file
+            .clone()
+            .try_into()
+            .map_err(|e| format!("Could not parse {}: {}", path.display(), e))?;
+        settings.validate()?;
+        settings.file = file;
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        self.validate()?;
+        let ours = toml::Table::try_from(self).map_err(|e| format!("Could not save: {}", e))?;
+
+        // Options that are unset now are left out rather than keeping their
+        // old value
+        let mut file = self.file.clone();
+        file.remove("last_script");
+        file.remove("window");
+        merge(&mut file, ours);
+
+        let text = toml::to_string(&file).map_err(|e| format!("Could not save: {}", e))?;
+        fs::write(path, text).map_err(|e| format!("Could not write file: {}", e))
+    }
+
+    // At least one loop, and a different plain key for each of record, stop
+    // and run
+    pub fn validate(&self) -> Result<(), String> {
+        if self.loop_count < 1 {
+            return Err(format!(
+                "loop_count is {}, it must be at least 1",
+                self.loop_count
+            ));
+        }
+
+        let keys = [
+            ("record", self.keys.record),
+            ("stop", self.keys.stop),
+            ("run", self.keys.run),
+        ];
+        for (i, (name, key)) in keys.iter().enumerate() {
+            if matches!(
+                key,
+                SerializableKey::ControlLeft
+                    | SerializableKey::ControlRight
+                    | SerializableKey::Alt
+                    | SerializableKey::AltGr
+                    | SerializableKey::ShiftLeft
+                    | SerializableKey::ShiftRight
+                    | SerializableKey::MetaLeft
+                    | SerializableKey::MetaRight
+            ) {
+                return Err(format!("The {} key can't be the modifier {:?}", name, key));
+            }
+            if let Some((other, _)) = keys[..i].iter().find(|(_, other)| other == key) {
+                return Err(format!(
+                    "{:?} is both the {} and the {} key",
+                    key, other, name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Copy `ours` over `base`, going into tables both have so what's only in
+// `base` is kept
+fn merge(base: &mut toml::Table, ours: toml::Table) {
+    for (key, value) in ours {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(ours)) => merge(base, ours),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "simplyscriptor2-settings-{}-{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn merge_keeps_keys_only_the_base_has() {
+        let mut base: toml::Table = toml::from_str(
+            "theme = \"dark\"\nloop_count = 1\n\n[keys]\nrecord = \"Comma\"\npause = \"KeyP\"\n",
+        )
+        .unwrap();
+        let ours: toml::Table =
+            toml::from_str("loop_count = 3\n\n[keys]\nrecord = \"F1\"\n").unwrap();
+        merge(&mut base, ours);
+
+        assert_eq!(base["theme"].as_str(), Some("dark"));
+        assert_eq!(base["loop_count"].as_integer(), Some(3));
+        assert_eq!(base["keys"]["record"].as_str(), Some("F1"));
+        assert_eq!(base["keys"]["pause"].as_str(), Some("KeyP"));
+    }
+
+    #[test]
+    fn saving_keeps_unknown_keys_and_drops_unset_options() {
+        let path = temp_file("unknown");
+        fs::write(
+            &path,
+            "theme = \"dark\"\nlast_script = \"old.bin\"\n\n[keys]\npause = \"KeyP\"\n\n[window]\nx = 1\ny = 2\n",
+        )
+        .unwrap();
+
+        let mut settings = Settings::load(&path).unwrap();
+        assert_eq!(settings.keys, ControlKeys::default());
+        settings.keyboard_layout = KeyboardLayout::De;
+        settings.last_script = None;
+        settings.window = None;
+        settings.save(&path).unwrap();
+
+        let file: toml::Table = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(file["theme"].as_str(), Some("dark"));
+        assert_eq!(file["keys"]["pause"].as_str(), Some("KeyP"));
+        assert!(!file.contains_key("last_script") && !file.contains_key("window"));
+
+        let reloaded = Settings::load(&path).unwrap();
+        assert_eq!(reloaded.keyboard_layout, KeyboardLayout::De);
+        assert_eq!(reloaded.keys, settings.keys);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn refuses_clashing_or_modifier_keys() {
+        let mut settings = Settings::default();
+        settings.validate().unwrap();
+
+        settings.keys.stop = SerializableKey::Comma;
+        assert!(settings.validate().is_err());
+        settings.keys.stop = SerializableKey::ShiftLeft;
+        assert!(settings.validate().is_err());
+
+        let settings = Settings {
+            loop_count: 0,
+            ..Settings::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::serializable_event::{SerializableEvent, SerializableEventType};
+use std::time::Duration;
+
+// Limits for how far a simplified mouse path may drift from the recorded one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimplifyOptions {
+    // Largest distance in pixels a dropped move may be from the kept path
+    pub tolerance: f64,
+    // Longest time allowed between two kept moves, so pauses and hovers survive
+    pub max_interval: Duration,
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> Self {
+        SimplifyOptions {
+            tolerance: 2.0,
+            max_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+// Ramer-Douglas-Peucker over every uninterrupted run of MouseMove events.
+// Any other event ends a run, so the move right before a click, key or scroll
+// is always kept and click positions and drag start/end points never shift.
+pub fn simplify_mouse_moves(
+    events: &[SerializableEvent],
+    options: &SimplifyOptions,
+) -> Vec<SerializableEvent> {
+    let mut keep = vec![true; events.len()];
+    let mut run_start = None;
+
+    for i in 0..=events.len() {
+        let is_move = events
+            .get(i)
+            .is_some_and(|e| matches!(e.event_type, SerializableEventType::MouseMove { .. }));
+
+        match (is_move, run_start) {
+            (true, None) => run_start = Some(i),
+            (false, Some(start)) => {
+                simplify_run(events, start, i - 1, options, &mut keep);
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    events
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(event, _)| event.clone())
+        .collect()
+}
+
+fn simplify_run(
+    events: &[SerializableEvent],
+    first: usize,
+    last: usize,
+    options: &SimplifyOptions,
+    keep: &mut [bool],
+) {
+    if last <= first + 1 {
+        return;
+    }
+
+    for k in keep.iter_mut().take(last).skip(first + 1) {
+        *k = false;
+    }
+
+    // Explicit stack instead of recursion, recordings can hold thousands of moves
+    let mut segments = vec![(first, last)];
+    while let Some((start, end)) = segments.pop() {
+        if end <= start + 1 {
+            continue;
+        }
+
+        let (farthest, distance) = (start + 1..end)
+            .map(|i| {
+                (
+                    i,
+                    distance_to_segment(
+                        position(&events[i]),
+                        position(&events[start]),
+                        position(&events[end]),
+                    ),
+                )
+            })
+            .fold((start + 1, -1.0), |best, current| {
+                if current.1 > best.1 {
+                    current
+                } else {
+                    best
+                }
+            });
+
+        let split = if distance > options.tolerance {
+            Some(farthest)
+        } else if interval(events, start, end) > options.max_interval {
+            Some(middle_in_time(events, start, end))
+        } else {
+            None
+        };
+
+        if let Some(split) = split {
+            keep[split] = true;
+            segments.push((start, split));
+            segments.push((split, end));
+        }
+    }
+}
+
+fn position(event: &SerializableEvent) -> (f64, f64) {
+    match event.event_type {
+        SerializableEventType::MouseMove { x, y } => (x, y),
+        _ => (0.0, 0.0),
+    }
+}
+
+fn interval(events: &[SerializableEvent], start: usize, end: usize) -> Duration {
+    events[end]
+        .time
+        .duration_since(events[start].time)
+        .unwrap_or_default()
+}
+
+// Index between start and end whose time is closest to halfway through the segment
+fn middle_in_time(events: &[SerializableEvent], start: usize, end: usize) -> usize {
+    let half = interval(events, start, end) / 2;
+    (start + 1..end)
+        .min_by_key(|&i| interval(events, start, i).abs_diff(half))
+        .unwrap_or(start + 1)
+}
+
+fn distance_to_segment(point: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    if length_squared == 0.0 {
+        return (point.0 - a.0).hypot(point.1 - a.1);
+    }
+
+    let t = (((point.0 - a.0) * dx + (point.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0);
+    (point.0 - (a.0 + t * dx)).hypot(point.1 - (a.1 + t * dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serializable_event::SerializableButton;
+    use std::time::UNIX_EPOCH;
+
+    fn at(millis: u64, event_type: SerializableEventType) -> SerializableEvent {
+        SerializableEvent {
+            time: UNIX_EPOCH + Duration::from_millis(millis),
+            event_type,
+        }
+    }
+
+    fn mouse(millis: u64, x: f64, y: f64) -> SerializableEvent {
+        at(millis, SerializableEventType::MouseMove { x, y })
+    }
+
+    fn positions(events: &[SerializableEvent]) -> Vec<(f64, f64)> {
+        events.iter().map(position).collect()
+    }
+
+    #[test]
+    fn straight_line_keeps_only_its_ends() {
+        let events: Vec<_> = (0..10)
+            .map(|i| mouse(i * 10, i as f64 * 5.0, i as f64 * 0.1))
+            .collect();
+        let simplified = simplify_mouse_moves(&events, &SimplifyOptions::default());
+        assert_eq!(positions(&simplified), vec![(0.0, 0.0), (45.0, 0.9)]);
+    }
+
+    #[test]
+    fn keeps_corners_beyond_the_tolerance() {
+        let events = vec![
+            mouse(0, 0.0, 0.0),
+            mouse(10, 10.0, 1.0),
+            mouse(20, 20.0, 0.0),
+            mouse(30, 20.0, 10.0),
+            mouse(40, 20.0, 20.0),
+        ];
+        let simplified = simplify_mouse_moves(&events, &SimplifyOptions::default());
+        assert_eq!(
+            positions(&simplified),
+            vec![(0.0, 0.0), (20.0, 0.0), (20.0, 20.0)]
+        );
+    }
+
+    #[test]
+    fn never_drops_moves_around_other_events() {
+        let events = vec![
+            mouse(0, 0.0, 0.0),
+            mouse(10, 5.0, 0.0),
+            at(
+                20,
+                SerializableEventType::ButtonPress(SerializableButton::Left),
+            ),
+            mouse(30, 10.0, 0.0),
+            mouse(40, 15.0, 0.0),
+            mouse(50, 20.0, 0.0),
+            at(
+                60,
+                SerializableEventType::ButtonRelease(SerializableButton::Left),
+            ),
+        ];
+        let simplified = simplify_mouse_moves(&events, &SimplifyOptions::default());
+        assert_eq!(simplified.len(), 6);
+        assert!(matches!(
+            simplified[1].event_type,
+            SerializableEventType::MouseMove { x, .. } if x == 5.0
+        ));
+    }
+
+    #[test]
+    fn keeps_a_move_in_long_pauses() {
+        // A slow, straight drift that would otherwise keep only its ends
+        let events: Vec<_> = (0..13).map(|i| mouse(i * 50, i as f64, 0.0)).collect();
+        let options = SimplifyOptions::default();
+        let simplified = simplify_mouse_moves(&events, &options);
+        assert!(simplified.len() > 2 && simplified.len() < events.len());
+        assert!(simplified.windows(2).all(|pair| pair[1]
+            .time
+            .duration_since(pair[0].time)
+            .unwrap()
+            <= options.max_interval));
+    }
+}
@@ -0,0 +1,423 @@
+use crate::capture::{Region, ScreenCapture};
+use crate::wait::{poll, WaitError};
+use image::{imageops, GrayImage, RgbImage};
+use std::{cell::Cell, sync::atomic::AtomicBool, time::Duration};
+
+// Templates are searched on a shrunken copy of the screen first, this is roughly
+// how small the shrunken template gets before the search stops shrinking
+const COARSE_TEMPLATE_SIZE: u32 = 12;
+// How many of the best coarse positions are checked again at full size
+const COARSE_CANDIDATES: usize = 5;
+
+// Where a template was found on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    // Normalized cross-correlation, 1.0 is a perfect match
+    pub confidence: f32,
+}
+
+impl Match {
+    pub fn center(&self) -> (f64, f64) {
+        (
+            self.x as f64 + self.width as f64 / 2.0,
+            self.y as f64 + self.height as f64 / 2.0,
+        )
+    }
+}
+
+// Best position of `template` inside `haystack`, in haystack coordinates
+pub fn find_template(haystack: &RgbImage, template: &RgbImage) -> Option<Match> {
+    let haystack = imageops::grayscale(haystack);
+    let template = imageops::grayscale(template);
+    let (template_width, template_height) = template.dimensions();
+    if template_width == 0
+        || template_height == 0
+        || template_width > haystack.width()
+        || template_height > haystack.height()
+    {
+        return None;
+    }
+
+    let prepared = PreparedTemplate::new(&template);
+    let sums = IntegralImage::new(&haystack);
+
+    let factor = (template_width.min(template_height) / COARSE_TEMPLATE_SIZE).max(1);
+    let candidates = if factor > 1 {
+        let small_haystack = shrink(&haystack, factor);
+        let small_template = shrink(&template, factor);
+
+        let mut scores = all_scores(
+            &small_haystack,
+            &PreparedTemplate::new(&small_template),
+            &IntegralImage::new(&small_haystack),
+        );
+        scores.sort_by(|a, b| b.2.total_cmp(&a.2));
+        scores.truncate(COARSE_CANDIDATES);
+
+        // Refine every coarse hit in a small window at full resolution
+        scores
+            .into_iter()
+            .filter_map(|(x, y, _)| {
+                let window = factor as i64;
+                best_in_window(
+                    &haystack,
+                    &prepared,
+                    &sums,
+                    (x as i64 * window - window, y as i64 * window - window),
+                    (x as i64 * window + window, y as i64 * window + window),
+                )
+            })
+            .collect::<Vec<_>>()
+    } else {
+        all_scores(&haystack, &prepared, &sums)
+    };
+
+    candidates
+        .into_iter()
+        .max_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(x, y, confidence)| Match {
+            x: x as i32,
+            y: y as i32,
+            width: template_width,
+            height: template_height,
+            confidence,
+        })
+}
+
+// Block until `template` shows up in `region` (or anywhere on screen) with at
+// least `confidence`, returning the match in screen coordinates
+pub fn wait_for_image(
+    screen: &mut dyn ScreenCapture,
+    template: &RgbImage,
+    region: Option<Region>,
+    confidence: f32,
+    timeout: Duration,
+    run: &AtomicBool,
+) -> Result<Match, WaitError> {
+    let region = match region {
+        Some(region) => region,
+        None => screen.screen_region().map_err(WaitError::Failed)?,
+    };
+
+    let best = Cell::new(None::<f32>);
+    poll(
+        timeout,
+        run,
+        || {
+            let haystack = screen.capture(region)?;
+            let found = find_template(&haystack, template);
+            if let Some(found) = found {
+                best.set(Some(
+                    best.get()
+                        .map_or(found.confidence, |b| b.max(found.confidence)),
+                ));
+            }
+
+            Ok(found
+                .filter(|found| found.confidence >= confidence)
+                .map(|found| Match {
+                    x: found.x + region.x,
+                    y: found.y + region.y,
+                    ..found
+                }))
+        },
+        || {
+            format!(
+                "image never appeared in {}x{} at ({}, {}) with confidence {:.2} within {:.1}s, best was {}",
+                region.width,
+                region.height,
+                region.x,
+                region.y,
+                confidence,
+                timeout.as_secs_f64(),
+                best.get().map_or("no match".to_string(), |b| format!("{:.2}", b))
+            )
+        },
+    )
+}
+
+fn shrink(image: &GrayImage, factor: u32) -> GrayImage {
+    imageops::resize(
+        image,
+        (image.width() / factor).max(1),
+        (image.height() / factor).max(1),
+        imageops::FilterType::Triangle,
+    )
+}
+
+fn all_scores(
+    haystack: &GrayImage,
+    template: &PreparedTemplate,
+    sums: &IntegralImage,
+) -> Vec<(u32, u32, f32)> {
+    let last_x = haystack.width().saturating_sub(template.width);
+    let last_y = haystack.height().saturating_sub(template.height);
+
+    (0..=last_y)
+        .flat_map(|y| (0..=last_x).map(move |x| (x, y)))
+        .map(|(x, y)| (x, y, template.score(haystack, sums, x, y)))
+        .collect()
+}
+
+fn best_in_window(
+    haystack: &GrayImage,
+    template: &PreparedTemplate,
+    sums: &IntegralImage,
+    from: (i64, i64),
+    to: (i64, i64),
+) -> Option<(u32, u32, f32)> {
+    let last_x = (haystack.width() - template.width) as i64;
+    let last_y = (haystack.height() - template.height) as i64;
+
+    (from.1.max(0)..=to.1.min(last_y))
+        .flat_map(|y| (from.0.max(0)..=to.0.min(last_x)).map(move |x| (x as u32, y as u32)))
+        .map(|(x, y)| (x, y, template.score(haystack, sums, x, y)))
+        .max_by(|a, b| a.2.total_cmp(&b.2))
+}
+
+// Template with its mean removed, so each position only needs one pass over it
+struct PreparedTemplate {
+    width: u32,
+    height: u32,
+    centered: Vec<f64>,
+    mean: f64,
+    norm: f64,
+}
+
+impl PreparedTemplate {
+    fn new(template: &GrayImage) -> Self {
+        let count = template.pixels().len() as f64;
+        let mean = template.pixels().map(|p| p.0[0] as f64).sum::<f64>() / count;
+        let centered: Vec<f64> = template.pixels().map(|p| p.0[0] as f64 - mean).collect();
+        let norm = centered.iter().map(|v| v * v).sum::<f64>().sqrt();
+
+        PreparedTemplate {
+            width: template.width(),
+            height: template.height(),
+            centered,
+            mean,
+            norm,
+        }
+    }
+
+    // Normalized cross-correlation of the template placed at (x, y)
+    fn score(&self, haystack: &GrayImage, sums: &IntegralImage, x: u32, y: u32) -> f32 {
+        let count = (self.width * self.height) as f64;
+        let (sum, sum_squared) = sums.window(x, y, self.width, self.height);
+        let window_mean = sum / count;
+        let window_norm = (sum_squared - sum * sum / count).max(0.0).sqrt();
+
+        // Flat areas have no shape to correlate, compare brightness instead
+        if self.norm < 1e-6 || window_norm < 1e-6 {
+            return if self.norm < 1e-6 && window_norm < 1e-6 {
+                (1.0 - (window_mean - self.mean).abs() / 255.0) as f32
+            } else {
+                0.0
+            };
+        }
+
+        let mut cross = 0.0;
+        for row in 0..self.height {
+            let template_row = &self.centered[(row * self.width) as usize..][..self.width as usize];
+            for (column, t) in template_row.iter().enumerate() {
+                cross += haystack.get_pixel(x + column as u32, y + row).0[0] as f64 * t;
+            }
+        }
+
+        (cross / (self.norm * window_norm)) as f32
+    }
+}
+
+// Running sums of pixel values and their squares, for constant time window sums
+struct IntegralImage {
+    width: usize,
+    sums: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+impl IntegralImage {
+    fn new(image: &GrayImage) -> Self {
+        let width = image.width() as usize + 1;
+        let height = image.height() as usize + 1;
+        let mut sums = vec![0.0; width * height];
+        let mut squares = vec![0.0; width * height];
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let (x, y) = (x as usize + 1, y as usize + 1);
+            let value = pixel.0[0] as f64;
+            sums[y * width + x] = value + sums[(y - 1) * width + x] + sums[y * width + x - 1]
+                - sums[(y - 1) * width + x - 1];
+            squares[y * width + x] =
+                value * value + squares[(y - 1) * width + x] + squares[y * width + x - 1]
+                    - squares[(y - 1) * width + x - 1];
+        }
+
+        IntegralImage {
+            width,
+            sums,
+            squares,
+        }
+    }
+
+    fn window(&self, x: u32, y: u32, width: u32, height: u32) -> (f64, f64) {
+        let (left, top) = (x as usize, y as usize);
+        let (right, bottom) = (left + width as usize, top + height as usize);
+        let total = |values: &[f64]| {
+            values[bottom * self.width + right]
+                - values[top * self.width + right]
+                - values[bottom * self.width + left]
+                + values[top * self.width + left]
+        };
+        (total(&self.sums), total(&self.squares))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::ImageFileScreen;
+    use image::Rgb;
+    use std::path::PathBuf;
+
+    // Busy pattern that doesn't repeat nearby, so a template only fits in one place
+    fn pattern(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let value = (x * 37 + y * 91 + x * y * 13 + (x ^ y) * 7) % 256;
+            Rgb([value as u8, (value * 3 % 256) as u8, (255 - value) as u8])
+        })
+    }
+
+    fn cut(image: &RgbImage, x: u32, y: u32, width: u32, height: u32) -> RgbImage {
+        imageops::crop_imm(image, x, y, width, height).to_image()
+    }
+
+    fn screen_file(name: &str, image: &RgbImage) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "simplyscriptor2-template-{}-{}.png",
+            name,
+            std::process::id()
+        ));
+        image.save(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn finds_exact_match() {
+        let haystack = pattern(64, 48);
+        let found = find_template(&haystack, &cut(&haystack, 20, 10, 8, 8)).unwrap();
+        assert_eq!((found.x, found.y), (20, 10));
+        assert!(found.confidence > 0.999);
+        assert_eq!(found.center(), (24.0, 14.0));
+    }
+
+    #[test]
+    fn finds_exact_match_through_coarse_search() {
+        let haystack = pattern(160, 120);
+        let found = find_template(&haystack, &cut(&haystack, 71, 43, 40, 30)).unwrap();
+        assert_eq!((found.x, found.y), (71, 43));
+        assert!(found.confidence > 0.999);
+    }
+
+    #[test]
+    fn rejects_templates_larger_than_the_haystack() {
+        let haystack = pattern(10, 10);
+        assert!(find_template(&haystack, &pattern(11, 4)).is_none());
+        assert!(find_template(&haystack, &RgbImage::new(0, 0)).is_none());
+    }
+
+    #[test]
+    fn integral_image_matches_direct_sums() {
+        let image = imageops::grayscale(&pattern(9, 7));
+        let sums = IntegralImage::new(&image);
+        let (mut sum, mut squares) = (0.0, 0.0);
+        for y in 2..6 {
+            for x in 3..8 {
+                let value = image.get_pixel(x, y).0[0] as f64;
+                sum += value;
+                squares += value * value;
+            }
+        }
+        assert_eq!(sums.window(3, 2, 5, 4), (sum, squares));
+    }
+
+    #[test]
+    fn waits_for_confidence_threshold() {
+        let haystack = pattern(64, 48);
+        let mut template = cut(&haystack, 30, 20, 10, 10);
+        // Spoil part of the template so it no longer matches perfectly
+        for x in 0..10 {
+            template.put_pixel(x, 0, Rgb([0, 0, 0]));
+        }
+        let path = screen_file("confidence", &haystack);
+        let mut screen = ImageFileScreen::new(&path);
+        let run = AtomicBool::new(true);
+
+        let found =
+            wait_for_image(&mut screen, &template, None, 0.7, Duration::ZERO, &run).unwrap();
+        assert_eq!((found.x, found.y), (30, 20));
+        assert!(found.confidence < 0.999);
+
+        let strict = wait_for_image(&mut screen, &template, None, 0.999, Duration::ZERO, &run);
+        match strict {
+            Err(WaitError::TimedOut(message)) => assert!(message.contains("best was 0.")),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn searches_only_the_region() {
+        let haystack = pattern(64, 48);
+        let template = cut(&haystack, 40, 30, 8, 8);
+        let path = screen_file("region", &haystack);
+        let mut screen = ImageFileScreen::new(&path);
+        let run = AtomicBool::new(true);
+        let region = |x, y, width, height| {
+            Some(Region {
+                x,
+                y,
+                width,
+                height,
+            })
+        };
+
+        // Matches are given in screen coordinates
+        let found = wait_for_image(
+            &mut screen,
+            &template,
+            region(32, 24, 20, 20),
+            0.99,
+            Duration::ZERO,
+            &run,
+        )
+        .unwrap();
+        assert_eq!((found.x, found.y), (40, 30));
+
+        // Cutting through the template hides it
+        let clipped = wait_for_image(
+            &mut screen,
+            &template,
+            region(0, 0, 44, 48),
+            0.99,
+            Duration::ZERO,
+            &run,
+        );
+        assert!(matches!(clipped, Err(WaitError::TimedOut(_))));
+
+        // A region reaching past the screen can't be captured
+        let outside = wait_for_image(
+            &mut screen,
+            &template,
+            region(50, 40, 20, 20),
+            0.99,
+            Duration::ZERO,
+            &run,
+        );
+        assert!(matches!(outside, Err(WaitError::Failed(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
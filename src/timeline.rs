@@ -0,0 +1,447 @@
+use crate::serializable_event::{
+    SerializableButton, SerializableEvent, SerializableEventType, SerializableKey,
+};
+use std::time::Duration;
+
+// Rows of the timeline, each kind of input gets its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Keyboard,
+    Buttons,
+    Wheel,
+    Movement,
+}
+
+impl Lane {
+    pub const ALL: [Lane; 4] = [Lane::Keyboard, Lane::Buttons, Lane::Wheel, Lane::Movement];
+}
+
+impl std::fmt::Display for Lane {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Lane::Keyboard => "Keys",
+            Lane::Buttons => "Buttons",
+            Lane::Wheel => "Wheel",
+            Lane::Movement => "Moves",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A key or button held from `start` to `end`, or a scroll or typed text that
+// happens at `start` and has the same `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub lane: Lane,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+// What the timeline draws, with times from the first event
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timeline {
+    pub length: Duration,
+    pub spans: Vec<Span>,
+    // When each mouse move happens, drawn as how many there are over time
+    pub moves: Vec<Duration>,
+}
+
+impl Timeline {
+    pub fn new(events: &[SerializableEvent]) -> Self {
+        let Some(first) = events.first().map(|event| event.time) else {
+            return Timeline::default();
+        };
+        let offset =
+            |event: &SerializableEvent| event.time.duration_since(first).unwrap_or_default();
+        let length = events.iter().map(offset).max().unwrap_or_default();
+
+        let mut timeline = Timeline {
+            length,
+            ..Default::default()
+        };
+        let mut keys: Vec<(SerializableKey, Duration)> = Vec::new();
+        let mut buttons: Vec<(SerializableButton, Duration)> = Vec::new();
+        for event in events {
+            let time = offset(event);
+            let instant = |lane| Span {
+                lane,
+                start: time,
+                end: time,
+            };
+            match &event.event_type {
+                SerializableEventType::KeyPress(key)
+                    if !keys.iter().any(|(held, _)| held == key) =>
+                {
+                    keys.push((*key, time));
+                }
+                SerializableEventType::KeyRelease(key) => {
+                    if let Some(i) = keys.iter().position(|(held, _)| held == key) {
+                        let (_, start) = keys.remove(i);
+                        timeline.spans.push(Span {
+                            lane: Lane::Keyboard,
+                            start,
+                            end: time,
+                        });
+                    }
+                }
+                SerializableEventType::ButtonPress(button)
+                    if !buttons.iter().any(|(held, _)| held == button) =>
+                {
+                    buttons.push((*button, time));
+                }
+                SerializableEventType::ButtonRelease(button) => {
+                    if let Some(i) = buttons.iter().position(|(held, _)| held == button) {
+                        let (_, start) = buttons.remove(i);
+                        timeline.spans.push(Span {
+                            lane: Lane::Buttons,
+                            start,
+                            end: time,
+                        });
+                    }
+                }
+                SerializableEventType::TypeText { .. } => {
+                    timeline.spans.push(instant(Lane::Keyboard))
+                }
+                SerializableEventType::Wheel { .. } => timeline.spans.push(instant(Lane::Wheel)),
+                SerializableEventType::MouseMove { .. } => timeline.moves.push(time),
+                _ => {}
+            }
+        }
+
+        // Anything still held when the script ends is held until the end
+        for (_, start) in keys {
+            timeline.spans.push(Span {
+                lane: Lane::Keyboard,
+                start,
+                end: length,
+            });
+        }
+        for (_, start) in buttons {
+            timeline.spans.push(Span {
+                lane: Lane::Buttons,
+                start,
+                end: length,
+            });
+        }
+        timeline
+    }
+
+    // Mouse moves in each of `buckets` equal slices of `from` to `to`
+    pub fn density(&self, from: Duration, to: Duration, buckets: usize) -> Vec<usize> {
+        let mut counts = vec![0; buckets];
+        if buckets == 0 || to <= from {
+            return counts;
+        }
+        let width = (to - from).as_secs_f64() / buckets as f64;
+        for time in &self.moves {
+            if (from..to).contains(time) {
+                let bucket = ((*time - from).as_secs_f64() / width) as usize;
+                counts[bucket.min(buckets - 1)] += 1;
+            }
+        }
+        counts
+    }
+}
+
+// Loop, condition and jump markers, left alone by range edits so the script's
+// blocks still match up
+fn is_marker(event_type: &SerializableEventType) -> bool {
+    matches!(
+        event_type,
+        SerializableEventType::Repeat { .. }
+            | SerializableEventType::If { .. }
+            | SerializableEventType::Else
+            | SerializableEventType::End
+            | SerializableEventType::Label { .. }
+            | SerializableEventType::Jump { .. }
+            | SerializableEventType::Exit { .. }
+    )
+}
+
+// A key or button, which range edits keep pressed and released in pairs
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Held {
+    Key(SerializableKey),
+    Button(SerializableButton),
+}
+
+impl Held {
+    // What an event presses (true) or releases (false)
+    fn of(event_type: &SerializableEventType) -> Option<(Held, bool)> {
+        match event_type {
+            SerializableEventType::KeyPress(key) => Some((Held::Key(*key), true)),
+            SerializableEventType::KeyRelease(key) => Some((Held::Key(*key), false)),
+            SerializableEventType::ButtonPress(button) => Some((Held::Button(*button), true)),
+            SerializableEventType::ButtonRelease(button) => Some((Held::Button(*button), false)),
+            _ => None,
+        }
+    }
+
+    fn release(self) -> SerializableEventType {
+        match self {
+            Held::Key(key) => SerializableEventType::KeyRelease(key),
+            Held::Button(button) => SerializableEventType::ButtonRelease(button),
+        }
+    }
+}
+
+// Take `held` out of `list`, saying whether it was there
+fn take(list: &mut Vec<Held>, held: Held) -> bool {
+    match list.iter().position(|other| *other == held) {
+        Some(i) => {
+            list.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+fn offset(events: &[SerializableEvent], event: &SerializableEvent) -> Duration {
+    events
+        .first()
+        .and_then(|first| event.time.duration_since(first.time).ok())
+        .unwrap_or_default()
+}
+
+// Remove the events from `from` to `to` and close the gap, markers in the range
+// move to where it started. Keys and buttons stay paired: one pressed before the
+// range keeps its release, at the start of the range, and one pressed inside it
+// loses its release after it.
+pub fn delete_range(events: &mut Vec<SerializableEvent>, from: Duration, to: Duration) {
+    let Some(first) = events.first().map(|event| event.time) else {
+        return;
+    };
+    let gap = to.saturating_sub(from);
+    // Pressed by kept events, and pressed only by deleted ones
+    let mut kept: Vec<Held> = Vec::new();
+    let mut deleted: Vec<Held> = Vec::new();
+    events.retain(|event| {
+        let time = event.time.duration_since(first).unwrap_or_default();
+        let in_range = (from..=to).contains(&time) && !is_marker(&event.event_type);
+        match Held::of(&event.event_type) {
+            Some((held, true)) if in_range => {
+                if !kept.contains(&held) && !deleted.contains(&held) {
+                    deleted.push(held);
+                }
+                false
+            }
+            Some((held, true)) => {
+                take(&mut deleted, held);
+                if !kept.contains(&held) {
+                    kept.push(held);
+                }
+                true
+            }
+            Some((held, false)) => take(&mut kept, held) || !(take(&mut deleted, held) || in_range),
+            None => !in_range,
+        }
+    });
+    for event in events.iter_mut() {
+        let time = event.time.duration_since(first).unwrap_or_default();
+        if time > to {
+            event.time -= gap;
+        } else if time >= from {
+            event.time = first + from;
+        }
+    }
+}
+
+// Play the events from `from` to `to` `factor` times slower, later events move
+// along to make room
+pub fn stretch_range(events: &mut [SerializableEvent], from: Duration, to: Duration, factor: f64) {
+    let Some(first) = events.first().map(|event| event.time) else {
+        return;
+    };
+    let stretched = (to.saturating_sub(from)).mul_f64(factor);
+    for event in events.iter_mut() {
+        let time = event.time.duration_since(first).unwrap_or_default();
+        let new_time = if time > to {
+            time - to + from + stretched
+        } else if time >= from {
+            from + (time - from).mul_f64(factor)
+        } else {
+            time
+        };
+        event.time = first + new_time;
+    }
+}
+
+// Copy of the events from `from` to `to` that plays on its own. Releases of
+// keys and buttons pressed before the range are left out, and anything still
+// held at the end of it is released there.
+pub fn range_events(
+    events: &[SerializableEvent],
+    from: Duration,
+    to: Duration,
+) -> Vec<SerializableEvent> {
+    let mut held: Vec<Held> = Vec::new();
+    let mut part: Vec<SerializableEvent> = events
+        .iter()
+        .filter(|event| {
+            (from..=to).contains(&offset(events, event)) && !is_marker(&event.event_type)
+        })
+        .filter(|event| match Held::of(&event.event_type) {
+            Some((pressed, true)) => {
+                if !held.contains(&pressed) {
+                    held.push(pressed);
+                }
+                true
+            }
+            Some((released, false)) => take(&mut held, released),
+            None => true,
+        })
+        .cloned()
+        .collect();
+
+    if let Some(time) = part.last().map(|event| event.time) {
+        part.extend(held.into_iter().map(|held| SerializableEvent {
+            time,
+            event_type: held.release(),
+        }));
+    }
+    part
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    // Events at the given milliseconds from the start
+    fn events(timed: Vec<(u64, SerializableEventType)>) -> Vec<SerializableEvent> {
+        timed
+            .into_iter()
+            .map(|(millis, event_type)| SerializableEvent {
+                time: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+                event_type,
+            })
+            .collect()
+    }
+
+    fn timed(events: &[SerializableEvent]) -> Vec<(u128, String)> {
+        events
+            .iter()
+            .map(|event| {
+                (
+                    event
+                        .time
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis(),
+                    format!("{:?}", event.event_type),
+                )
+            })
+            .collect()
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn press(key: SerializableKey) -> SerializableEventType {
+        SerializableEventType::KeyPress(key)
+    }
+
+    fn release(key: SerializableKey) -> SerializableEventType {
+        SerializableEventType::KeyRelease(key)
+    }
+
+    fn moved(x: f64) -> SerializableEventType {
+        SerializableEventType::MouseMove { x, y: 0.0 }
+    }
+
+    #[test]
+    fn deleting_closes_the_gap_and_keeps_markers() {
+        let mut script = events(vec![
+            (0, moved(1.0)),
+            (100, moved(2.0)),
+            (150, SerializableEventType::Repeat { count: Some(2) }),
+            (200, moved(3.0)),
+            (300, moved(4.0)),
+        ]);
+        delete_range(&mut script, ms(50), ms(250));
+        assert_eq!(
+            timed(&script),
+            [
+                (0, format!("{:?}", moved(1.0))),
+                (50, "Repeat { count: Some(2) }".to_string()),
+                (100, format!("{:?}", moved(4.0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn deleting_keeps_presses_and_releases_paired() {
+        use SerializableKey::{KeyA, KeyB, ShiftLeft};
+        let mut script = events(vec![
+            (0, press(ShiftLeft)),
+            (100, press(KeyA)),
+            (150, press(ShiftLeft)),
+            (200, release(ShiftLeft)),
+            (350, press(KeyB)),
+            (400, release(KeyA)),
+            (500, release(KeyB)),
+        ]);
+        delete_range(&mut script, ms(50), ms(300));
+        // Shift was held from before, so its release moves to the start of the
+        // range, while A was pressed inside it and loses its release after it.
+        // B is pressed and released after the range, so it stays.
+        assert_eq!(
+            timed(&script),
+            [
+                (0, "KeyPress(ShiftLeft)".to_string()),
+                (50, "KeyRelease(ShiftLeft)".to_string()),
+                (100, "KeyPress(KeyB)".to_string()),
+                (250, "KeyRelease(KeyB)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn stretching_moves_later_events_along() {
+        let mut script = events(vec![
+            (0, moved(1.0)),
+            (100, moved(2.0)),
+            (150, moved(3.0)),
+            (200, moved(4.0)),
+            (300, moved(5.0)),
+        ]);
+        stretch_range(&mut script, ms(100), ms(200), 2.0);
+        let times: Vec<u128> = timed(&script).into_iter().map(|(time, _)| time).collect();
+        assert_eq!(times, [0, 100, 200, 300, 400]);
+
+        stretch_range(&mut script, ms(100), ms(300), 0.5);
+        let times: Vec<u128> = timed(&script).into_iter().map(|(time, _)| time).collect();
+        assert_eq!(times, [0, 100, 150, 200, 300]);
+    }
+
+    #[test]
+    fn range_copies_play_on_their_own() {
+        use SerializableKey::{KeyA, KeyB};
+        let script = events(vec![
+            (0, press(KeyA)),
+            (100, SerializableEventType::Else),
+            (150, press(KeyB)),
+            (200, release(KeyA)),
+            (
+                250,
+                SerializableEventType::ButtonPress(SerializableButton::Left),
+            ),
+            (300, moved(1.0)),
+            (400, release(KeyB)),
+        ]);
+        let part = range_events(&script, ms(100), ms(300));
+        assert_eq!(
+            timed(&part),
+            [
+                (150, "KeyPress(KeyB)".to_string()),
+                (250, "ButtonPress(Left)".to_string()),
+                (300, format!("{:?}", moved(1.0))),
+                (300, "KeyRelease(KeyB)".to_string()),
+                (300, "ButtonRelease(Left)".to_string()),
+            ]
+        );
+
+        assert!(range_events(&script, ms(500), ms(600)).is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{collections::BTreeMap, path::Path};
+
+// Values `{{name}}` placeholders in type-text steps are replaced with
+pub type Variables = BTreeMap<String, String>;
+
+// Replace every `{{name}}` in `text`, failing on names that have no value
+pub fn substitute(text: &str, variables: &Variables) -> Result<String, String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("Unclosed variable in \"{}\"", text))?;
+
+        let name = after[..end].trim();
+        let value = variables
+            .get(name)
+            .ok_or_else(|| format!("Variable \"{}\" has no value", name))?;
+        output.push_str(value);
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+// Replace the `{{name}}` placeholders that have a value, leaving the rest as they
+// are to be filled in later
+pub fn substitute_known(text: &str, variables: &Variables) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+
+        match variables.get(after[..end].trim()) {
+            Some(value) => output.push_str(value),
+            None => output.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// Rows of a CSV file, the header row naming the variable of each column
+#[derive(Debug, Clone, Default)]
+pub struct DataTable {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl DataTable {
+    pub fn load(path: &Path) -> Result<DataTable, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_path(path)
+            .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+
+        let headers = reader
+            .headers()
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?
+            .iter()
+            .map(|header| header.trim().to_string())
+            .collect();
+        let rows = reader
+            .records()
+            .map(|record| {
+                record
+                    .map(|record| record.iter().map(String::from).collect())
+                    .map_err(|e| format!("Could not read {}: {}", path.display(), e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if rows.is_empty() {
+            return Err(format!("{} has no rows below the header", path.display()));
+        }
+
+        Ok(DataTable { headers, rows })
+    }
+
+    // Variables for one row, columns missing from a short row are left unset
+    pub fn row_variables(&self, index: usize) -> Variables {
+        self.headers
+            .iter()
+            .cloned()
+            .zip(self.rows[index].iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn variables(pairs: &[(&str, &str)]) -> Variables {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    // A CSV file in the temp directory, removed by the test
+    fn csv(name: &str, text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "simplyscriptor2-variables-{}-{}.csv",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn substitutes_every_placeholder() {
+        let values = variables(&[("name", "Ada"), ("city", "London")]);
+        assert_eq!(
+            substitute("{{name}} lives in {{ city }}, {{name}}!", &values),
+            Ok("Ada lives in London, Ada!".to_string())
+        );
+        assert_eq!(
+            substitute("no placeholders", &values).unwrap(),
+            "no placeholders"
+        );
+        assert!(substitute("{{missing}}", &values)
+            .unwrap_err()
+            .contains("missing"));
+        assert!(substitute("{{name", &values).is_err());
+    }
+
+    #[test]
+    fn substitutes_known_placeholders_only() {
+        let values = variables(&[("user", "ada")]);
+        assert_eq!(
+            substitute_known("{{user}}:{{password}}", &values),
+            "ada:{{password}}"
+        );
+        assert_eq!(substitute_known("{{user}} {{open", &values), "ada {{open");
+    }
+
+    #[test]
+    fn loads_rows_with_header_names() {
+        let path = csv("rows", "name, city\nAda,London\nGrace\n");
+        let table = DataTable::load(&path).unwrap();
+        assert_eq!(table.headers, ["name", "city"]);
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(
+            table.row_variables(0),
+            variables(&[("name", "Ada"), ("city", "London")])
+        );
+        // Columns missing from a short row stay unset
+        assert_eq!(table.row_variables(1), variables(&[("name", "Grace")]));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn refuses_files_without_rows() {
+        let path = csv("empty", "name,city\n");
+        assert!(DataTable::load(&path).unwrap_err().contains("no rows"));
+        fs::remove_file(&path).unwrap();
+        assert!(DataTable::load(&path).is_err());
+    }
+}
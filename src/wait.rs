@@ -0,0 +1,212 @@
+use crate::capture::ScreenCapture;
+use std::{
+    cell::Cell,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Why a wait step gave up
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaitError {
+    // Playback was stopped while waiting
+    Stopped,
+    // The condition never held, with a description of what was expected
+    TimedOut(String),
+    // Checking the condition failed outright
+    Failed(String),
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::Stopped => write!(f, "Stopped while waiting"),
+            WaitError::TimedOut(message) => write!(f, "Timed out: {}", message),
+            WaitError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// Keep calling `check` until it returns a value, the timeout passes or `run` is cleared.
+// `describe` explains what was being waited for when the timeout is hit.
+pub fn poll<T>(
+    timeout: Duration,
+    run: &AtomicBool,
+    mut check: impl FnMut() -> Result<Option<T>, String>,
+    describe: impl FnOnce() -> String,
+) -> Result<T, WaitError> {
+    let start = Instant::now();
+    loop {
+        if !run.load(Ordering::Relaxed) {
+            return Err(WaitError::Stopped);
+        }
+
+        if let Some(value) = check().map_err(WaitError::Failed)? {
+            return Ok(value);
+        }
+
+        if start.elapsed() >= timeout {
+            return Err(WaitError::TimedOut(describe()));
+        }
+
+        thread::sleep(POLL_INTERVAL.min(timeout.saturating_sub(start.elapsed())));
+    }
+}
+
+// Whether every channel of two colors is within `tolerance` of each other
+pub fn color_matches(actual: [u8; 3], expected: [u8; 3], tolerance: u8) -> bool {
+    actual
+        .iter()
+        .zip(expected)
+        .all(|(a, e)| a.abs_diff(e) <= tolerance)
+}
+
+pub fn hex_color(color: [u8; 3]) -> String {
+    format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+}
+
+// "#RRGGBB", the # can be left out
+pub fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or_else(|| format!("\"{}\" is not a #RRGGBB color", color))?;
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+// Block until the pixel at (x, y) is within `tolerance` of `color`
+pub fn wait_for_pixel(
+    screen: &mut dyn ScreenCapture,
+    (x, y): (i32, i32),
+    color: [u8; 3],
+    tolerance: u8,
+    timeout: Duration,
+    run: &AtomicBool,
+) -> Result<(), WaitError> {
+    let last_seen = Cell::new(None);
+    poll(
+        timeout,
+        run,
+        || {
+            let actual = screen.pixel(x, y)?;
+            last_seen.set(Some(actual));
+            Ok(color_matches(actual, color, tolerance).then_some(()))
+        },
+        || {
+            format!(
+                "pixel ({}, {}) never became {} (±{}) within {:.1}s, last saw {}",
+                x,
+                y,
+                hex_color(color),
+                tolerance,
+                timeout.as_secs_f64(),
+                last_seen.get().map_or("nothing".to_string(), hex_color)
+            )
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::ImageFileScreen;
+    use image::{Rgb, RgbImage};
+    use std::path::PathBuf;
+
+    // A 4x4 screen that's dark grey apart from one red pixel at (2, 1)
+    fn screen(name: &str) -> (ImageFileScreen, PathBuf) {
+        let mut image = RgbImage::from_pixel(4, 4, Rgb([40, 40, 40]));
+        image.put_pixel(2, 1, Rgb([200, 30, 30]));
+        let path = std::env::temp_dir().join(format!(
+            "simplyscriptor2-wait-{}-{}.png",
+            name,
+            std::process::id()
+        ));
+        image.save(&path).unwrap();
+        (ImageFileScreen::new(&path), path)
+    }
+
+    #[test]
+    fn matches_exact_color() {
+        let (mut screen, path) = screen("match");
+        let run = AtomicBool::new(true);
+        let found = wait_for_pixel(
+            &mut screen,
+            (2, 1),
+            [200, 30, 30],
+            0,
+            Duration::from_secs(1),
+            &run,
+        );
+        assert_eq!(found, Ok(()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn matches_within_tolerance() {
+        let (mut screen, path) = screen("tolerance");
+        let run = AtomicBool::new(true);
+        let near = [205, 25, 35];
+        assert_eq!(
+            wait_for_pixel(&mut screen, (2, 1), near, 5, Duration::ZERO, &run),
+            Ok(())
+        );
+        assert!(matches!(
+            wait_for_pixel(&mut screen, (2, 1), near, 4, Duration::ZERO, &run),
+            Err(WaitError::TimedOut(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn times_out_with_the_last_color_seen() {
+        let (mut screen, path) = screen("timeout");
+        let run = AtomicBool::new(true);
+        let start = Instant::now();
+        let result = wait_for_pixel(
+            &mut screen,
+            (0, 0),
+            [255, 255, 255],
+            10,
+            Duration::from_millis(120),
+            &run,
+        );
+        assert!(start.elapsed() >= Duration::from_millis(120));
+        match result {
+            Err(WaitError::TimedOut(message)) => assert!(message.contains("#282828")),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stops_when_run_is_cleared() {
+        let (mut screen, path) = screen("stop");
+        let run = AtomicBool::new(false);
+        let result = wait_for_pixel(
+            &mut screen,
+            (2, 1),
+            [200, 30, 30],
+            0,
+            Duration::from_secs(1),
+            &run,
+        );
+        assert_eq!(result, Err(WaitError::Stopped));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fails_outside_the_screen() {
+        let (mut screen, path) = screen("outside");
+        let run = AtomicBool::new(true);
+        assert!(matches!(
+            wait_for_pixel(&mut screen, (10, 10), [0, 0, 0], 0, Duration::ZERO, &run),
+            Err(WaitError::Failed(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,635 @@
+use crate::capture::Region;
+use crate::wait::{poll, WaitError};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::atomic::AtomicBool,
+    time::{Duration, Instant},
+};
+
+// How often the focused window is looked up while the mouse is moving
+const TRACK_INTERVAL: Duration = Duration::from_millis(100);
+
+// A top level window as reported by the window manager
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub id: u32,
+    pub title: String,
+    // Both halves of WM_CLASS, instance name first
+    pub class: Vec<String>,
+    // Position of the window contents on the desktop, without decorations
+    pub geometry: Region,
+}
+
+// Which windows a step is looking for. Every field that is set has to match, as a
+// case insensitive substring of the title or of either WM_CLASS name.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct WindowMatcher {
+    pub title: Option<String>,
+    pub class: Option<String>,
+}
+
+impl WindowMatcher {
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        let title_matches = self
+            .title
+            .as_ref()
+            .is_none_or(|title| contains_ignore_case(&window.title, title));
+        let class_matches = self.class.as_ref().is_none_or(|class| {
+            window
+                .class
+                .iter()
+                .any(|name| contains_ignore_case(name, class))
+        });
+        title_matches && class_matches
+    }
+}
+
+impl std::fmt::Display for WindowMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.title, &self.class) {
+            (Some(title), Some(class)) => write!(f, "title \"{}\" and class \"{}\"", title, class),
+            (Some(title), None) => write!(f, "title \"{}\"", title),
+            (None, Some(class)) => write!(f, "class \"{}\"", class),
+            (None, None) => write!(f, "any window"),
+        }
+    }
+}
+
+// The other way round from Display, like `title "notes" and class "gedit"`.
+// Either half can be left out, and nothing at all matches any window.
+impl std::str::FromStr for WindowMatcher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut matcher = WindowMatcher::default();
+        let mut rest = s.trim();
+        if rest.eq_ignore_ascii_case("any window") {
+            return Ok(matcher);
+        }
+        while !rest.is_empty() {
+            let (field, value, after) = rest
+                .split_once('"')
+                .and_then(|(field, quoted)| {
+                    let (value, after) = quoted.split_once('"')?;
+                    Some((field.trim(), value, after.trim_start()))
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "\"{}\" should be like title \"notes\" and class \"gedit\"",
+                        s.trim()
+                    )
+                })?;
+            let value = Some(value.to_string()).filter(|value| !value.is_empty());
+            match field.to_lowercase().as_str() {
+                "title" => matcher.title = value,
+                "class" => matcher.class = value,
+                _ => {
+                    return Err(format!(
+                        "Windows are matched by title or class, not \"{}\"",
+                        field
+                    ))
+                }
+            }
+            rest = after.strip_prefix("and ").unwrap_or(after).trim_start();
+        }
+        Ok(matcher)
+    }
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowCondition {
+    // A matching window is open anywhere
+    Exists,
+    // A matching window has the input focus
+    Focused,
+}
+
+// What playback does when a wait for a window times out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeoutPolicy {
+    // Stop running the script
+    Stop,
+    // Log it and carry on with the next event
+    Continue,
+}
+
+// Source of window information, so the checks can run against anything
+pub trait WindowSystem {
+    fn windows(&mut self) -> Result<Vec<WindowInfo>, String>;
+
+    fn active_window(&mut self) -> Result<Option<WindowInfo>, String>;
+
+    // Ask the window manager to raise and focus a window
+    fn activate(&mut self, id: u32) -> Result<(), String>;
+}
+
+// Block until a window matching `matcher` satisfies `condition`
+pub fn wait_for_window(
+    windows: &mut dyn WindowSystem,
+    matcher: &WindowMatcher,
+    condition: WindowCondition,
+    timeout: Duration,
+    run: &AtomicBool,
+) -> Result<WindowInfo, WaitError> {
+    poll(
+        timeout,
+        run,
+        || {
+            Ok(match condition {
+                WindowCondition::Exists => {
+                    windows.windows()?.into_iter().find(|w| matcher.matches(w))
+                }
+                WindowCondition::Focused => windows.active_window()?.filter(|w| matcher.matches(w)),
+            })
+        },
+        || {
+            let state = match condition {
+                WindowCondition::Exists => "open",
+                WindowCondition::Focused => "focused",
+            };
+            format!(
+                "no window with {} was {} within {:.1}s",
+                matcher,
+                state,
+                timeout.as_secs_f64()
+            )
+        },
+    )
+}
+
+// Find the window a recorded ActiveWindow step refers to. Titles change a lot
+// (documents, tabs) so the class decides, and the title only picks between
+// windows of the same class.
+pub fn find_recorded_window(
+    windows: &mut dyn WindowSystem,
+    title: &str,
+    class: &[String],
+) -> Result<Option<WindowInfo>, String> {
+    let candidates: Vec<WindowInfo> = windows
+        .windows()?
+        .into_iter()
+        .filter(|w| {
+            if class.is_empty() {
+                w.title == title
+            } else {
+                w.class == class
+            }
+        })
+        .collect();
+
+    let exact = candidates.iter().position(|w| w.title == title);
+    Ok(match exact {
+        Some(index) => candidates.into_iter().nth(index),
+        None => candidates.into_iter().next(),
+    })
+}
+
+// Follows the focused window while recording, reporting whenever a different
+// window gets focus or the focused one moves
+pub struct WindowTracker {
+    windows: SystemWindows,
+    last: Option<WindowInfo>,
+    last_check: Option<Instant>,
+}
+
+impl WindowTracker {
+    pub fn new() -> Self {
+        WindowTracker {
+            windows: SystemWindows::new(),
+            last: None,
+            last_check: None,
+        }
+    }
+
+    // Forget the last window so the next check reports it again
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.last_check = None;
+    }
+
+    // The focused window if it changed since the last call. `throttle` skips the
+    // lookup when the previous one was very recent, for high rate events.
+    pub fn changed(&mut self, throttle: bool) -> Option<WindowInfo> {
+        if throttle
+            && self
+                .last_check
+                .is_some_and(|last| last.elapsed() < TRACK_INTERVAL)
+        {
+            return None;
+        }
+        self.last_check = Some(Instant::now());
+
+        let active = self.windows.active_window().ok().flatten()?;
+        let same = self
+            .last
+            .as_ref()
+            .is_some_and(|last| last.id == active.id && last.geometry == active.geometry);
+        if same {
+            return None;
+        }
+
+        self.last = Some(active.clone());
+        Some(active)
+    }
+}
+
+impl Default for WindowTracker {
+    fn default() -> Self {
+        WindowTracker::new()
+    }
+}
+
+// The running X11 session, found through EWMH properties on the root window
+#[derive(Default)]
+pub struct SystemWindows {
+    #[cfg(target_os = "linux")]
+    connection: Option<x11::X11Windows>,
+}
+
+impl SystemWindows {
+    pub fn new() -> Self {
+        SystemWindows::default()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl WindowSystem for SystemWindows {
+    fn windows(&mut self) -> Result<Vec<WindowInfo>, String> {
+        self.connection()?.windows()
+    }
+
+    fn active_window(&mut self) -> Result<Option<WindowInfo>, String> {
+        self.connection()?.active_window()
+    }
+
+    fn activate(&mut self, id: u32) -> Result<(), String> {
+        self.connection()?.activate(id)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl SystemWindows {
+    fn connection(&mut self) -> Result<&mut x11::X11Windows, String> {
+        if self.connection.is_none() {
+            self.connection = Some(x11::X11Windows::connect()?);
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl WindowSystem for SystemWindows {
+    fn windows(&mut self) -> Result<Vec<WindowInfo>, String> {
+        Err("Window checks are only supported on X11".to_string())
+    }
+
+    fn active_window(&mut self) -> Result<Option<WindowInfo>, String> {
+        Err("Window checks are only supported on X11".to_string())
+    }
+
+    fn activate(&mut self, _id: u32) -> Result<(), String> {
+        Err("Window checks are only supported on X11".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use super::WindowInfo;
+    use crate::capture::Region;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{
+        Atom, AtomEnum, ClientMessageEvent, ConnectionExt as _, EventMask, Window,
+    };
+    use x11rb::rust_connection::RustConnection;
+
+    pub struct X11Windows {
+        conn: RustConnection,
+        root: Window,
+        net_active_window: Atom,
+        net_client_list: Atom,
+        net_wm_name: Atom,
+        utf8_string: Atom,
+    }
+
+    impl X11Windows {
+        pub fn connect() -> Result<Self, String> {
+            let (conn, screen_num) = RustConnection::connect(None)
+                .map_err(|e| format!("Could not connect to X11: {}", e))?;
+            let root = conn.setup().roots[screen_num].root;
+
+            let intern = |name: &str| -> Result<Atom, String> {
+                Ok(conn
+                    .intern_atom(false, name.as_bytes())
+                    .map_err(error)?
+                    .reply()
+                    .map_err(error)?
+                    .atom)
+            };
+            let net_active_window = intern("_NET_ACTIVE_WINDOW")?;
+            let net_client_list = intern("_NET_CLIENT_LIST")?;
+            let net_wm_name = intern("_NET_WM_NAME")?;
+            let utf8_string = intern("UTF8_STRING")?;
+
+            Ok(X11Windows {
+                conn,
+                root,
+                net_active_window,
+                net_client_list,
+                net_wm_name,
+                utf8_string,
+            })
+        }
+
+        // A window can close between listing it and asking about it, that one
+        // is left out rather than failing the whole lookup
+        pub fn windows(&self) -> Result<Vec<WindowInfo>, String> {
+            Ok(self
+                .window_list(self.net_client_list)?
+                .into_iter()
+                .filter_map(|id| self.info(id).ok())
+                .collect())
+        }
+
+        pub fn active_window(&self) -> Result<Option<WindowInfo>, String> {
+            match self.window_list(self.net_active_window)?.first() {
+                Some(&id) if id != 0 => Ok(self.info(id).ok()),
+                _ => Ok(None),
+            }
+        }
+
+        // EWMH activation request, sent as if from a pager so it isn't ignored by
+        // focus stealing prevention
+        pub fn activate(&self, id: Window) -> Result<(), String> {
+            let message = ClientMessageEvent::new(32, id, self.net_active_window, [2, 0, 0, 0, 0]);
+            self.conn
+                .send_event(
+                    false,
+                    self.root,
+                    EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                    message,
+                )
+                .map_err(error)?;
+            self.conn.flush().map_err(error)
+        }
+
+        fn window_list(&self, property: Atom) -> Result<Vec<Window>, String> {
+            let reply = self
+                .conn
+                .get_property(false, self.root, property, AtomEnum::WINDOW, 0, u32::MAX)
+                .map_err(error)?
+                .reply()
+                .map_err(error)?;
+            Ok(reply.value32().map(|ids| ids.collect()).unwrap_or_default())
+        }
+
+        fn info(&self, id: Window) -> Result<WindowInfo, String> {
+            let mut title = self.text_property(id, self.net_wm_name, self.utf8_string)?;
+            if title.is_empty() {
+                title =
+                    self.text_property(id, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())?;
+            }
+
+            let class = self
+                .text_property(id, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())?
+                .split('\0')
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect();
+
+            Ok(WindowInfo {
+                id,
+                title,
+                class,
+                geometry: self.geometry(id)?,
+            })
+        }
+
+        fn geometry(&self, id: Window) -> Result<Region, String> {
+            let size = self
+                .conn
+                .get_geometry(id)
+                .map_err(error)?
+                .reply()
+                .map_err(error)?;
+            let position = self
+                .conn
+                .translate_coordinates(id, self.root, 0, 0)
+                .map_err(error)?
+                .reply()
+                .map_err(error)?;
+            Ok(Region {
+                x: position.dst_x as i32,
+                y: position.dst_y as i32,
+                width: size.width as u32,
+                height: size.height as u32,
+            })
+        }
+
+        fn text_property(&self, id: Window, property: Atom, kind: Atom) -> Result<String, String> {
+            let reply = self
+                .conn
+                .get_property(false, id, property, kind, 0, u32::MAX)
+                .map_err(error)?
+                .reply()
+                .map_err(error)?;
+            Ok(String::from_utf8_lossy(&reply.value).into_owned())
+        }
+    }
+
+    fn error(e: impl std::fmt::Display) -> String {
+        format!("X11 request failed: {}", e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(id: u32, title: &str, class: &[&str]) -> WindowInfo {
+        WindowInfo {
+            id,
+            title: title.to_string(),
+            class: class.iter().map(|name| name.to_string()).collect(),
+            geometry: Region {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 100,
+            },
+        }
+    }
+
+    // Windows that open one poll at a time, the last list stays open
+    struct FakeWindows {
+        lists: Vec<Vec<WindowInfo>>,
+        polls: usize,
+        active: Option<WindowInfo>,
+        broken: bool,
+    }
+
+    impl FakeWindows {
+        fn new(lists: Vec<Vec<WindowInfo>>) -> Self {
+            FakeWindows {
+                lists,
+                polls: 0,
+                active: None,
+                broken: false,
+            }
+        }
+    }
+
+    impl WindowSystem for FakeWindows {
+        fn windows(&mut self) -> Result<Vec<WindowInfo>, String> {
+            if self.broken {
+                return Err("no display".to_string());
+            }
+            let list = self.lists[self.polls.min(self.lists.len() - 1)].clone();
+            self.polls += 1;
+            Ok(list)
+        }
+
+        fn active_window(&mut self) -> Result<Option<WindowInfo>, String> {
+            Ok(self.active.clone())
+        }
+
+        fn activate(&mut self, _id: u32) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    fn matcher(title: Option<&str>, class: Option<&str>) -> WindowMatcher {
+        WindowMatcher {
+            title: title.map(String::from),
+            class: class.map(String::from),
+        }
+    }
+
+    #[test]
+    fn matches_title_and_class_ignoring_case() {
+        let editor = window(1, "notes.txt - Gedit", &["gedit", "Gedit"]);
+        assert!(matcher(Some("NOTES"), None).matches(&editor));
+        assert!(matcher(None, Some("gedit")).matches(&editor));
+        assert!(matcher(Some("notes"), Some("GEDIT")).matches(&editor));
+        assert!(!matcher(Some("notes"), Some("firefox")).matches(&editor));
+        assert!(!matcher(Some("todo"), None).matches(&editor));
+        assert!(matcher(None, None).matches(&editor));
+        assert_eq!(
+            matcher(Some("notes"), Some("gedit")).to_string(),
+            "title \"notes\" and class \"gedit\""
+        );
+    }
+
+    #[test]
+    fn matchers_parse_as_they_display() {
+        for matcher in [
+            matcher(Some("notes.txt - Gedit"), Some("gedit")),
+            matcher(Some("notes"), None),
+            matcher(None, Some("gedit")),
+            matcher(None, None),
+        ] {
+            assert_eq!(matcher.to_string().parse(), Ok(matcher));
+        }
+        assert_eq!(
+            "Class \"gedit\" Title \"a b\"".parse(),
+            Ok(matcher(Some("a b"), Some("gedit")))
+        );
+        assert_eq!("".parse(), Ok(matcher(None, None)));
+
+        for bad in ["notes", "title \"notes", "name \"notes\""] {
+            assert!(bad.parse::<WindowMatcher>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn finds_recorded_windows_by_class_first() {
+        let mut windows = FakeWindows::new(vec![vec![
+            window(1, "Inbox - Mail", &["mail", "Mail"]),
+            window(2, "notes.txt - Gedit", &["gedit", "Gedit"]),
+            window(3, "todo.txt - Gedit", &["gedit", "Gedit"]),
+        ]]);
+        let gedit = ["gedit".to_string(), "Gedit".to_string()];
+
+        let exact = find_recorded_window(&mut windows, "todo.txt - Gedit", &gedit).unwrap();
+        assert_eq!(exact.map(|w| w.id), Some(3));
+        // The title changed since recording, any window of the class will do
+        let renamed = find_recorded_window(&mut windows, "draft - Gedit", &gedit).unwrap();
+        assert_eq!(renamed.map(|w| w.id), Some(2));
+        // Without a class only the exact title is trusted
+        let classless = find_recorded_window(&mut windows, "Inbox - Mail", &[]).unwrap();
+        assert_eq!(classless.map(|w| w.id), Some(1));
+        assert_eq!(
+            find_recorded_window(&mut windows, "Inbox", &[]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn waits_for_a_window_to_open() {
+        let mut windows = FakeWindows::new(vec![
+            vec![window(1, "Terminal", &["xterm"])],
+            vec![window(1, "Terminal", &["xterm"])],
+            vec![
+                window(1, "Terminal", &["xterm"]),
+                window(2, "Calculator", &["gnome-calculator"]),
+            ],
+        ]);
+        let run = AtomicBool::new(true);
+        let found = wait_for_window(
+            &mut windows,
+            &matcher(Some("calc"), None),
+            WindowCondition::Exists,
+            Duration::from_secs(5),
+            &run,
+        );
+        assert_eq!(found.map(|w| w.id), Ok(2));
+        assert_eq!(windows.polls, 3);
+    }
+
+    #[test]
+    fn waits_for_focus() {
+        let mut windows = FakeWindows::new(vec![vec![window(1, "Calculator", &[])]]);
+        let run = AtomicBool::new(true);
+        let focused = |windows: &mut FakeWindows| {
+            wait_for_window(
+                windows,
+                &matcher(Some("calc"), None),
+                WindowCondition::Focused,
+                Duration::from_millis(50),
+                &run,
+            )
+        };
+        assert!(matches!(focused(&mut windows), Err(WaitError::TimedOut(_))));
+        windows.active = Some(window(1, "Calculator", &[]));
+        assert_eq!(focused(&mut windows).map(|w| w.id), Ok(1));
+    }
+
+    #[test]
+    fn stops_times_out_and_fails() {
+        let mut windows = FakeWindows::new(vec![Vec::new()]);
+        let exists = |windows: &mut FakeWindows, run: &AtomicBool| {
+            wait_for_window(
+                windows,
+                &matcher(None, Some("xterm")),
+                WindowCondition::Exists,
+                Duration::from_millis(50),
+                run,
+            )
+        };
+        let running = AtomicBool::new(true);
+        let stopped = AtomicBool::new(false);
+        assert_eq!(exists(&mut windows, &stopped), Err(WaitError::Stopped));
+        match exists(&mut windows, &running) {
+            Err(WaitError::TimedOut(message)) => assert!(message.contains("class \"xterm\"")),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        windows.broken = true;
+        assert!(matches!(
+            exists(&mut windows, &running),
+            Err(WaitError::Failed(_))
+        ));
+    }
+}